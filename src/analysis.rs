@@ -0,0 +1,249 @@
+//! # Modal analysis of the optical sensitivities
+//!
+//! Singular value decomposition of the optical sensitivity matrices, individually or stacked with [from_opticals]
+
+use std::fmt::Display;
+
+use nalgebra as na;
+
+use crate::{
+    from_opticals, optical_sensitivities::into_pupil, Formatting, LinearOpticalModelError,
+    OpticalSensitivities, OpticalSensitivity, Result,
+};
+
+/// Full singular value decomposition of the `[m,n]` matrix `d`
+///
+/// The matrix is padded with zeros if `m<n` in order to get the `[n,n]` right singular vectors;
+/// returns the `[max(m,n),n]` left singular vectors, the singular values in descending order and the right singular vectors
+pub(crate) fn full_svd(d: na::DMatrix<f64>) -> (na::DMatrix<f64>, Vec<f64>, na::DMatrix<f64>) {
+    let (m, n) = d.shape();
+    let svd = d.resize_vertically(m.max(n), 0f64).svd(true, true);
+    (
+        svd.u.unwrap(),
+        svd.singular_values.iter().cloned().collect(),
+        svd.v_t.unwrap().transpose(),
+    )
+}
+
+/// Singular value decomposition of optical sensitivities
+#[derive(Debug, Clone)]
+pub struct ModalAnalysis {
+    // sensitivities names and number of rows
+    blocks: Vec<(String, usize)>,
+    // singular values in descending order
+    singular_values: Vec<f64>,
+    // `[m,N]` left singular vectors
+    u: na::DMatrix<f64>,
+    // `[N,N]` right singular vectors
+    v: na::DMatrix<f64>,
+    // singular values threshold for the rank
+    tolerance: f64,
+    pupil_mask: Option<Vec<bool>>,
+    pub format: Formatting,
+}
+impl ModalAnalysis {
+    /// Returns the names of the sensitivities
+    pub fn name(&self) -> String {
+        self.blocks
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+    /// Sets the singular values threshold for the rank relative to the largest singular value
+    pub fn relative_tolerance(mut self, rtol: f64) -> Self {
+        self.tolerance = rtol * self.singular_values[0];
+        self
+    }
+    /// Returns the singular values in descending order
+    pub fn singular_values(&self) -> &[f64] {
+        &self.singular_values
+    }
+    /// Returns the number of singular values above the tolerance
+    pub fn rank(&self) -> usize {
+        self.singular_values
+            .iter()
+            .filter(|&&s| s > self.tolerance)
+            .count()
+    }
+    /// Returns the ratio of the largest to the smallest singular value
+    pub fn condition_number(&self) -> f64 {
+        self.singular_values[0] / self.singular_values.last().unwrap()
+    }
+    /// Returns the ratio of the largest to the smallest singular value above the tolerance
+    pub fn effective_condition_number(&self) -> f64 {
+        self.singular_values[0] / self.singular_values[self.rank().max(1) - 1]
+    }
+    /// Returns the `[N,N]` rigid body motion modes
+    ///
+    /// The modes are ordered by descending singular values and given in the same order than [RigidBodyMotions](crate::RigidBodyMotions) rows
+    pub fn rbm_modes(&self) -> &na::DMatrix<f64> {
+        &self.v
+    }
+    /// Returns the `[N,N-r]` rigid body motion modes of the null space where `r` is the [rank](ModalAnalysis::rank)
+    pub fn null_space(&self) -> na::DMatrix<f64> {
+        let r = self.rank();
+        self.v.columns(r, self.v.ncols() - r).into_owned()
+    }
+    /// Returns the optical response to the `i`th rigid body motion mode
+    ///
+    /// The response is the `i`th left singular vector scaled by the `i`th singular value
+    pub fn response(&self, i: usize) -> Vec<f64> {
+        (self.u.column(i) * self.singular_values[i])
+            .as_slice()
+            .to_vec()
+    }
+    /// Returns the wavefront in the exit pupil in `[m]` of the `i`th rigid body motion mode
+    ///
+    /// Returns `None` if the analysis does not include the wavefront sensitivity
+    pub fn mode_shape(&self, i: usize) -> Option<Vec<f64>> {
+        let mask = self.pupil_mask.as_ref()?;
+        let mut offset = 0;
+        for (name, n) in &self.blocks {
+            if name == "Wavefront" {
+                let response = self.response(i);
+                return Some(into_pupil(mask, &response[offset..offset + n]));
+            }
+            offset += n;
+        }
+        None
+    }
+}
+impl Display for ModalAnalysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.singular_values.len();
+        match self.format {
+            Formatting::AdHoc => {
+                writeln!(f, "{} SVD:", self.name())?;
+                writeln!(
+                    f,
+                    " - rank: {}/{}, condition number: {:.3e} (effective: {:.3e})",
+                    self.rank(),
+                    n,
+                    self.condition_number(),
+                    self.effective_condition_number()
+                )?;
+                writeln!(f, " - singular values:")?;
+                for (i, s) in self.singular_values.chunks(6).enumerate() {
+                    let s: Vec<_> = s.iter().map(|s| format!("{:9.3e}", s)).collect();
+                    writeln!(f, "   #{:2}: {}", i * 6 + 1, s.join(" "))?;
+                }
+            }
+            Formatting::Latex => {
+                writeln!(
+                    f,
+                    "\\begin{{tabular}}{{cc}}
+\\multicolumn{{2}}{{c}}{{{}}} \\\\
+Mode & Singular value \\\\",
+                    self.name()
+                )?;
+                for (i, s) in self.singular_values.iter().enumerate() {
+                    writeln!(f, r" {:} & {:9.3e} \\", i + 1, s)?;
+                }
+                writeln!(
+                    f,
+                    r"\multicolumn{{2}}{{c}}{{rank: {}/{}, condition number: {:.3e}}} \\",
+                    self.rank(),
+                    n,
+                    self.effective_condition_number()
+                )?;
+                writeln!(f, "\\end{{tabular}}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Returns the singular value decomposition of the optical sensitivity of the same variant than `sens`
+    pub fn modal_analysis(&self, sens: OpticalSensitivity<N>) -> Result<ModalAnalysis> {
+        self.stacked_modal_analysis(&[sens])
+    }
+    /// Returns the singular value decomposition of the optical sensitivities of the same variants than `senses`
+    /// stacked on top of each other
+    pub fn stacked_modal_analysis(
+        &self,
+        senses: &[OpticalSensitivity<N>],
+    ) -> Result<ModalAnalysis> {
+        let senses = senses
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::SegmentMask(_) | OpticalSensitivity::PupilMask(_) => {
+                    Err(LinearOpticalModelError::NotAMeasurement(s.to_string()))
+                }
+                _ => self.try_get(s.clone()).cloned(),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if senses.contains(&OpticalSensitivity::Wavefront(vec![])) {
+            self.check_pupil()?;
+        }
+        let blocks = senses
+            .iter()
+            .map(|s| (s.to_string(), <&[f64]>::from(s).len() / N))
+            .collect();
+        let d = from_opticals(&senses);
+        let m = d.nrows();
+        let (u, singular_values, v) = full_svd(d);
+        let tolerance = f64::EPSILON * m.max(N) as f64 * singular_values[0];
+        Ok(ModalAnalysis {
+            blocks,
+            singular_values,
+            u: u.rows(0, m).into_owned(),
+            v,
+            tolerance,
+            pupil_mask: self.pupil_mask().ok().map(|mask| mask.to_vec()),
+            format: Formatting::AdHoc,
+        })
+    }
+    /// Returns the singular value decomposition of each optical sensitivity
+    pub fn modal_analyses(&self) -> Vec<ModalAnalysis> {
+        self.iter()
+            .filter(|s| {
+                !matches!(
+                    s,
+                    OpticalSensitivity::SegmentMask(_) | OpticalSensitivity::PupilMask(_)
+                )
+            })
+            .filter_map(|s| self.modal_analysis(s.clone()).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modal_analysis() {
+        let sens = OpticalSensitivities::synthetic();
+        let tt = sens
+            .modal_analysis(OpticalSensitivity::TipTilt(vec![]))
+            .unwrap();
+        assert_eq!(tt.singular_values().len(), 84);
+        assert_eq!(tt.rank(), 2);
+        assert_eq!(tt.null_space().ncols(), 82);
+        assert!(tt.condition_number().is_infinite() || tt.condition_number() > 1e12);
+        assert!((tt.effective_condition_number() - 1f64).abs() < 1e-9);
+        assert!(tt.mode_shape(0).is_none());
+
+        let stacked = sens
+            .stacked_modal_analysis(&[
+                OpticalSensitivity::TipTilt(vec![]),
+                OpticalSensitivity::Wavefront(vec![]),
+            ])
+            .unwrap();
+        assert_eq!(stacked.name(), "TipTilt+Wavefront");
+        let shape = stacked.mode_shape(0).unwrap();
+        assert_eq!(shape.len(), 64);
+        assert_eq!(
+            stacked.null_space().ncols() + stacked.rank(),
+            stacked.singular_values().len()
+        );
+        assert!(stacked.to_string().contains("rank"));
+
+        assert_eq!(sens.modal_analyses().len(), 4);
+        assert!(sens
+            .modal_analysis(OpticalSensitivity::PupilMask(vec![]))
+            .is_err());
+    }
+}
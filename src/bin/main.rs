@@ -5,7 +5,7 @@
 //!  2. the parquet file name without the ".parquet" extension <"data">
 
 use clap::Parser;
-use gmt_lom::{OpticalMetrics, Stats, Table, ToPkl, LOM};
+use gmt_lom::{OpticalMetrics, Spectral, Stats, Table, ToPkl, LOM};
 use skyangle::Conversion;
 use std::path::Path;
 
@@ -27,10 +27,10 @@ struct Cli {
     /// Compute statistics on the last n seconds
     #[arg(short, long)]
     last: Option<f64>,
-    /// Compute the tip and tilt PSDs (requires the "welch-sde" feature)
+    /// Compute the tip and tilt PSDs
     #[arg(long)]
     tip_tilt_psds: bool,
-    /// Compute the segment piston PSDs (requires the "welch-sde" feature)
+    /// Compute the segment piston PSDs
     #[arg(long)]
     segment_piston_psds: bool,
     /// Save the tip and tilte to a pickle file
@@ -58,11 +58,7 @@ fn main() -> anyhow::Result<()> {
     let table = Table::from_parquet(path.join(cli.file))?;
 
     let mut lom = LOM::builder()
-        .table_rigid_body_motions(
-            &table,
-            Some("M1RigidBodyMotions"),
-            Some("M2RigidBodyMotions"),
-        )?
+        .table_rigid_body_motions(&table, None, None)?
         .build()?;
     if cli.zm1 {
         lom.rbm.zeroed_m1()
@@ -110,7 +106,7 @@ fn main() -> anyhow::Result<()> {
         lom.time()
             .iter()
             .zip(tiptilt.items())
-            .map(|(&t, xy)| (t * 1e-3, xy.to_vec())),
+            .map(|(&t, xy)| (t, xy.to_vec())),
         complot::complot!(
             "lom_tiptilt.png",
             xlabel = "Time [s]",
@@ -124,7 +120,7 @@ fn main() -> anyhow::Result<()> {
             .iter()
             .zip(segment_piston.items())
             .skip(n)
-            .map(|(&t, xy)| (t * 1e-3, xy.to_vec())),
+            .map(|(&t, xy)| (t, xy.to_vec())),
         complot::complot!(
             "lom_segment-piston.png",
             xlabel = "Time [s]",
@@ -133,53 +129,25 @@ fn main() -> anyhow::Result<()> {
     )
         .into();
 
+    let sampling_frequency = cli
+        .sampling_frequency
+        .or(lom.sampling_frequency())
+        .unwrap_or(1f64);
     if cli.tip_tilt_psds {
-        let n = lom.len() - n_sample;
-        let (tip, tilt): (Vec<f64>, Vec<f64>) = {
-            let (tip, tilt): (Vec<_>, Vec<_>) =
-                tiptilt.items().skip(n).map(|xy| (xy[0], xy[1])).unzip();
-            let mean_tip = tip.iter().cloned().sum::<f64>() / tip.len() as f64;
-            let mean_tilt = tilt.iter().cloned().sum::<f64>() / tilt.len() as f64;
-            (
-                tip.into_iter().map(|x| 1e-3 * (x - mean_tip)).collect(),
-                tilt.into_iter().map(|x| 1e-3 * (x - mean_tilt)).collect(),
-            )
-        };
-        use welch_sde::{Build, PowerSpectrum};
-        let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&tip)
-            .sampling_frequency(cli.sampling_frequency.unwrap_or(1f64))
-            .dft_log2_max_size(10)
-            .build();
-        println!("{welch}");
-        let tip_psd = welch.periodogram();
-        let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&tilt)
-            .sampling_frequency(cli.sampling_frequency.unwrap_or(1f64))
-            .dft_log2_max_size(10)
-            .build();
-        let tilt_psd = welch.periodogram();
-        /*
-                let n = lom.len() - n_sample;
-                let (mut tiptilt, mut tilt): (Vec<_>, Vec<_>) =
-                    tiptilt.items().skip(n).map(|xy| (xy[0], xy[1])).unzip();
-                tiptilt.append(&mut tilt);
-                use welch_sde::{Build, PowerSpectrum};
-                let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&tiptilt)
-                    .n_signal(2)
-                    .sampling_frequency(opt.sampling_frequency.unwrap_or(1f64))
-                    .build();
-                println!("{welch}");
-                let tiptilt_psd = welch.periodogram();
-                let h = tiptilt_psd.len() / 2;
-                let (tip_psd, tilt_psd) = tiptilt_psd.split_at(h);
-        */
-
-        let _: complot::LogLog = (
-            tip_psd
-                .frequency()
+        let psd = tiptilt.psd(sampling_frequency, Some(n_sample));
+        println!(
+            "TT RMS (from PSD): {:.0?}mas",
+            psd.rms()
                 .into_iter()
-                .zip(tip_psd.iter().zip(tilt_psd.iter()))
+                .map(|x| x.to_mas())
+                .collect::<Vec<f64>>()
+        );
+        let _: complot::LogLog = (
+            psd.frequencies()
+                .iter()
+                .zip(psd.matrix().column_iter())
                 .skip(1)
-                .map(|(f, (x, y))| (f, vec![*x, *y])),
+                .map(|(&f, p)| (f, p.iter().map(|p| p * 1f64.to_mas().powi(2)).collect())),
             complot::complot!(
                 "lom_tiptilt-psds.png",
                 xlabel = "Frequency [Hz]",
@@ -189,56 +157,13 @@ fn main() -> anyhow::Result<()> {
             .into();
     }
     if cli.segment_piston_psds {
-        /*
-               let n = lom.len() - n_sample;
-               let (tip, tilt): (Vec<f64>, Vec<f64>) = {
-                   let (tip, tilt): (Vec<_>, Vec<_>) =
-                       tiptilt.items().skip(n).map(|xy| (xy[0], xy[1])).unzip();
-                   let mean_tip = tip.iter().cloned().sum::<f64>() / tip.len() as f64;
-                   let mean_tilt = tilt.iter().cloned().sum::<f64>() / tilt.len() as f64;
-                   (
-                       tip.into_iter().map(|x| 1e-3 * (x - mean_tip)).collect(),
-                       tilt.into_iter().map(|x| 1e-3 * (x - mean_tilt)).collect(),
-                   )
-               };
-               use welch_sde::{Build, PowerSpectrum};
-               let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&tip)
-                   .sampling_frequency(opt.sampling_frequency.unwrap_or(1f64))
-                   .build();
-               println!("{welch}");
-               let tip_psd = welch.periodogram();
-               let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&tilt)
-                   .sampling_frequency(opt.sampling_frequency.unwrap_or(1f64))
-                   .build();
-               let tilt_psd = welch.periodogram();
-        let n = lom.len() - n_sample;
-        let segments_piston = segment_piston.time_wise(Some(n_sample));
-        use welch_sde::{Build, PowerSpectrum};
-        let welch: PowerSpectrum<f64> = PowerSpectrum::builder(&segments_piston)
-            .n_signal(7)
-            .sampling_frequency(opt.sampling_frequency.unwrap_or(1f64))
-            .build();
-        println!("{welch}");
-        let segments_piston_psd = welch.periodogram();
-        let h = segments_piston_psd.len() / 7;
-
+        let psd = segment_piston.psd(sampling_frequency, Some(n_sample));
         let _: complot::LogLog = (
-            segments_piston_psd
-                .frequency()
-                .into_iter()
-                .enumerate()
+            psd.frequencies()
+                .iter()
+                .zip(psd.matrix().column_iter())
                 .skip(1)
-                .map(|(i, f)| {
-                    (
-                        f,
-                        segments_piston_psd
-                            .iter()
-                            .skip(i)
-                            .step_by(h)
-                            .cloned()
-                            .collect::<Vec<f64>>(),
-                    )
-                }),
+                .map(|(&f, p)| (f, p.iter().map(|p| p * 1e18).collect())),
             complot::complot!(
                 "lom_segments-piston-psds.png",
                 xlabel = "Frequency [Hz]",
@@ -246,7 +171,6 @@ fn main() -> anyhow::Result<()> {
             ),
         )
             .into();
-        */
     }
 
     Ok(())
@@ -0,0 +1,326 @@
+//! # Contributions of the rigid body motions to the optical metrics variance
+//!
+//! The variance of an optical metric `y=Sx` is split among the rigid body motions `x`
+//! grouped by mirror, by segment or by degree of freedom
+
+use std::fmt::Display;
+
+use nalgebra as na;
+
+use crate::{
+    Formatting, OpticalSensitivities, OpticalSensitivity, RbmCovariance, Result, RigidBodyMotions,
+    LOM,
+};
+
+/// Grouping of the rigid body motions
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Grouping {
+    /// M1 and M2
+    Mirror,
+    /// The 7 segments of M1 and M2
+    Segment,
+    /// The 84 degrees of freedom
+    #[default]
+    Dof,
+}
+impl Grouping {
+    /// Returns the label and the rigid body motions indices of each group
+    fn groups(&self) -> Vec<(String, Vec<usize>)> {
+        let labels = RigidBodyMotions::labels();
+        match self {
+            Grouping::Mirror => (0..2)
+                .map(|i| (format!("M{}", i + 1), (i * 42..(i + 1) * 42).collect()))
+                .collect(),
+            Grouping::Segment => (0..14)
+                .map(|i| {
+                    (
+                        labels[i * 6][..5].to_string(),
+                        (i * 6..(i + 1) * 6).collect(),
+                    )
+                })
+                .collect(),
+            Grouping::Dof => labels
+                .into_iter()
+                .enumerate()
+                .map(|(i, l)| (l, vec![i]))
+                .collect(),
+        }
+    }
+}
+
+/// Handling of the covariance between rigid body motions of different groups
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CrossTerms {
+    /// The cross-terms are shared equally between the groups, the contributions sum to the total variance but may be negative
+    #[default]
+    Shared,
+    /// Each group contribution is the variance of the metric due to this group alone,
+    /// the remainder is reported as a separate `cross-terms` contribution
+    Separate,
+}
+
+/// Contributions of the rigid body motions to the variance of an optical metric
+#[derive(Debug, Clone)]
+pub struct Contributions {
+    metric: String,
+    labels: Vec<String>,
+    // `[n_item,n_group]` variance contributions
+    variance: na::DMatrix<f64>,
+    // weight of the items in the total variance
+    weight: f64,
+    pub format: Formatting,
+}
+impl Contributions {
+    fn new(
+        metric: String,
+        s: &na::DMatrix<f64>,
+        covariance: &na::DMatrix<f64>,
+        grouping: Grouping,
+        cross_terms: CrossTerms,
+        weight: f64,
+    ) -> Self {
+        let groups = grouping.groups();
+        let total = (s * covariance).component_mul(s).column_sum();
+        let mut variance = na::DMatrix::<f64>::zeros(s.nrows(), groups.len());
+        for (mut v, (_, idx)) in variance.column_iter_mut().zip(&groups) {
+            let s_g = s.select_columns(idx);
+            let var = match cross_terms {
+                CrossTerms::Shared => (s * covariance.select_columns(idx)).component_mul(&s_g),
+                CrossTerms::Separate => {
+                    let c_gg = covariance.select_rows(idx).select_columns(idx);
+                    (&s_g * c_gg).component_mul(&s_g)
+                }
+            };
+            v.copy_from(&var.column_sum());
+        }
+        let mut labels: Vec<String> = groups.into_iter().map(|(l, _)| l).collect();
+        if cross_terms == CrossTerms::Separate {
+            let cross = &total - variance.column_sum();
+            variance = variance.insert_column(labels.len(), 0f64);
+            variance.column_mut(labels.len()).copy_from(&cross);
+            labels.push("cross-terms".to_string());
+        }
+        Self {
+            metric,
+            labels,
+            variance,
+            weight,
+            format: Formatting::AdHoc,
+        }
+    }
+    /// Returns the group labels
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+    /// Returns the `[n_item,n_group]` matrix of variance contributions
+    pub fn variance(&self) -> &na::DMatrix<f64> {
+        &self.variance
+    }
+    /// Returns the total variance summed over all the metric items
+    ///
+    /// For the wavefront, the total variance is averaged over the pupil
+    pub fn total(&self) -> f64 {
+        self.variance.sum() * self.weight
+    }
+    /// Returns the contributions summed over all the metric items, sorted by decreasing magnitude
+    pub fn table(&self) -> Vec<(String, f64)> {
+        let row_sum = self.variance.row_sum();
+        self.sorted(row_sum.iter().map(|v| v * self.weight))
+    }
+    /// Returns the contributions to the `i`th item of the metric, sorted by decreasing magnitude
+    pub fn item_table(&self, i: usize) -> Vec<(String, f64)> {
+        self.sorted(self.variance.row(i).iter().cloned())
+    }
+    fn sorted(&self, variance: impl Iterator<Item = f64>) -> Vec<(String, f64)> {
+        let mut table: Vec<_> = self.labels.iter().cloned().zip(variance).collect();
+        table.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+        table
+    }
+}
+impl Display for Contributions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        match self.format {
+            Formatting::AdHoc => {
+                writeln!(f, "{} variance: {:.3e}", self.metric, total)?;
+                for (label, var) in self.table() {
+                    writeln!(
+                        f,
+                        " - {:12}: {:10.3e} ({:5.1}%)",
+                        label,
+                        var,
+                        100. * var / total
+                    )?;
+                }
+            }
+            Formatting::Latex => {
+                writeln!(
+                    f,
+                    "\\begin{{tabular}}{{lcc}}
+\\multicolumn{{3}}{{c}}{{{} variance: {:.3e}}} \\\\",
+                    self.metric, total
+                )?;
+                for (label, var) in self.table() {
+                    writeln!(
+                        f,
+                        r" {} & {:.3e} & {:.1}\% \\",
+                        label.replace('_', r"\_"),
+                        var,
+                        100. * var / total
+                    )?;
+                }
+                writeln!(f, "\\end{{tabular}}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OpticalSensitivities {
+    /// Returns the contributions of the rigid body motions to the variance of the optical metric of the same variant than `index`
+    ///
+    /// For the [Wavefront](OpticalSensitivity::Wavefront), the contributions are given for the WFE variance averaged over the pupil
+    pub fn contributions(
+        &self,
+        index: OpticalSensitivity,
+        covariance: &RbmCovariance,
+        grouping: Grouping,
+        cross_terms: CrossTerms,
+    ) -> Result<Contributions> {
+        let sens = self.try_get(index)?;
+        let s = match sens {
+            OpticalSensitivity::SegmentMask(_) | OpticalSensitivity::PupilMask(_) => {
+                return Err(crate::LinearOpticalModelError::NotAMeasurement(
+                    sens.to_string(),
+                ))
+            }
+            _ => na::DMatrix::from(sens),
+        };
+        let weight = match sens {
+            OpticalSensitivity::Wavefront(_) => (s.nrows() as f64).recip(),
+            _ => 1f64,
+        };
+        Ok(Contributions::new(
+            sens.to_string(),
+            &s,
+            covariance.matrix(),
+            grouping,
+            cross_terms,
+            weight,
+        ))
+    }
+}
+
+impl LOM {
+    /// Returns the covariance of the rigid body motions
+    ///
+    /// The covariance is normalized by the number of samples like [Stats::var](crate::Stats::var)
+    ///
+    /// Fails if the rigid body motions do not have 84 rows
+    pub fn rbm_covariance(&self) -> Result<RbmCovariance> {
+        let data = self.rbm.data();
+        let n = data.ncols();
+        let centered = data - data.column_mean() * na::RowDVector::from_element(n, 1f64);
+        RbmCovariance::new(&centered * centered.transpose() / n as f64)
+    }
+    /// Returns the contributions of the rigid body motions to the variance of the optical metric of the same variant than `index`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use gmt_lom::{contributions::{CrossTerms, Grouping}, OpticalSensitivity, LOM};
+    ///
+    /// let lom = LOM::builder().build()?;
+    /// let contributions = lom.contributions(
+    ///     OpticalSensitivity::TipTilt(vec![]),
+    ///     Grouping::Segment,
+    ///     CrossTerms::Shared,
+    /// )?;
+    /// println!("{contributions}");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn contributions(
+        &self,
+        index: OpticalSensitivity,
+        grouping: Grouping,
+        cross_terms: CrossTerms,
+    ) -> Result<Contributions> {
+        self.sensitivities()
+            .contributions(index, &self.rbm_covariance()?, grouping, cross_terms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stats;
+
+    #[test]
+    fn contributions() {
+        let n = 300;
+        let rbm = na::DMatrix::<f64>::from_fn(84, n, |i, j| {
+            1e-6 * ((i as f64 + 1.1) * (j as f64 + 0.3) * 0.731).sin()
+        });
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = rbm.into();
+        let var = lom.segment_piston().var(None);
+        for grouping in [Grouping::Mirror, Grouping::Segment, Grouping::Dof] {
+            for cross_terms in [CrossTerms::Shared, CrossTerms::Separate] {
+                let c = lom
+                    .contributions(
+                        OpticalSensitivity::SegmentPiston(vec![]),
+                        grouping,
+                        cross_terms,
+                    )
+                    .unwrap();
+                let total = var.iter().sum::<f64>();
+                assert!((c.total() - total).abs() < 1e-9 * total);
+                for (i, var) in var.iter().enumerate() {
+                    let item = c.item_table(i).iter().map(|(_, v)| v).sum::<f64>();
+                    assert!((item - var).abs() < 1e-9 * total);
+                }
+            }
+        }
+        let c = lom
+            .contributions(
+                OpticalSensitivity::SegmentPiston(vec![]),
+                Grouping::Segment,
+                CrossTerms::Separate,
+            )
+            .unwrap();
+        assert_eq!(c.labels().len(), 15);
+        let table = c.table();
+        assert!(table.windows(2).all(|x| x[0].1.abs() >= x[1].1.abs()));
+        // only the Tz of each segment drives the synthetic segment piston
+        let c = lom
+            .contributions(
+                OpticalSensitivity::SegmentPiston(vec![]),
+                Grouping::Dof,
+                CrossTerms::Shared,
+            )
+            .unwrap();
+        assert!(c
+            .table()
+            .iter()
+            .filter(|(_, v)| *v != 0f64)
+            .all(|(l, _)| l.ends_with("Tz")));
+        assert!(
+            lom.contributions(
+                OpticalSensitivity::Wavefront(vec![]),
+                Grouping::Mirror,
+                CrossTerms::Shared
+            )
+            .unwrap()
+            .total()
+                > 0f64
+        );
+
+        lom.rbm = na::DMatrix::<f64>::zeros(42, n).into();
+        assert!(matches!(
+            lom.rbm_covariance(),
+            Err(crate::LinearOpticalModelError::CovarianceShape { expected: 84, .. })
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+//! # Covariance propagation
+//!
+//! Analytic propagation of the rigid body motions covariance `Σ` to the optical metrics as `S Σ Sᵀ`
+//! where `S` is an [OpticalSensitivity]
+
+use nalgebra as na;
+
+use crate::{LinearOpticalModelError, OpticalSensitivities, OpticalSensitivity, Result};
+
+/// Covariance of the rigid body motions
+///
+/// The `[N,N]` covariance matrix rows and columns are given in the same order than [RigidBodyMotions](crate::RigidBodyMotions) rows
+#[derive(Debug, Clone)]
+pub struct RbmCovariance<const N: usize = 84>(na::DMatrix<f64>);
+impl<const N: usize> RbmCovariance<N> {
+    /// Creates a rigid body motions covariance from a `[N,N]` matrix
+    pub fn new(covariance: na::DMatrix<f64>) -> Result<Self> {
+        if covariance.shape() != (N, N) {
+            return Err(LinearOpticalModelError::CovarianceShape {
+                expected: N,
+                found: covariance.shape(),
+            });
+        }
+        Ok(Self(covariance))
+    }
+    /// Creates a diagonal rigid body motions covariance from the standard deviation of each degree of freedom
+    pub fn from_std(std: &[f64]) -> Result<Self> {
+        if std.len() != N {
+            return Err(LinearOpticalModelError::CovarianceShape {
+                expected: N,
+                found: (std.len(), 1),
+            });
+        }
+        Ok(Self(na::DMatrix::from_diagonal(
+            &na::DVector::from_iterator(N, std.iter().map(|x| x * x)),
+        )))
+    }
+    /// Returns a reference to the `[N,N]` covariance matrix
+    pub fn matrix(&self) -> &na::DMatrix<f64> {
+        &self.0
+    }
+}
+
+impl<const N: usize> OpticalSensitivity<N> {
+    /// Returns the covariance `S Σ Sᵀ` of the optical metric
+    ///
+    /// For the [Wavefront](OpticalSensitivity::Wavefront) sensitivity, the matrix is as large as the square of the number of pixels in the pupil,
+    /// consider [variance](OpticalSensitivity::variance) instead
+    pub fn covariance(&self, rbm: &RbmCovariance<N>) -> Result<na::DMatrix<f64>> {
+        let s = self.matrix()?;
+        Ok(&s * rbm.matrix() * s.transpose())
+    }
+    /// Returns the variance of the optical metric, the diagonal of `S Σ Sᵀ`
+    pub fn variance(&self, rbm: &RbmCovariance<N>) -> Result<Vec<f64>> {
+        let s = self.matrix()?;
+        let s_sigma = &s * rbm.matrix();
+        Ok(s_sigma
+            .component_mul(&s)
+            .column_sum()
+            .iter()
+            .cloned()
+            .collect())
+    }
+    /// Returns the standard deviation of the optical metric
+    ///
+    /// The values are given in the same order and units than [Stats::std](crate::Stats::std)
+    pub fn std(&self, rbm: &RbmCovariance<N>) -> Result<Vec<f64>> {
+        Ok(self.variance(rbm)?.into_iter().map(f64::sqrt).collect())
+    }
+    fn matrix(&self) -> Result<na::DMatrix<f64>> {
+        match self {
+            OpticalSensitivity::SegmentMask(_) | OpticalSensitivity::PupilMask(_) => {
+                Err(LinearOpticalModelError::NotAMeasurement(self.to_string()))
+            }
+            _ => Ok(self.into()),
+        }
+    }
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Returns the covariance `S Σ Sᵀ` of the optical metric of the same variant than `index`
+    pub fn covariance(
+        &self,
+        index: OpticalSensitivity<N>,
+        rbm: &RbmCovariance<N>,
+    ) -> Result<na::DMatrix<f64>> {
+        self.try_get(index)?.covariance(rbm)
+    }
+    /// Returns the standard deviation of the optical metric of the same variant than `index`
+    ///
+    /// The values are given in the same order and units than [Stats::std](crate::Stats::std)
+    pub fn std(&self, index: OpticalSensitivity<N>, rbm: &RbmCovariance<N>) -> Result<Vec<f64>> {
+        self.try_get(index)?.std(rbm)
+    }
+    /// Returns the expected WFE RMS in `[m]` within the exit pupil for zero mean rigid body motions
+    pub fn expected_wfe_rms(&self, rbm: &RbmCovariance<N>) -> Result<f64> {
+        let variance = self
+            .try_get(OpticalSensitivity::Wavefront(vec![]))?
+            .variance(rbm)?;
+        Ok((variance.iter().sum::<f64>() / variance.len() as f64).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Stats, LOM};
+
+    #[test]
+    fn propagation() {
+        let n = 500;
+        let rbm = na::DMatrix::<f64>::from_fn(84, n, |i, j| {
+            1e-6 * ((i as f64 + 1.3) * (j as f64 + 0.7) * 0.917).sin()
+        });
+        let mean = rbm.column_mean();
+        let centered = rbm.clone() - &mean * na::RowDVector::from_element(n, 1f64);
+        let covariance = RbmCovariance::new(&centered * centered.transpose() / n as f64).unwrap();
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = rbm.into();
+        let sens = lom.sensitivities();
+        for (std, expected) in [
+            (
+                lom.tiptilt().std(None),
+                sens.std(OpticalSensitivity::TipTilt(vec![]), &covariance),
+            ),
+            (
+                lom.segment_tiptilt().std(None),
+                sens.std(OpticalSensitivity::SegmentTipTilt(vec![]), &covariance),
+            ),
+            (
+                lom.segment_piston().std(None),
+                sens.std(OpticalSensitivity::SegmentPiston(vec![]), &covariance),
+            ),
+        ] {
+            let expected = expected.unwrap();
+            assert_eq!(std.len(), expected.len());
+            std.iter()
+                .zip(&expected)
+                .for_each(|(a, b)| assert!((a - b).abs() <= 1e-9 * a.abs().max(1e-15)));
+        }
+        let cov = sens
+            .covariance(OpticalSensitivity::TipTilt(vec![]), &covariance)
+            .unwrap();
+        assert_eq!(cov.shape(), (2, 2));
+        assert!(RbmCovariance::<84>::from_std(&[1f64; 42]).is_err());
+        let diagonal = RbmCovariance::<84>::from_std(&[1e-6; 84]).unwrap();
+        assert!(sens.expected_wfe_rms(&diagonal).unwrap() > 0f64);
+    }
+}
@@ -0,0 +1,375 @@
+//! # FITS export of wavefront maps
+//!
+//! The [LOM] wavefront pupil maps are written to a [FITS](https://fits.gsfc.nasa.gov/fits_standard.html) file
+//! as a 2-D image for a single sample or as a 3-D cube `[n_px,n_px,n_sample]` for a time series,
+//! with the x axis of the [PupilGeometry](crate::PupilGeometry) along the first image axis.
+//! The primary header carries:
+//!  - the pupil sampling: the number of pixels within the pupil `NPUPIL` and, if known,
+//!    the pixel scale `PIXSCALE` `[m]` with the matching linear coordinates of the first 2 axes,
+//!  - the wavefront units `BUNIT`,
+//!  - the time stamps: `TIME` `[s]` for a map, `TSTART`, `TSTOP` `[s]` and the linear coordinates of the 3rd axis for a cube.
+//!
+//! The segment mask, if available, is written as a `[n_px,n_px]` int32 image in a second HDU named `SEGMASK`,
+//! with the segment number within the pupil and 0 outside.
+//!
+//! # Example
+//! ```no_run
+//! use gmt_lom::{fits::FitsOptions, LOM};
+//!
+//! let lom = LOM::builder()
+//!     .parquet_rigid_body_motions("rbm.parquet", &Default::default(), None, None)?
+//!     .build()?;
+//! lom.wavefront_to_fits("wavefront.fits", &FitsOptions::default().samples(0..100))?;
+//! # Ok::<(), gmt_lom::LinearOpticalModelError>(())
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crate::{Result, LOM};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FitsError {
+    #[error("failed to write FITS file")]
+    Io(#[from] io::Error),
+    #[error("invalid samples range {range:?} for {n_sample} samples")]
+    Samples {
+        range: Range<usize>,
+        n_sample: usize,
+    },
+}
+
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+
+/// FITS header
+#[derive(Default)]
+struct Header(Vec<u8>);
+impl Header {
+    fn card(&mut self, key: &str, value: &str, comment: &str) {
+        let mut card = format!("{key:<8}= {value:>20}");
+        if !comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(comment);
+        }
+        card.truncate(CARD);
+        self.0.extend(format!("{card:<CARD$}").into_bytes());
+    }
+    fn logical(&mut self, key: &str, value: bool, comment: &str) {
+        self.card(key, if value { "T" } else { "F" }, comment);
+    }
+    fn integer(&mut self, key: &str, value: i64, comment: &str) {
+        self.card(key, &value.to_string(), comment);
+    }
+    fn real(&mut self, key: &str, value: f64, comment: &str) {
+        self.card(key, &format!("{value:.12E}"), comment);
+    }
+    fn string(&mut self, key: &str, value: &str, comment: &str) {
+        self.card(
+            key,
+            &format!("{:<20}", format!("'{:<8}'", value.replace('\'', "''"))),
+            comment,
+        );
+    }
+    /// Writes the header with the `END` card, padded with spaces to a multiple of the block size
+    fn write<W: Write>(mut self, writer: &mut W) -> io::Result<()> {
+        self.0.extend(format!("{:<CARD$}", "END").into_bytes());
+        self.0.resize(self.0.len().next_multiple_of(BLOCK), b' ');
+        writer.write_all(&self.0)
+    }
+}
+
+/// Pads the data unit of `n_byte` bytes with zeros to a multiple of the block size
+fn pad<W: Write>(writer: &mut W, n_byte: usize) -> io::Result<()> {
+    writer.write_all(&vec![0u8; n_byte.next_multiple_of(BLOCK) - n_byte])
+}
+
+/// FITS export options
+#[derive(Debug, Clone, Default)]
+pub struct FitsOptions {
+    samples: Option<Range<usize>>,
+    pixel_scale: Option<f64>,
+}
+impl FitsOptions {
+    /// Only writes the pupil map of the sample `index` as a 2-D image
+    pub fn sample(self, index: usize) -> Self {
+        self.samples(index..index + 1)
+    }
+    /// Only writes the pupil maps of the samples within the `samples` range
+    ///
+    /// A range with a single sample is written as a 2-D image
+    pub fn samples(mut self, samples: Range<usize>) -> Self {
+        self.samples = Some(samples);
+        self
+    }
+    /// Sets the size of a pupil pixel `[m]`, overriding the pixel size of the [PupilGeometry](crate::PupilGeometry)
+    pub fn pixel_scale(mut self, pixel_scale: f64) -> Self {
+        self.pixel_scale = Some(pixel_scale);
+        self
+    }
+}
+
+impl LOM {
+    /// Writes the wavefront pupil maps `[m]` to a FITS file
+    ///
+    /// The pupil maps are computed one at a time as they are written to the file
+    pub fn wavefront_to_fits<P: AsRef<Path>>(&self, path: P, options: &FitsOptions) -> Result<()> {
+        let sens = self.sensitivities();
+        let pupil_mask = sens.pupil_mask()?;
+        let maps = self.try_wavefront_iter()?;
+        let n_sample = self.len();
+        let range = options.samples.clone().unwrap_or(0..n_sample);
+        if range.is_empty() || range.end > n_sample {
+            return Err(FitsError::Samples { range, n_sample }.into());
+        }
+        let pupil = sens.pupil_geometry()?;
+        let n_px = pupil.side;
+        // image pixels ordered along x first
+        let order: Vec<usize> = (0..n_px)
+            .flat_map(|j| (0..n_px).map(move |i| (i, j)))
+            .map(|(i, j)| pupil.index(i, j))
+            .collect();
+        let pixel_scale = options.pixel_scale.or(sens
+            .metadata()
+            .and_then(|metadata| metadata.pupil.as_ref())
+            .map(|pupil| pupil.pixel_size));
+        let time = self.time();
+
+        let mut header = Header::default();
+        header.logical("SIMPLE", true, "conforms to FITS standard");
+        header.integer("BITPIX", -64, "IEEE double precision");
+        header.integer("NAXIS", if range.len() > 1 { 3 } else { 2 }, "");
+        header.integer("NAXIS1", n_px as i64, "");
+        header.integer("NAXIS2", n_px as i64, "");
+        if range.len() > 1 {
+            header.integer("NAXIS3", range.len() as i64, "number of samples");
+        }
+        header.logical("EXTEND", true, "");
+        header.string("BUNIT", "m", "wavefront units");
+        header.integer(
+            "NPUPIL",
+            pupil_mask.iter().filter(|&&m| m).count() as i64,
+            "number of pixels within the pupil",
+        );
+        if let Some(pixel_scale) = pixel_scale {
+            header.real("PIXSCALE", pixel_scale, "[m] pupil pixel size");
+            for axis in ["1", "2"] {
+                header.string(&format!("CTYPE{axis}"), "LINEAR", "");
+                header.string(&format!("CUNIT{axis}"), "m", "");
+                header.real(&format!("CRPIX{axis}"), 0.5 * (n_px + 1) as f64, "");
+                header.real(&format!("CRVAL{axis}"), 0., "pupil center");
+                header.real(&format!("CDELT{axis}"), pixel_scale, "");
+            }
+        }
+        header.string("TIMEUNIT", "s", "");
+        if range.len() > 1 {
+            header.real("TSTART", time[range.start], "[s] time of the first sample");
+            header.real("TSTOP", time[range.end - 1], "[s] time of the last sample");
+            let tau = self.sampling_frequency().map_or_else(
+                || (time[range.end - 1] - time[range.start]) / (range.len() - 1) as f64,
+                f64::recip,
+            );
+            header.string("CTYPE3", "TIME", "");
+            header.string("CUNIT3", "s", "");
+            header.real("CRPIX3", 1., "");
+            header.real("CRVAL3", time[range.start], "");
+            header.real("CDELT3", tau, "sampling period");
+        } else {
+            header.real("TIME", time[range.start], "[s] time of the sample");
+        }
+        if let Some(sampling_frequency) = self.sampling_frequency() {
+            header.real("SAMPFREQ", sampling_frequency, "[Hz] sampling frequency");
+        }
+
+        let mut file = BufWriter::new(File::create(path).map_err(FitsError::from)?);
+        let write = || -> io::Result<()> {
+            header.write(&mut file)?;
+            for map in maps.skip(range.start).take(range.len()) {
+                let bytes: Vec<u8> = order.iter().flat_map(|&k| map[k].to_be_bytes()).collect();
+                file.write_all(&bytes)?;
+            }
+            pad(&mut file, range.len() * n_px * n_px * 8)?;
+            if let Ok(segment_mask) = sens.segment_mask() {
+                let mut grid = vec![0i32; n_px * n_px];
+                pupil_mask
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, &m)| m.then_some(k))
+                    .zip(segment_mask)
+                    .for_each(|(k, &s)| grid[k] = s);
+                let map: Vec<u8> = order.iter().flat_map(|&k| grid[k].to_be_bytes()).collect();
+                let mut header = Header::default();
+                header.string("XTENSION", "IMAGE", "image extension");
+                header.integer("BITPIX", 32, "");
+                header.integer("NAXIS", 2, "");
+                header.integer("NAXIS1", n_px as i64, "");
+                header.integer("NAXIS2", n_px as i64, "");
+                header.integer("PCOUNT", 0, "");
+                header.integer("GCOUNT", 1, "");
+                header.string("EXTNAME", "SEGMASK", "segment mask");
+                header.write(&mut file)?;
+                file.write_all(&map)?;
+                pad(&mut file, map.len())?;
+            }
+            file.flush()
+        };
+        write().map_err(FitsError::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        LinearOpticalModelError, OpticalSensitivities, OpticalSensitivity, PupilGeometry,
+        PupilOrientation,
+    };
+    use nalgebra as na;
+
+    // Checks that the FITS data and the LOM wavefront agree within rounding errors
+    fn assert_close(data: &[f64], wavefront: &[f64]) {
+        assert_eq!(data.len(), wavefront.len());
+        assert!(data
+            .iter()
+            .zip(wavefront)
+            .all(|(a, b)| (a - b).abs() < 1e-15));
+    }
+
+    // Returns the value of the card `key` in the header starting at `offset`
+    fn value(buffer: &[u8], offset: usize, key: &str) -> Option<String> {
+        buffer[offset..]
+            .chunks(CARD)
+            .map(|card| std::str::from_utf8(card).unwrap())
+            .take_while(|card| !card.starts_with("END "))
+            .find(|card| card[..8].trim_end() == key)
+            .map(|card| {
+                card[10..]
+                    .split(" / ")
+                    .next()
+                    .unwrap()
+                    .trim()
+                    .trim_matches('\'')
+                    .trim()
+                    .to_string()
+            })
+    }
+
+    #[test]
+    fn wavefront_to_fits() {
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = na::DMatrix::<f64>::from_fn(84, 3, |i, j| 1e-6 * ((i + j) % 5) as f64).into();
+        let path = std::env::temp_dir().join(format!("gmt-lom_{}.fits", std::process::id()));
+
+        lom.wavefront_to_fits(&path, &FitsOptions::default().pixel_scale(0.1))
+            .unwrap();
+        let buffer = std::fs::read(&path).unwrap();
+        assert_eq!(buffer.len() % BLOCK, 0);
+        assert_eq!(value(&buffer, 0, "SIMPLE").unwrap(), "T");
+        assert_eq!(value(&buffer, 0, "NAXIS").unwrap(), "3");
+        assert_eq!(value(&buffer, 0, "NAXIS1").unwrap(), "8");
+        assert_eq!(value(&buffer, 0, "NAXIS3").unwrap(), "3");
+        assert_eq!(value(&buffer, 0, "BUNIT").unwrap(), "m");
+        assert_eq!(value(&buffer, 0, "NPUPIL").unwrap(), "60");
+        assert_eq!(
+            value(&buffer, 0, "TSTOP").unwrap().parse::<f64>().unwrap(),
+            2.
+        );
+        let data: Vec<f64> = buffer[BLOCK..BLOCK + 3 * 64 * 8]
+            .chunks(8)
+            .map(|x| f64::from_be_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_close(&data, &lom.wavefront());
+        let x_data = data;
+        let offset = 2 * BLOCK;
+        assert_eq!(value(&buffer, offset, "XTENSION").unwrap(), "IMAGE");
+        assert_eq!(value(&buffer, offset, "EXTNAME").unwrap(), "SEGMASK");
+        let segment_mask: Vec<i32> = buffer[offset + BLOCK..offset + BLOCK + 64 * 4]
+            .chunks(4)
+            .map(|x| i32::from_be_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_eq!(segment_mask[0], 0);
+        assert_eq!(
+            segment_mask.iter().filter(|&&s| s > 0).count(),
+            lom.sensitivities().segment_mask().unwrap().len()
+        );
+
+        lom.wavefront_to_fits(&path, &FitsOptions::default().sample(1))
+            .unwrap();
+        let buffer = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value(&buffer, 0, "NAXIS").unwrap(), "2");
+        assert!(value(&buffer, 0, "NAXIS3").is_none());
+        assert_eq!(
+            value(&buffer, 0, "TIME").unwrap().parse::<f64>().unwrap(),
+            1.
+        );
+        let data: Vec<f64> = buffer[BLOCK..BLOCK + 64 * 8]
+            .chunks(8)
+            .map(|x| f64::from_be_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_close(&data, &lom.wavefront_cube()[1]);
+
+        assert!(lom
+            .wavefront_to_fits(&path, &FitsOptions::default().samples(2..4))
+            .is_err());
+
+        // y fastest pupil: the images are the transpose of the x fastest ones
+        let rbm = lom.rbm.clone();
+        let mut y_lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic().with_pupil_geometry(
+                PupilGeometry::new(8, 0.1).orientation(PupilOrientation::YFastest),
+            ))
+            .build()
+            .unwrap();
+        y_lom.rbm = rbm;
+        y_lom
+            .wavefront_to_fits(&path, &FitsOptions::default().sample(0))
+            .unwrap();
+        let y_buffer = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let image = |data: &[f64]| na::DMatrix::from_column_slice(8, 8, data);
+        let y_data: Vec<f64> = y_buffer[BLOCK..BLOCK + 64 * 8]
+            .chunks(8)
+            .map(|x| f64::from_be_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_close(
+            image(&y_data).as_slice(),
+            image(&x_data[..64]).transpose().as_slice(),
+        );
+        let y_segment_mask: Vec<f64> = y_buffer[2 * BLOCK + BLOCK..2 * BLOCK + BLOCK + 64 * 4]
+            .chunks(4)
+            .map(|x| i32::from_be_bytes(x.try_into().unwrap()) as f64)
+            .collect();
+        let x_segment_mask: Vec<f64> = segment_mask.iter().map(|&s| s as f64).collect();
+        assert_eq!(image(&y_segment_mask), image(&x_segment_mask).transpose());
+
+        // non-square pupil mask
+        let sens: OpticalSensitivities = lom
+            .sensitivities()
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::PupilMask(mask) => {
+                    OpticalSensitivity::PupilMask(mask[..mask.len() - 1].to_vec())
+                }
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        let rbm = lom.rbm.clone();
+        let mut lom = LOM::builder().optical_sensitivities(sens).build().unwrap();
+        lom.rbm = rbm;
+        assert!(matches!(
+            lom.wavefront_to_fits(&path, &FitsOptions::default()),
+            Err(LinearOpticalModelError::PupilGeometry { n_pixel: 63, .. })
+        ));
+        assert!(!path.exists());
+    }
+}
@@ -38,13 +38,13 @@
 //! sp.chunks(7).enumerate().for_each(|(k, sp)| println!(" - S{}: {:.0?}nm", k + 1, sp) );
 //! ```
 
-use bincode;
 use serde::Serialize;
 use serde_pickle as pickle;
 
 use std::{
     env,
     fs::File,
+    io::Write,
     marker::PhantomData,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
@@ -52,17 +52,44 @@ use std::{
 };
 
 pub mod lom;
-pub use lom::{LOMBuilder, LOM};
+pub use lom::{LOMBuilder, PistonPairs, WavefrontResidual, LOM};
 mod optical_sensitivities;
-pub use optical_sensitivities::{from_opticals, OpticalSensitivities, OpticalSensitivity};
+pub use optical_sensitivities::{
+    from_opticals, OpticalSensitivities, OpticalSensitivity, Provenance, PupilGeometry,
+    PupilOrientation, SensitivitiesHeader, SensitivitiesMetadata, FORMAT_VERSION,
+};
 mod rigid_body_motions;
-pub use rigid_body_motions::RigidBodyMotions;
+pub use rigid_body_motions::{
+    AngleUnit, CsvLayout, CsvOptions, ImportOptions, ImportReport, LengthUnit, MissingPolicy,
+    RigidBodyMotions, RigidBodyMotionsError, TIME_LABELS,
+};
+#[cfg(feature = "apache")]
+pub use rigid_body_motions::{ArrowLayout, SAMPLING_FREQUENCY_KEY};
+pub mod analysis;
+pub mod contributions;
+mod covariance;
+pub use covariance::RbmCovariance;
+pub mod fits;
+pub mod matlab;
+mod psd;
+pub use psd::{OpticalPsd, RbmCsd};
+pub mod reconstructor;
+pub use reconstructor::{Reconstructor, Regularization};
+#[cfg(feature = "numpy")]
+pub mod numpy;
+#[cfg(feature = "apache")]
+pub mod streaming;
 #[cfg(feature = "apache")]
 mod table;
+#[cfg(feature = "tolerancing")]
+pub mod tolerancing;
+pub mod zernike;
 #[cfg(feature = "apache")]
-pub use table::Table;
+pub use table::{
+    RbmColumns, RbmSchema, Table, TableError, TableOptions, ToTable, RBM_CONVENTIONS,
+    RBM_WIDE_LABELS, UNITS_KEY,
+};
 
-use crate::rigid_body_motions::RigidBodyMotionsError;
 // pub mod actors_interface;
 
 #[derive(thiserror::Error, Debug)]
@@ -73,21 +100,75 @@ pub enum LinearOpticalModelError {
     ParquetFile(#[source] std::io::Error, PathBuf),
     #[error("sensitivities cannot be loaded from optical_sensitivities.rs.bin")]
     SensitivityData(#[from] bincode::Error),
+    #[error("unsupported sensitivities file format version: {0}")]
+    SensitivityFormat(u32),
+    #[error("sensitivities are given for {found} degrees of freedom instead of {expected}")]
+    SensitivityDof { expected: usize, found: usize },
+    #[error("{name} sensitivity of length {len} is not a multiple of {n_dof} degrees of freedom")]
+    SensitivityLength {
+        name: String,
+        len: usize,
+        n_dof: usize,
+    },
+    #[error("{name} sensitivity has {found} rows instead of the {expected} rows of the metadata")]
+    SensitivityRows {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("sensitivities file is corrupted (checksum mismatch)")]
+    SensitivityChecksum,
+    #[error("a {side}x{side} pupil grid does not match a {n_pixel} pixels pupil mask")]
+    PupilGeometry { side: usize, n_pixel: usize },
+    #[error("the pupil mask has no pixel within the exit pupil")]
+    EmptyPupil,
+    #[error("{name} sensitivity has {found} pixels instead of the {expected} pixels within the pupil mask")]
+    PupilPixels {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
     #[error("segment tip-tilt sensitivity is missing")]
     SegmentTipTilt,
+    #[error("optical sensitivity {0} is missing")]
+    MissingSensitivity(String),
+    #[error("{0} is not a measurement sensitivity")]
+    NotAMeasurement(String),
+    #[error("expected a [{expected},{expected}] covariance, found {found:?}")]
+    CovarianceShape {
+        expected: usize,
+        found: (usize, usize),
+    },
+    #[error("expected as many cross-spectral densities as increasing frequencies (at least one), found {n_csd} and {n_frequency}")]
+    CsdFrequencies { n_frequency: usize, n_csd: usize },
+    #[error("expected {expected} time samples, found {found}")]
+    TimeLength { expected: usize, found: usize },
     #[error("rigid body motions are missing")]
     MissingRigidBodyMotions,
+    #[error("rigid body motions sampling frequency is unknown")]
+    MissingSamplingFrequency,
     #[error("failed to write optical metric to pickle file ")]
     MetricsPickleFile(#[from] pickle::Error),
     #[error("missing table {0} column ")]
     Table(String),
-    #[error("PMT read failed")]
+    #[error("CSV read or write failed")]
     Read(#[from] csv::Error),
     #[cfg(feature = "apache")]
     #[error("failed to read parquet Table")]
     TableRead(#[from] table::TableError),
     #[error("failed to process rigid body motions")]
     RigidBodyMotions(#[from] RigidBodyMotionsError),
+    #[cfg(feature = "numpy")]
+    #[error("NumPy file import or export failed")]
+    Numpy(#[from] numpy::NumpyError),
+    #[error("FITS export failed")]
+    Fits(#[from] fits::FitsError),
+    #[error("MAT-file export failed")]
+    Matlab(#[from] matlab::MatError),
+    #[error("Zernike decomposition failed")]
+    Zernike(#[from] zernike::ZernikeError),
+    #[error("failed to reconstruct rigid body motions")]
+    Reconstructor(#[from] reconstructor::ReconstructorError),
 }
 type Result<T> = std::result::Result<T, LinearOpticalModelError>;
 
@@ -98,6 +179,8 @@ pub enum Formatting {
 }
 
 /// Sensitivities serialization into a [bincode] file
+///
+/// The sensitivities are preceded by a [SensitivitiesHeader]; legacy files without header can still be loaded
 pub trait Bin {
     fn dump<P: AsRef<Path>>(self, path: P) -> Result<Self>
     where
@@ -109,12 +192,12 @@ pub trait Bin {
 impl<const N: usize> Bin for OpticalSensitivities<N> {
     /// Saves sensitivities to `path`
     fn dump<P: AsRef<Path>>(self, path: P) -> Result<Self> {
-        bincode::serialize_into(File::create(path)?, &self)?;
+        File::create(path)?.write_all(&self.to_bytes()?)?;
         Ok(self)
     }
     /// Load sensitivities from `path`
     fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(bincode::deserialize_from(File::open(path)?)?)
+        Self::from_bytes(&std::fs::read(path)?)
     }
 }
 
@@ -192,6 +275,28 @@ pub struct SegmentTipTilt(Vec<f64>);
 /// Type holding the segment piston values
 #[derive(Serialize, Debug, Clone)]
 pub struct SegmentPiston(Vec<f64>);
+/// Type holding the differential segment piston values
+///
+/// The segment pairs `(i,j)` are given as segment ids `[1,...,7]` and the differential piston is `pj-pi`
+#[derive(Serialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct DifferentialSegmentPiston {
+    data: Vec<f64>,
+    #[serde(skip)]
+    pairs: Vec<(usize, usize)>,
+}
+impl DifferentialSegmentPiston {
+    /// Returns the segment pairs
+    pub fn pairs(&self) -> &[(usize, usize)] {
+        &self.pairs
+    }
+}
+/// Type holding the segment wavefront error RMS values
+#[derive(Serialize, Debug, Clone)]
+pub struct SegmentWfeRms(Vec<f64>);
+/// Type holding the exit pupil wavefront error RMS values
+#[derive(Serialize, Debug, Clone)]
+pub struct WfeRms(Vec<f64>);
 // Dereferencing
 impl Deref for TipTilt {
     type Target = Vec<f64>;
@@ -226,6 +331,39 @@ impl DerefMut for SegmentPiston {
         &mut self.0
     }
 }
+impl Deref for DifferentialSegmentPiston {
+    type Target = Vec<f64>;
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+impl DerefMut for DifferentialSegmentPiston {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+impl Deref for SegmentWfeRms {
+    type Target = Vec<f64>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for SegmentWfeRms {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+impl Deref for WfeRms {
+    type Target = Vec<f64>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for WfeRms {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
 impl From<TipTilt> for Vec<f64> {
     fn from(value: TipTilt) -> Self {
         value.0
@@ -241,6 +379,21 @@ impl From<SegmentTipTilt> for Vec<f64> {
         value.0
     }
 }
+impl From<DifferentialSegmentPiston> for Vec<f64> {
+    fn from(value: DifferentialSegmentPiston) -> Self {
+        value.data
+    }
+}
+impl From<SegmentWfeRms> for Vec<f64> {
+    fn from(value: SegmentWfeRms) -> Self {
+        value.0
+    }
+}
+impl From<WfeRms> for Vec<f64> {
+    fn from(value: WfeRms) -> Self {
+        value.0
+    }
+}
 
 pub trait ToPkl {
     /// Writes optical metrics to a [pickle] file
@@ -256,12 +409,71 @@ pub trait ToPkl {
 impl ToPkl for TipTilt {}
 impl ToPkl for SegmentTipTilt {}
 impl ToPkl for SegmentPiston {}
+impl ToPkl for DifferentialSegmentPiston {}
+impl ToPkl for SegmentWfeRms {}
+impl ToPkl for WfeRms {}
+
+/// Optical metrics serialization into a CSV file
+///
+/// The CSV file has a `time` column followed by one column per [item](OpticalMetrics::item_labels),
+/// the units are given within brackets in the header, e.g. `S3_tip [rd]`
+pub trait ToCsv: Deref<Target = Vec<f64>> + OpticalMetrics {
+    /// Writes optical metrics to a CSV file with the `time` `[s]` of each sample
+    fn to_csv<P: AsRef<Path>>(&self, path: P, time: &[f64]) -> Result<()> {
+        let file = File::create(path).map_err(csv::Error::from)?;
+        self.to_csv_writer(file, time, self.units())
+    }
+    /// Writes optical metrics to a CSV writer with the `time` `[s]` of each sample and the items `units`
+    fn to_csv_writer<W: Write>(&self, writer: W, time: &[f64], units: &str) -> Result<()> {
+        let n_item = self.n_item();
+        if time.len() * n_item != self.len() {
+            return Err(LinearOpticalModelError::TimeLength {
+                expected: self.len() / n_item.max(1),
+                found: time.len(),
+            });
+        }
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(
+            std::iter::once("time [s]".to_string()).chain(
+                self.item_labels()
+                    .into_iter()
+                    .map(|label| format!("{label} [{units}]")),
+            ),
+        )?;
+        for (t, items) in time.iter().zip(self.chunks(n_item)) {
+            writer.write_record(
+                std::iter::once(t.to_string()).chain(items.iter().map(|x| x.to_string())),
+            )?;
+        }
+        writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+}
+impl ToCsv for TipTilt {}
+impl ToCsv for SegmentTipTilt {}
+impl ToCsv for SegmentPiston {}
+impl ToCsv for DifferentialSegmentPiston {}
+impl ToCsv for SegmentWfeRms {}
+impl ToCsv for WfeRms {}
 
 /// Trait for the [LOM] optical metrics
 ///
-/// A simple trait looking at the number of items in the [TipTilt], [SegmentTipTilt] and [SegmentPiston] metrics
+/// A simple trait looking at the number of items in the [TipTilt], [SegmentTipTilt], [SegmentPiston],
+/// [DifferentialSegmentPiston], [SegmentWfeRms] and [WfeRms] metrics
 pub trait OpticalMetrics {
     fn n_item(&self) -> usize;
+    /// Returns the label of each item
+    ///
+    /// Defaults to `item_1`,...,`item_n`
+    fn item_labels(&self) -> Vec<String> {
+        (1..=self.n_item()).map(|i| format!("item_{i}")).collect()
+    }
+    /// Returns the units of the items
+    ///
+    /// Defaults to no units
+    fn units(&self) -> &str {
+        ""
+    }
     /// Returns a [Chunks] iterator with chunks the size of [n_item](OpticalMetrics::n_item)
     fn items(&self) -> Chunks<'_, f64>
     where
@@ -277,6 +489,12 @@ impl OpticalMetrics for TipTilt {
     fn n_item(&self) -> usize {
         2
     }
+    fn item_labels(&self) -> Vec<String> {
+        vec!["tip".to_string(), "tilt".to_string()]
+    }
+    fn units(&self) -> &str {
+        "rd"
+    }
     fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
         let n_item = self.n_item();
         let n_total = self.len() / n_item;
@@ -297,6 +515,15 @@ impl OpticalMetrics for SegmentTipTilt {
     fn n_item(&self) -> usize {
         14
     }
+    /// `S1_tip`,...,`S7_tip`,`S1_tilt`,...,`S7_tilt`
+    fn item_labels(&self) -> Vec<String> {
+        segment_labels("tip")
+            .chain(segment_labels("tilt"))
+            .collect()
+    }
+    fn units(&self) -> &str {
+        "rd"
+    }
     fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
         let n_item = self.n_item();
         let n_total = self.len() / n_item;
@@ -317,6 +544,12 @@ impl OpticalMetrics for SegmentPiston {
     fn n_item(&self) -> usize {
         7
     }
+    fn item_labels(&self) -> Vec<String> {
+        segment_labels("piston").collect()
+    }
+    fn units(&self) -> &str {
+        "m"
+    }
     fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
         let n_item = self.n_item();
         let n_total = self.len() / n_item;
@@ -332,6 +565,86 @@ impl OpticalMetrics for SegmentPiston {
             .collect()
     }
 }
+impl OpticalMetrics for DifferentialSegmentPiston {
+    /// [DifferentialSegmentPiston] as many items as segment pairs
+    fn n_item(&self) -> usize {
+        self.pairs.len()
+    }
+    /// `Sj-Si_piston` for each segment pair `(i,j)`
+    fn item_labels(&self) -> Vec<String> {
+        self.pairs
+            .iter()
+            .map(|(i, j)| format!("S{j}-S{i}_piston"))
+            .collect()
+    }
+    fn units(&self) -> &str {
+        "m"
+    }
+    fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
+        let n_item = self.n_item();
+        let n_total = self.len() / n_item;
+        assert!(n_total >= n_sample.unwrap_or(n_total), "not enough samples");
+        (0..n_item)
+            .flat_map(|i| {
+                self.iter()
+                    .skip(i)
+                    .step_by(n_item)
+                    .skip(n_total - n_sample.unwrap_or(n_total))
+            })
+            .cloned()
+            .collect()
+    }
+}
+impl OpticalMetrics for SegmentWfeRms {
+    /// [SegmentWfeRms] `7` items
+    fn n_item(&self) -> usize {
+        7
+    }
+    fn item_labels(&self) -> Vec<String> {
+        segment_labels("wfe_rms").collect()
+    }
+    fn units(&self) -> &str {
+        "m"
+    }
+    fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
+        let n_item = self.n_item();
+        let n_total = self.len() / n_item;
+        assert!(n_total >= n_sample.unwrap_or(n_total), "not enough samples");
+        (0..n_item)
+            .flat_map(|i| {
+                self.iter()
+                    .skip(i)
+                    .step_by(n_item)
+                    .skip(n_total - n_sample.unwrap_or(n_total))
+            })
+            .cloned()
+            .collect()
+    }
+}
+impl OpticalMetrics for WfeRms {
+    /// [WfeRms] `1` item
+    fn n_item(&self) -> usize {
+        1
+    }
+    fn item_labels(&self) -> Vec<String> {
+        vec!["wfe_rms".to_string()]
+    }
+    fn units(&self) -> &str {
+        "m"
+    }
+    fn time_wise(&self, n_sample: Option<usize>) -> Vec<f64> {
+        let n_total = self.len();
+        assert!(n_total >= n_sample.unwrap_or(n_total), "not enough samples");
+        self.iter()
+            .skip(n_total - n_sample.unwrap_or(n_total))
+            .cloned()
+            .collect()
+    }
+}
+
+fn segment_labels(suffix: &str) -> impl Iterator<Item = String> + '_ {
+    (1..=7).map(move |i| format!("S{i}_{suffix}"))
+}
 
 /// Statistics on [OpticalMetrics]
 pub trait Stats {
@@ -392,6 +705,91 @@ pub trait Stats {
 impl Stats for TipTilt {}
 impl Stats for SegmentTipTilt {}
 impl Stats for SegmentPiston {}
+impl Stats for DifferentialSegmentPiston {}
+impl Stats for SegmentWfeRms {}
+impl Stats for WfeRms {}
+
+/// Spectral analysis of [OpticalMetrics] with the Welch method
+#[cfg(feature = "welch-sde")]
+pub trait Spectral: Deref<Target = Vec<f64>> + OpticalMetrics {
+    /// Returns the one-sided power spectral densities of each item in `[unit^2/Hz]`
+    ///
+    /// The mean of each item is removed before the estimation.
+    /// Optionally, the spectral densities are evaluated on the last `n_sample`, or on all the samples if there are fewer.
+    /// The spectral densities are empty if the metric has no item or less than 5 samples,
+    /// the minimum for the Welch method with 4 half-overlapping segments
+    fn psd(&self, sampling_frequency: f64, n_sample: Option<usize>) -> OpticalPsd {
+        use welch_sde::{Build, SpectralDensity};
+        let name = std::any::type_name::<Self>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let n_item = self.n_item();
+        let n_total = self.len().checked_div(n_item).unwrap_or_default();
+        let n = n_sample.map_or(n_total, |n| n.min(n_total));
+        if n < 5 {
+            return OpticalPsd::new(name, vec![], nalgebra::DMatrix::zeros(n_item, 0));
+        }
+        let psds: Vec<(Vec<f64>, Vec<f64>)> = (0..n_item)
+            .map(|i| {
+                let x: Vec<f64> = self
+                    .iter()
+                    .skip(i)
+                    .step_by(n_item)
+                    .skip(n_total - n)
+                    .cloned()
+                    .collect();
+                let mean = x.iter().sum::<f64>() / n as f64;
+                let x: Vec<f64> = x.into_iter().map(|x| x - mean).collect();
+                let welch: SpectralDensity<f64> =
+                    SpectralDensity::builder(&x, sampling_frequency).build();
+                let periodogram = welch.periodogram();
+                (
+                    periodogram.frequency(),
+                    periodogram.iter().map(|p| 2. * p).collect(),
+                )
+            })
+            .collect();
+        let frequencies = psds[0].0.clone();
+        let psd = nalgebra::DMatrix::from_row_iterator(
+            n_item,
+            frequencies.len(),
+            psds.into_iter().flat_map(|(_, p)| p),
+        );
+        OpticalPsd::new(name, frequencies, psd)
+    }
+    /// Returns the `[n_item,n_frequency]` cumulative power spectral densities in `[unit^2]`
+    fn cumulative_psd(
+        &self,
+        sampling_frequency: f64,
+        n_sample: Option<usize>,
+    ) -> nalgebra::DMatrix<f64> {
+        self.psd(sampling_frequency, n_sample).cumulative()
+    }
+    /// Returns the RMS of each item within the frequency band `[f_lo,f_hi]`
+    fn band_rms(
+        &self,
+        sampling_frequency: f64,
+        f_lo: f64,
+        f_hi: f64,
+        n_sample: Option<usize>,
+    ) -> Vec<f64> {
+        self.psd(sampling_frequency, n_sample).band_rms(f_lo, f_hi)
+    }
+}
+#[cfg(feature = "welch-sde")]
+impl Spectral for TipTilt {}
+#[cfg(feature = "welch-sde")]
+impl Spectral for SegmentTipTilt {}
+#[cfg(feature = "welch-sde")]
+impl Spectral for SegmentPiston {}
+#[cfg(feature = "welch-sde")]
+impl Spectral for DifferentialSegmentPiston {}
+#[cfg(feature = "welch-sde")]
+impl Spectral for SegmentWfeRms {}
+#[cfg(feature = "welch-sde")]
+impl Spectral for WfeRms {}
 
 #[cfg(test)]
 mod tests {
@@ -432,8 +830,8 @@ mod tests {
     fn segment_wfe_rms() {
         let mut m1_rbm = vec![vec![0f64; 6]; 7];
         let mut m2_rbm = vec![vec![0f64; 6]; 7];
-        for i in 0..6 {
-            m1_rbm[i][2] = 100e-9 * (i + 1) as f64;
+        for (i, rbm) in m1_rbm.iter_mut().take(6).enumerate() {
+            rbm[2] = 100e-9 * (i + 1) as f64;
         }
         m2_rbm[6][2] = -100e-9;
         let lom = LOM::builder()
@@ -443,4 +841,100 @@ mod tests {
         let swferms = lom.segment_wfe_rms::<-9>();
         print!("Segment WFE RMS : {:.0?} mas", swferms);
     }
+
+    #[cfg(feature = "welch-sde")]
+    #[test]
+    fn spectral() {
+        let fs = 100f64;
+        let tiptilt = TipTilt(
+            (0..4096)
+                .flat_map(|k| {
+                    let t = k as f64 / fs;
+                    [
+                        1e-6 * (2. * std::f64::consts::PI * 10. * t).sin(),
+                        1e-6 * (2. * std::f64::consts::PI * 30. * t).sin(),
+                    ]
+                })
+                .collect(),
+        );
+        let psd = tiptilt.psd(fs, None);
+        assert_eq!(psd.n_item(), 2);
+        let std = tiptilt.std(None);
+        psd.rms()
+            .iter()
+            .zip(&std)
+            .for_each(|(rms, std)| assert!((rms / std - 1.).abs() < 0.1));
+        let tip = tiptilt.band_rms(fs, 5., 15., None);
+        assert!((tip[0] / std[0] - 1.).abs() < 0.1);
+        assert!(tip[1] < 1e-2 * std[1]);
+        let cumulative = tiptilt.cumulative_psd(fs, Some(2048));
+        let last = cumulative.ncols() - 1;
+        assert!((cumulative[(1, last)].sqrt() / std[1] - 1.).abs() < 0.1);
+
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            lom.metric_psd(&tiptilt, None, None),
+            Err(LinearOpticalModelError::MissingSamplingFrequency)
+        ));
+        lom.rbm = lom.rbm.with_sampling_frequency(fs);
+        assert_eq!(
+            lom.metric_psd(&tiptilt, None, None).unwrap().frequencies(),
+            psd.frequencies()
+        );
+        assert_ne!(
+            lom.metric_psd(&tiptilt, Some(2. * fs), None)
+                .unwrap()
+                .frequencies(),
+            psd.frequencies()
+        );
+
+        assert_eq!(
+            tiptilt.psd(fs, Some(10_000)).frequencies(),
+            psd.frequencies()
+        );
+        for n in 0..5 {
+            let psd = tiptilt.psd(fs, Some(n));
+            assert_eq!(psd.n_item(), 2);
+            assert!(psd.frequencies().is_empty());
+        }
+        assert!(tiptilt
+            .psd(fs, Some(5))
+            .matrix()
+            .iter()
+            .all(|x| x.is_finite()));
+        let empty = DifferentialSegmentPiston {
+            data: vec![],
+            pairs: vec![],
+        };
+        let psd = empty.psd(fs, None);
+        assert_eq!(psd.n_item(), 0);
+        assert!(psd.frequencies().is_empty());
+        assert!(TipTilt(vec![]).psd(fs, None).rms().iter().all(|x| *x == 0.));
+    }
+
+    #[test]
+    fn to_csv() {
+        let segment_piston = SegmentPiston((0..14).map(|k| k as f64).collect());
+        let mut buffer = vec![];
+        segment_piston
+            .to_csv_writer(&mut buffer, &[0., 0.5], "nm")
+            .unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("time [s],S1_piston [nm],S2_piston [nm]"));
+        assert_eq!(lines.nth(1).unwrap(), "0.5,7,8,9,10,11,12,13");
+        assert!(matches!(
+            segment_piston.to_csv_writer(std::io::sink(), &[0.], "m"),
+            Err(LinearOpticalModelError::TimeLength {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
 }
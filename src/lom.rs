@@ -3,12 +3,88 @@ use std::fmt::Display;
 use skyangle::Conversion;
 
 use crate::{
-    Formatting, LinearOpticalModelError, Loader, LoaderTrait, OpticalSensitivities,
-    OpticalSensitivity, RigidBodyMotions, SegmentPiston, SegmentTipTilt, TipTilt,
+    DifferentialSegmentPiston, Formatting, LinearOpticalModelError, Loader, LoaderTrait,
+    OpticalSensitivities, OpticalSensitivity, RigidBodyMotions, SegmentPiston, SegmentTipTilt,
+    SegmentWfeRms, TipTilt, WfeRms,
 };
 
 type Result<T> = std::result::Result<T, LinearOpticalModelError>;
 
+/// Low order modes removed from the wavefront before computing the WFE RMS
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WavefrontResidual {
+    /// Nothing is removed
+    #[default]
+    Full,
+    /// The piston is removed
+    PistonRemoved,
+    /// The piston and the tip-tilt are removed
+    TipTiltRemoved,
+}
+impl WavefrontResidual {
+    /// Returns the RMS of the wavefront `w` sampled at the coordinates `xy` once the low order modes are removed
+    fn rms(&self, w: &[f64], xy: &[(f64, f64)]) -> f64 {
+        if w.is_empty() {
+            return 0f64;
+        }
+        let n = w.len() as f64;
+        match self {
+            WavefrontResidual::Full => (w.iter().map(|w| w * w).sum::<f64>() / n).sqrt(),
+            WavefrontResidual::PistonRemoved => {
+                let mean = w.iter().sum::<f64>() / n;
+                (w.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n).sqrt()
+            }
+            WavefrontResidual::TipTiltRemoved => {
+                // least-squares fit of a plane `a + bx + cy`
+                let mut ata = nalgebra::Matrix3::<f64>::zeros();
+                let mut atw = nalgebra::Vector3::<f64>::zeros();
+                for (w, (x, y)) in w.iter().zip(xy) {
+                    let a = nalgebra::Vector3::new(1f64, *x, *y);
+                    ata += a * a.transpose();
+                    atw += a * *w;
+                }
+                let c = ata
+                    .pseudo_inverse(1e-12)
+                    .map(|ata_inv| ata_inv * atw)
+                    .unwrap_or_default();
+                (w.iter()
+                    .zip(xy)
+                    .map(|(w, (x, y))| (w - c[0] - c[1] * x - c[2] * y).powi(2))
+                    .sum::<f64>()
+                    / n)
+                    .sqrt()
+            }
+        }
+    }
+}
+
+/// Segment pairs of the differential segment piston
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PistonPairs {
+    /// All the 21 segment pairs
+    #[default]
+    All,
+    /// The 12 pairs of adjacent segments
+    NearestNeighbours,
+    /// The 6 outer segments relative to the center segment
+    CenterRelative,
+}
+impl PistonPairs {
+    /// Returns the segment pairs `(i,j)` with segment ids in `[1,...,7]`
+    pub fn pairs(&self) -> Vec<(usize, usize)> {
+        match self {
+            PistonPairs::All => (1..7)
+                .flat_map(|i| (i + 1..=7).map(move |j| (i, j)))
+                .collect(),
+            PistonPairs::NearestNeighbours => (1..=6)
+                .map(|i| (i, i % 6 + 1))
+                .chain((1..=6).map(|i| (i, 7)))
+                .collect(),
+            PistonPairs::CenterRelative => (1..=6).map(|i| (7, i)).collect(),
+        }
+    }
+}
+
 /// LOM builder
 #[derive(Default)]
 pub struct LOMBuilder {
@@ -16,6 +92,13 @@ pub struct LOMBuilder {
     rbm: Option<RigidBodyMotions>,
 }
 impl LOMBuilder {
+    /// Sets the optical sensitivities
+    pub fn optical_sensitivities(self, sens: OpticalSensitivities) -> Self {
+        Self {
+            sens: Some(sens),
+            ..self
+        }
+    }
     /// Sets the [bincode] loader for a [Vec] of [OpticalSensitivity]
     pub fn load_optical_sensitivities(
         self,
@@ -50,6 +133,18 @@ impl LOMBuilder {
             ..self
         })
     }
+    /// Sets [RigidBodyMotions] from a [parquet](https://docs.rs/parquet) file loaded with the given [TableOptions](crate::TableOptions)
+    #[cfg(feature = "apache")]
+    pub fn parquet_rigid_body_motions<P: AsRef<std::path::Path>>(
+        self,
+        path: P,
+        options: &crate::TableOptions,
+        m1_rbm_label: Option<&str>,
+        m2_rbm_label: Option<&str>,
+    ) -> Result<Self> {
+        let table = crate::Table::from_parquet_with(path, options)?;
+        self.table_rigid_body_motions(&table, m1_rbm_label, m2_rbm_label)
+    }
     #[cfg(feature = "apache")]
     pub fn rigid_body_motions_record(
         self,
@@ -89,7 +184,10 @@ impl LOMBuilder {
     /// Creates a [LOM]
     pub fn build(self) -> Result<LOM> {
         Ok(LOM {
-            sens: self.sens.unwrap_or(Loader::default().load()?),
+            sens: match self.sens {
+                Some(sens) => sens,
+                None => Loader::default().load()?,
+            },
             rbm: self.rbm.unwrap_or_default(),
         })
     }
@@ -111,7 +209,7 @@ impl<'a> TryFrom<&'a [u8]> for LOM {
 
     fn try_from(bytes: &'a [u8]) -> std::result::Result<Self, Self::Error> {
         Ok(Self {
-            sens: bincode::deserialize(bytes)?,
+            sens: OpticalSensitivities::from_bytes(bytes)?,
             rbm: Default::default(),
         })
     }
@@ -127,6 +225,10 @@ impl LOM {
     pub fn builder() -> LOMBuilder {
         Default::default()
     }
+    /// Returns a reference to the optical sensitivities
+    pub fn sensitivities(&self) -> &OpticalSensitivities {
+        &self.sens
+    }
     /// Returns the number of rigid body motions sample `n`
     pub fn len(&self) -> usize {
         self.rbm.len()
@@ -134,6 +236,10 @@ impl LOM {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+    /// Returns the rigid body motions sampling frequency `[Hz]` if known
+    pub fn sampling_frequency(&self) -> Option<f64> {
+        self.rbm.sampling_frequency()
+    }
     /// Returns a the time vector
     pub fn time(&self) -> Vec<f64> {
         self.rbm.time()
@@ -142,117 +248,558 @@ impl LOM {
     ///
     /// The tip-tilt vector is given as `[x1,y1,...,xi,yi,...,xn,yn]` where i is the time index
     pub fn tiptilt(&self) -> TipTilt {
-        TipTilt(self.sens[OpticalSensitivity::<84>::TipTilt(vec![])].into_optics(self.rbm.data()))
+        self.try_tiptilt().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the pupil average tip and tilt in `[rd]` or an error if the sensitivity is missing
+    pub fn try_tiptilt(&self) -> Result<TipTilt> {
+        self.try_optics(OpticalSensitivity::TipTilt(vec![]))
+            .map(TipTilt)
     }
     pub fn tiptilt_mas(&self) -> TipTilt {
-        TipTilt(
-            self.sens[OpticalSensitivity::<84>::TipTilt(vec![])]
-                .into_optics(self.rbm.data())
-                .into_iter()
-                .map(|x| x.to_mas())
-                .collect::<Vec<f64>>(),
-        )
+        self.try_tiptilt_mas().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the pupil average tip and tilt in `[mas]` or an error if the sensitivity is missing
+    pub fn try_tiptilt_mas(&self) -> Result<TipTilt> {
+        self.try_optics(OpticalSensitivity::TipTilt(vec![]))
+            .map(|tt| TipTilt(tt.into_iter().map(|x| x.to_mas()).collect()))
     }
     /// Returns the segment piston in the telescope exit pupil in `[m]`
     ///
     /// The segment piston vector is given as `[p11,p21,...,p71,...,p1i,p2i,...,p7i,...,p1n,p2n,...,p7n]` where i is the time index
     pub fn segment_piston(&self) -> SegmentPiston {
-        SegmentPiston(
-            self.sens[OpticalSensitivity::<84>::SegmentPiston(vec![])].into_optics(self.rbm.data()),
-        )
+        self.try_segment_piston().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the segment piston in `[m]` or an error if the sensitivity is missing
+    pub fn try_segment_piston(&self) -> Result<SegmentPiston> {
+        self.try_optics(OpticalSensitivity::SegmentPiston(vec![]))
+            .map(SegmentPiston)
+    }
+    /// Returns the differential segment piston in the telescope exit pupil in `[m]`
+    ///
+    /// The differential piston vector is given as `[d11,d21,...,dp1,...,d1i,d2i,...,dpi,...,d1n,d2n,...,dpn]`
+    /// where i is the time index and p is the number of segment [pairs](PistonPairs)
+    pub fn differential_segment_piston(&self, pairs: PistonPairs) -> DifferentialSegmentPiston {
+        self.try_differential_segment_piston(pairs)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the differential segment piston in `[m]` or an error if the sensitivity is missing
+    pub fn try_differential_segment_piston(
+        &self,
+        pairs: PistonPairs,
+    ) -> Result<DifferentialSegmentPiston> {
+        self.differential_segment_piston_of(pairs, &self.rbm)
+    }
+    /// Returns the differential segment piston in `[m]` for the rigid body motions `rbm`
+    pub(crate) fn differential_segment_piston_of(
+        &self,
+        pairs: PistonPairs,
+        rbm: &RigidBodyMotions,
+    ) -> Result<DifferentialSegmentPiston> {
+        let pairs = pairs.pairs();
+        let data = self
+            .optics_of(OpticalSensitivity::SegmentPiston(vec![]), rbm)?
+            .chunks(7)
+            .flat_map(|p| {
+                pairs
+                    .iter()
+                    .map(|(i, j)| p[j - 1] - p[i - 1])
+                    .collect::<Vec<f64>>()
+            })
+            .collect();
+        Ok(DifferentialSegmentPiston { data, pairs })
     }
     /// Returns the segment averaged tip and tilt in the telescope exit pupil in `[rd]`
     ///
     /// The segment tip-tilt vector is given as `[x11,x21,...,x71,y11,y21,...,y71,...,x1i,x2i,...,x7i,y1i,y2i,...,y7i,...,x1n,x2n,...,x7n,y1n,y2n,...,y7n]` where i is the time index
     pub fn segment_tiptilt(&self) -> SegmentTipTilt {
-        SegmentTipTilt(
-            self.sens[OpticalSensitivity::<84>::SegmentTipTilt(vec![])]
-                .into_optics(self.rbm.data()),
-        )
+        self.try_segment_tiptilt().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the segment tip and tilt in `[rd]` or an error if the sensitivity is missing
+    pub fn try_segment_tiptilt(&self) -> Result<SegmentTipTilt> {
+        self.try_optics(OpticalSensitivity::SegmentTipTilt(vec![]))
+            .map(SegmentTipTilt)
     }
     pub fn segment_tiptilt_mas(&self) -> SegmentTipTilt {
-        SegmentTipTilt(
-            self.sens[OpticalSensitivity::<84>::SegmentTipTilt(vec![])]
-                .into_optics(self.rbm.data())
-                .into_iter()
-                .map(|x| x.to_mas())
-                .collect::<Vec<f64>>(),
-        )
+        self.try_segment_tiptilt_mas()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the segment tip and tilt in `[mas]` or an error if the sensitivity is missing
+    pub fn try_segment_tiptilt_mas(&self) -> Result<SegmentTipTilt> {
+        self.try_optics(OpticalSensitivity::SegmentTipTilt(vec![]))
+            .map(|stt| SegmentTipTilt(stt.into_iter().map(|x| x.to_mas()).collect()))
     }
     /// Returns the wavefront within the exit pupil in `[m]`
     pub fn masked_wavefront(&self) -> Vec<f64> {
-        self.sens[OpticalSensitivity::<84>::Wavefront(vec![])].into_optics(self.rbm.data())
+        self.try_masked_wavefront()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront within the exit pupil in `[m]` or an error if the sensitivity is missing
+    pub fn try_masked_wavefront(&self) -> Result<Vec<f64>> {
+        self.sens.try_masked_wavefront(self.rbm.data())
     }
     /// Returns the wavefront of each segment within the exit pupil in `[m]`
+    ///
+    /// The wavefront of a segment is given as `[w1,...,wi,...,wn]` where `wi` is the segment wavefront at time index i
     pub fn segment_wavefront(&self) -> Vec<Vec<f64>> {
-        let mut wavefront = self.sens[OpticalSensitivity::<84>::Wavefront(vec![])]
-            .into_optics(self.rbm.data())
-            .into_iter();
-        if let OpticalSensitivity::SegmentMask(mask) =
-            &self.sens[OpticalSensitivity::<84>::SegmentMask(vec![])]
-        {
-            (1..=7)
-                .map(|sid| {
-                    wavefront
-                        .by_ref()
-                        .zip(mask)
-                        .filter_map(|(w, m)| (*m == sid).then_some(w))
-                        .collect::<Vec<f64>>()
-                })
-                .collect()
-        } else {
-            panic!("`SegmentMask` is missing from `OpticalSensitivities`")
+        self.try_segment_wavefront()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront of each segment within the exit pupil in `[m]`
+    ///
+    /// Fails if either the `Wavefront` or the `SegmentMask` sensitivities are missing
+    /// or if the segment mask does not match the wavefront
+    pub fn try_segment_wavefront(&self) -> Result<Vec<Vec<f64>>> {
+        let wavefront = self.try_masked_wavefront()?;
+        let mask = self.segment_mask(&wavefront, self.len())?;
+        Ok((1..=7)
+            .map(|sid| {
+                wavefront
+                    .chunks(mask.len())
+                    .flat_map(|wavefront| {
+                        wavefront
+                            .iter()
+                            .zip(mask)
+                            .filter_map(move |(w, m)| (*m == sid).then_some(*w))
+                    })
+                    .collect::<Vec<f64>>()
+            })
+            .collect())
+    }
+    /// Returns the segment mask, checking that it matches the wavefront of `n_sample` time steps
+    fn segment_mask(&self, wavefront: &[f64], n_sample: usize) -> Result<&[i32]> {
+        let mask = self.sens.segment_mask()?;
+        if mask.is_empty() {
+            return Err(LinearOpticalModelError::EmptyPupil);
+        }
+        let n_pixel = wavefront.len() / n_sample.max(1);
+        if !wavefront.is_empty() && mask.len() != n_pixel {
+            return Err(LinearOpticalModelError::PupilPixels {
+                name: "SegmentMask".to_string(),
+                expected: n_pixel,
+                found: mask.len(),
+            });
         }
+        Ok(mask)
     }
     /// Returns the WFE RMS of each segment within the exit pupil in `[m]`
+    ///
+    /// The WFE RMS vector is given as `[s11,s21,...,s71,...,s1i,s2i,...,s7i,...,s1n,s2n,...,s7n]` where i is the time index
+    /// and the WFE RMS is scaled by `10^-E`
+    ///
+    /// The [SegmentWfeRms] metric is given by [segment_residual_wfe_rms](LOM::segment_residual_wfe_rms)
+    /// with [WavefrontResidual::Full]
     pub fn segment_wfe_rms<const E: i32>(&self) -> Vec<f64> {
-        let wavefront = self.masked_wavefront();
-        let wavefront_iter = wavefront.as_slice();
-        if let OpticalSensitivity::SegmentMask(mask) =
-            &self.sens[OpticalSensitivity::<84>::SegmentMask(vec![])]
-        {
-            (1..=7)
-                .map(|sid| {
-                    let (i, s) = wavefront_iter
-                        .iter()
-                        .zip(mask)
-                        .filter_map(|(w, m)| (*m == sid).then_some(w))
-                        .enumerate()
-                        .fold((0usize, 0f64), |(_, mut s), (i, w)| {
-                            s += w * w;
-                            (i, s)
-                        });
-                    let n = (i + 1) as f64;
-                    let mut wfe_rms = (s / n).sqrt();
-                    if E != 0 {
-                        wfe_rms *= 10f64.powi(-E);
-                    }
-                    wfe_rms
+        self.try_segment_wfe_rms::<E>()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the WFE RMS of each segment within the exit pupil in `[m]`
+    ///
+    /// Fails if either the `Wavefront` or the `SegmentMask` sensitivities are missing
+    pub fn try_segment_wfe_rms<const E: i32>(&self) -> Result<Vec<f64>> {
+        self.try_segment_residual_wfe_rms::<E>(WavefrontResidual::Full)
+            .map(Vec::from)
+    }
+    /// Returns the WFE RMS of each segment after removing the segment piston or the segment piston and tip-tilt
+    pub fn segment_residual_wfe_rms<const E: i32>(
+        &self,
+        residual: WavefrontResidual,
+    ) -> SegmentWfeRms {
+        self.try_segment_residual_wfe_rms::<E>(residual)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the WFE RMS of each segment after removing the segment piston or the segment piston and tip-tilt
+    ///
+    /// Fails if either the `Wavefront`, the `SegmentMask` or the `PupilMask` sensitivities are missing
+    /// or if the segment mask does not match the wavefront
+    pub fn try_segment_residual_wfe_rms<const E: i32>(
+        &self,
+        residual: WavefrontResidual,
+    ) -> Result<SegmentWfeRms> {
+        self.segment_residual_wfe_rms_of::<E>(residual, &self.rbm)
+    }
+    /// Returns the residual WFE RMS of each segment for the rigid body motions `rbm`
+    pub(crate) fn segment_residual_wfe_rms_of<const E: i32>(
+        &self,
+        residual: WavefrontResidual,
+        rbm: &RigidBodyMotions,
+    ) -> Result<SegmentWfeRms> {
+        let xy = match residual {
+            WavefrontResidual::TipTiltRemoved => self.sens.masked_pixel_coordinates()?,
+            _ => vec![],
+        };
+        let wavefront = self.sens.try_masked_wavefront(rbm.data())?;
+        let mask = self.segment_mask(&wavefront, rbm.len())?;
+        Ok(SegmentWfeRms(
+            wavefront
+                .chunks(mask.len())
+                .flat_map(|wavefront| {
+                    (1..=7)
+                        .map(|sid| {
+                            let (w, xy): (Vec<f64>, Vec<(f64, f64)>) = wavefront
+                                .iter()
+                                .zip(mask)
+                                .enumerate()
+                                .filter(|(_, (_, m))| **m == sid)
+                                .map(|(k, (w, _))| (*w, xy.get(k).cloned().unwrap_or_default()))
+                                .unzip();
+                            residual.rms(&w, &xy) * 10f64.powi(-E)
+                        })
+                        .collect::<Vec<f64>>()
                 })
-                .collect()
-        } else {
-            panic!("`SegmentMask` is missing from `OpticalSensitivities`")
-        }
+                .collect(),
+        ))
+    }
+    /// Returns the WFE RMS within the exit pupil in `[m]`
+    ///
+    /// The WFE RMS vector is given as `[w1,...,wi,...,wn]` where i is the time index
+    /// and the WFE RMS is scaled by `10^-E`
+    pub fn wfe_rms<const E: i32>(&self) -> WfeRms {
+        self.try_wfe_rms::<E>().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the WFE RMS within the exit pupil in `[m]`
+    ///
+    /// Fails if the `Wavefront` sensitivity is missing
+    pub fn try_wfe_rms<const E: i32>(&self) -> Result<WfeRms> {
+        self.try_residual_wfe_rms::<E>(WavefrontResidual::Full)
+    }
+    /// Returns the WFE RMS within the exit pupil after removing the piston or the piston and tip-tilt
+    pub fn residual_wfe_rms<const E: i32>(&self, residual: WavefrontResidual) -> WfeRms {
+        self.try_residual_wfe_rms::<E>(residual)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the WFE RMS within the exit pupil after removing the piston or the piston and tip-tilt
+    ///
+    /// Fails if either the `Wavefront` or the `PupilMask` sensitivities are missing
+    pub fn try_residual_wfe_rms<const E: i32>(
+        &self,
+        residual: WavefrontResidual,
+    ) -> Result<WfeRms> {
+        self.residual_wfe_rms_of::<E>(residual, &self.rbm)
+    }
+    /// Returns the residual WFE RMS within the exit pupil for the rigid body motions `rbm`
+    pub(crate) fn residual_wfe_rms_of<const E: i32>(
+        &self,
+        residual: WavefrontResidual,
+        rbm: &RigidBodyMotions,
+    ) -> Result<WfeRms> {
+        let xy = match residual {
+            WavefrontResidual::TipTiltRemoved => self.sens.masked_pixel_coordinates()?,
+            _ => vec![],
+        };
+        let wavefront = self.sens.try_masked_wavefront(rbm.data())?;
+        let n = wavefront.len() / rbm.len().max(1);
+        Ok(WfeRms(
+            wavefront
+                .chunks(n.max(1))
+                .map(|w| residual.rms(w, &xy) * 10f64.powi(-E))
+                .collect(),
+        ))
     }
     /// Returns the wavefront in the exit pupil in `[rmm]`
+    ///
+    /// The wavefront vector is given as `[w1,...,wi,...,wn]` where `wi` is the pupil map at time index i
     pub fn wavefront(&self) -> Vec<f64> {
-        let mut wavefront = self.sens[OpticalSensitivity::<84>::Wavefront(vec![])]
-            .into_optics(self.rbm.data())
-            .into_iter();
-        if let OpticalSensitivity::PupilMask(mask) =
-            &self.sens[OpticalSensitivity::<84>::PupilMask(vec![])]
-        {
-            mask.into_iter()
-                .map(|&mask| {
-                    if mask {
-                        wavefront.next().unwrap()
-                    } else {
-                        0f64
+        self.try_wavefront().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront in the exit pupil in `[rmm]`
+    ///
+    /// Fails if either the `Wavefront` or the `PupilMask` sensitivities are missing
+    pub fn try_wavefront(&self) -> Result<Vec<f64>> {
+        self.sens.try_wavefront(self.rbm.data())
+    }
+    /// Returns the wavefront in the exit pupil in `[rmm]`, one pupil map per time step
+    pub fn wavefront_cube(&self) -> Vec<Vec<f64>> {
+        self.try_wavefront_cube().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront in the exit pupil in `[rmm]`, one pupil map per time step
+    ///
+    /// Fails if either the `Wavefront` or the `PupilMask` sensitivities are missing or if the pupil mask is empty
+    pub fn try_wavefront_cube(&self) -> Result<Vec<Vec<f64>>> {
+        self.sens.try_wavefront_cube(self.rbm.data())
+    }
+    /// Returns an iterator over the wavefront pupil maps in `[rmm]`, one per time step
+    pub fn wavefront_iter(&self) -> impl Iterator<Item = Vec<f64>> + '_ {
+        self.try_wavefront_iter().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns an iterator over the wavefront pupil maps in `[rmm]`, one per time step
+    ///
+    /// Fails if either the `Wavefront` or the `PupilMask` sensitivities are missing
+    pub fn try_wavefront_iter(&self) -> Result<impl Iterator<Item = Vec<f64>> + '_> {
+        self.sens.try_wavefront_iter(self.rbm.data())
+    }
+    /// Returns the wavefront in the exit pupil in `[m]` as `[side,side]` maps, one per time step
+    ///
+    /// The maps are shaped according to the [PupilGeometry](crate::PupilGeometry) with the x axis along the rows
+    pub fn wavefront_maps(&self) -> Vec<nalgebra::DMatrix<f64>> {
+        self.try_wavefront_maps().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront in the exit pupil in `[m]` as `[side,side]` maps, one per time step
+    ///
+    /// Fails if either the `Wavefront` or the `PupilMask` sensitivities are missing
+    pub fn try_wavefront_maps(&self) -> Result<Vec<nalgebra::DMatrix<f64>>> {
+        let pupil = self.sens.pupil_geometry()?;
+        Ok(self
+            .try_wavefront_iter()?
+            .map(|map| pupil.to_map(&map))
+            .collect())
+    }
+    /// Returns the `(x,y)` coordinates `[m]` of the pupil pixels as a pair of `[side,side]` maps
+    ///
+    /// The maps have the same shape than the [wavefront maps](LOM::wavefront_maps)
+    pub fn pupil_coordinates(&self) -> (nalgebra::DMatrix<f64>, nalgebra::DMatrix<f64>) {
+        self.try_pupil_coordinates()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the `(x,y)` coordinates `[m]` of the pupil pixels as a pair of `[side,side]` maps
+    ///
+    /// Fails if the `PupilMask` sensitivity is missing
+    pub fn try_pupil_coordinates(
+        &self,
+    ) -> Result<(nalgebra::DMatrix<f64>, nalgebra::DMatrix<f64>)> {
+        let pupil = self.sens.pupil_geometry()?;
+        let (x, y): (Vec<f64>, Vec<f64>) = pupil.coordinates().into_iter().unzip();
+        Ok((pupil.to_map(&x), pupil.to_map(&y)))
+    }
+    /// Returns the one-sided power spectral densities of the optical `metric` in `[unit^2/Hz]`
+    ///
+    /// The spectral densities are estimated at the rigid body motions sampling frequency unless
+    /// `sampling_frequency` `[Hz]` is given; fails if neither is known.
+    /// Optionally, the spectral densities are evaluated on the last `n_sample`
+    #[cfg(feature = "welch-sde")]
+    pub fn metric_psd<T: crate::Spectral>(
+        &self,
+        metric: &T,
+        sampling_frequency: Option<f64>,
+        n_sample: Option<usize>,
+    ) -> Result<crate::OpticalPsd> {
+        let sampling_frequency = sampling_frequency
+            .or(self.sampling_frequency())
+            .ok_or(LinearOpticalModelError::MissingSamplingFrequency)?;
+        Ok(metric.psd(sampling_frequency, n_sample))
+    }
+    /// Applies the sensitivity of the same variant than `index` to the rigid body motions
+    fn try_optics(&self, index: OpticalSensitivity) -> Result<Vec<f64>> {
+        self.optics_of(index, &self.rbm)
+    }
+    /// Applies the sensitivity of the same variant than `index` to the rigid body motions `rbm`
+    pub(crate) fn optics_of(
+        &self,
+        index: OpticalSensitivity,
+        rbm: &RigidBodyMotions,
+    ) -> Result<Vec<f64>> {
+        Ok(self.sens.try_get(index)?.into_optics(rbm.data()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wavefront_cube() {
+        let mut rbm = nalgebra::DMatrix::<f64>::zeros(84, 2);
+        rbm[(2, 0)] = 1e-6;
+        rbm[(42 + 6 + 3, 1)] = 1e-6;
+        let lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .map(|lom| LOM {
+                rbm: rbm.clone().into(),
+                ..lom
+            })
+            .unwrap();
+        let cube = lom.wavefront_cube();
+        assert_eq!(cube.len(), 2);
+        assert_eq!(cube.concat(), lom.wavefront());
+        for (k, (map, iter_map)) in cube.iter().zip(lom.wavefront_iter()).enumerate() {
+            let single = LOM {
+                rbm: rbm.columns(k, 1).into_owned().into(),
+                ..lom.clone()
+            };
+            assert_eq!(*map, single.wavefront());
+            assert_eq!(map.len(), 64);
+            let err = map
+                .iter()
+                .zip(&iter_map)
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>();
+            assert!(err < 1e-15);
+        }
+        assert!(cube[0] != cube[1]);
+    }
+
+    #[test]
+    fn wavefront_maps() {
+        let rbm = nalgebra::DMatrix::<f64>::from_fn(84, 2, |i, j| 1e-6 * ((i + j) % 3) as f64);
+        let mut lom = LOM::builder()
+            .optical_sensitivities(
+                OpticalSensitivities::synthetic()
+                    .with_pupil_geometry(crate::PupilGeometry::new(8, 0.5)),
+            )
+            .build()
+            .unwrap();
+        lom.rbm = rbm.into();
+        let maps = lom.wavefront_maps();
+        assert_eq!(maps.len(), 2);
+        assert_eq!(maps[1].shape(), (8, 8));
+        assert!(maps[1]
+            .iter()
+            .zip(&lom.wavefront_cube()[1])
+            .all(|(a, b)| (a - b).abs() < 1e-15));
+        let (x, y) = lom.pupil_coordinates();
+        assert_eq!((x[(0, 7)], y[(0, 7)]), (-1.75, 1.75));
+        assert_eq!(x[(7, 0)], 1.75);
+
+        lom.sens = lom.sens.clone().with_pupil_geometry(
+            crate::PupilGeometry::new(8, 0.5).orientation(crate::PupilOrientation::YFastest),
+        );
+        assert_eq!(lom.wavefront_maps()[1], maps[1].transpose());
+        assert_eq!(lom.pupil_coordinates().0, y.transpose());
+
+        lom.sens = lom
+            .sens
+            .clone()
+            .with_pupil_geometry(crate::PupilGeometry::new(7, 0.5));
+        assert!(matches!(
+            lom.try_wavefront_maps(),
+            Err(LinearOpticalModelError::PupilGeometry { side: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn non_square_pupil() {
+        let sens: OpticalSensitivities = OpticalSensitivities::synthetic()
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::PupilMask(mask) => {
+                    OpticalSensitivity::PupilMask(mask[..mask.len() - 1].to_vec())
+                }
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        let lom = LOM::builder().optical_sensitivities(sens).build().unwrap();
+        assert!(lom.try_segment_wfe_rms::<0>().is_ok());
+        assert!(matches!(
+            lom.try_segment_residual_wfe_rms::<0>(WavefrontResidual::TipTiltRemoved),
+            Err(LinearOpticalModelError::PupilGeometry { n_pixel: 63, .. })
+        ));
+    }
+
+    #[test]
+    fn wfe_rms_time_series() {
+        let mut rbm = nalgebra::DMatrix::<f64>::zeros(84, 2);
+        rbm[(2, 0)] = 1e-6;
+        rbm[(6 + 3, 1)] = 1e-6;
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = rbm.into();
+        let segment_wavefront = lom.segment_wavefront();
+        assert_eq!(
+            segment_wavefront.iter().map(|w| w.len()).sum::<usize>(),
+            2 * 60
+        );
+        let swfe = lom.segment_wfe_rms::<-6>();
+        assert_eq!(swfe.len(), 14);
+        assert!((swfe[0] - 2f64).abs() < 1e-9);
+        assert!(swfe.iter().skip(1).take(6).all(|x| *x == 0f64));
+        assert!(swfe[8] > 0f64);
+        let swfe = lom.segment_residual_wfe_rms::<-6>(WavefrontResidual::PistonRemoved);
+        assert!(swfe[0] < 1e-9);
+        let swfe = lom.segment_residual_wfe_rms::<-6>(WavefrontResidual::TipTiltRemoved);
+        assert!(swfe.iter().all(|x| *x < 1e-9));
+        let wfe = lom.wfe_rms::<-6>();
+        assert_eq!(wfe.len(), 2);
+        assert!(lom.residual_wfe_rms::<-6>(WavefrontResidual::TipTiltRemoved)[0] > 0f64);
+    }
+
+    #[test]
+    fn differential_segment_piston() {
+        use crate::{OpticalMetrics, Stats};
+        let mut rbm = nalgebra::DMatrix::<f64>::zeros(84, 2);
+        for i in 0..7 {
+            rbm[(i * 6 + 2, 1)] = (i + 1) as f64 * 1e-9;
+        }
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = rbm.into();
+        let dp = lom.differential_segment_piston(PistonPairs::All);
+        assert_eq!(dp.n_item(), 21);
+        assert_eq!(dp.len(), 42);
+        assert!(dp.iter().take(21).all(|x| *x == 0f64));
+        assert!((dp[21] - 2e-9).abs() < 1e-18);
+        let dp = lom.differential_segment_piston(PistonPairs::NearestNeighbours);
+        assert_eq!(dp.n_item(), 12);
+        assert_eq!(dp.pairs()[5], (6, 1));
+        let dp = lom.differential_segment_piston(PistonPairs::CenterRelative);
+        assert_eq!(dp.n_item(), 6);
+        let std = dp.std(None);
+        assert!((std[0] - 6e-9).abs() < 1e-18);
+    }
+
+    #[test]
+    fn missing_segment_mask() {
+        let sens: OpticalSensitivities = OpticalSensitivities::synthetic()
+            .iter()
+            .filter(|s| **s != OpticalSensitivity::SegmentMask(vec![]))
+            .cloned()
+            .collect::<Vec<_>>()
+            .into();
+        let lom = LOM::builder().optical_sensitivities(sens).build().unwrap();
+        assert!(lom.try_tiptilt().is_ok());
+        assert!(lom.try_wavefront().is_ok());
+        assert!(matches!(
+            lom.try_segment_wfe_rms::<0>(),
+            Err(LinearOpticalModelError::MissingSensitivity(_))
+        ));
+        assert!(matches!(
+            lom.try_segment_wavefront(),
+            Err(LinearOpticalModelError::MissingSensitivity(_))
+        ));
+    }
+
+    #[test]
+    fn segment_mask_length() {
+        let segment_mask = |len: usize| -> OpticalSensitivities {
+            OpticalSensitivities::synthetic()
+                .iter()
+                .map(|s| match s {
+                    OpticalSensitivity::SegmentMask(mask) => {
+                        OpticalSensitivity::SegmentMask(mask[..len].to_vec())
                     }
+                    s => s.clone(),
                 })
-                .collect()
-        } else {
-            panic!("`PupilMask` is missing from `OpticalSensitivities`")
-        }
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let mut lom = LOM::builder()
+            .optical_sensitivities(segment_mask(59))
+            .build()
+            .unwrap();
+        lom.rbm = nalgebra::DMatrix::<f64>::zeros(84, 2).into();
+        assert!(matches!(
+            lom.try_segment_wavefront(),
+            Err(LinearOpticalModelError::PupilPixels {
+                expected: 60,
+                found: 59,
+                ..
+            })
+        ));
+        assert!(lom
+            .try_segment_residual_wfe_rms::<0>(WavefrontResidual::PistonRemoved)
+            .is_err());
+        let mut lom = LOM::builder()
+            .optical_sensitivities(segment_mask(0))
+            .build()
+            .unwrap();
+        lom.rbm = nalgebra::DMatrix::<f64>::zeros(84, 2).into();
+        assert!(matches!(
+            lom.try_segment_wfe_rms::<0>(),
+            Err(LinearOpticalModelError::EmptyPupil)
+        ));
     }
 }
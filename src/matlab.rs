@@ -0,0 +1,483 @@
+//! # MATLAB MAT-file export
+//!
+//! The optical sensitivities, the rigid body motions and the optical metrics are saved in
+//! [MAT-file version 5](https://www.mathworks.com/help/pdf_doc/matlab/matfile_format.pdf) format,
+//! the format of `save -v6`, which is loaded in MATLAB with `load`:
+//!  - each [OpticalSensitivity] is a `[n,N]` matrix named after its variant, e.g. `SegmentTipTilt`,
+//!    that can be used as the output matrix `C` of a state space model, `PupilMask` is a `[n,1]` logical
+//!    and `SegmentMask` a `[n,1]` int32 vector,
+//!  - the [RigidBodyMotions] are a `[84,n_sample]` matrix `rbm` with a `[1,n_sample]` `time` vector,
+//!  - an [optical metric](OpticalMetrics) is a struct with the `[n_item,n_sample]` `data`,
+//!    the `[1,n_sample]` `time`, the `labels` cell array and the `units`.
+//!
+//! # Example
+//! ```no_run
+//! use gmt_lom::{matlab::MatFile, LOM};
+//!
+//! let lom = LOM::builder()
+//!     .parquet_rigid_body_motions("rbm.parquet", &Default::default(), None, None)?
+//!     .build()?;
+//! let time = lom.time();
+//! MatFile::default()
+//!     .sensitivities(lom.sensitivities())
+//!     .rigid_body_motions(&lom.rbm)
+//!     .metric("segment_tiptilt", &lom.segment_tiptilt(), &time)?
+//!     .save("lom.mat")?;
+//! # Ok::<(), gmt_lom::LinearOpticalModelError>(())
+//! ```
+//! ```matlab
+//! load lom.mat
+//! y = SegmentTipTilt*rbm; % equal to segment_tiptilt.data
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::Deref,
+    path::Path,
+};
+
+use crate::{
+    LinearOpticalModelError, OpticalMetrics, OpticalSensitivities, OpticalSensitivity, Result,
+    RigidBodyMotions,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MatError {
+    #[error("failed to write MAT-file")]
+    Io(#[from] io::Error),
+    #[error("{0:?} is not a valid MATLAB name")]
+    Name(String),
+    #[error("expected {expected} values for a {rows}x{cols} array, found {found}")]
+    Shape {
+        rows: usize,
+        cols: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error("optical metric {0:?} has no item")]
+    EmptyMetric(String),
+}
+
+// data types
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+// array classes
+const MX_CELL_CLASS: u32 = 1;
+const MX_STRUCT_CLASS: u32 = 2;
+const MX_CHAR_CLASS: u32 = 4;
+const MX_DOUBLE_CLASS: u32 = 6;
+const MX_UINT8_CLASS: u32 = 9;
+const MX_INT32_CLASS: u32 = 12;
+const LOGICAL_FLAG: u32 = 0x0200;
+
+/// MATLAB array
+#[derive(Debug, Clone)]
+enum MatArray {
+    Double(usize, usize, Vec<f64>),
+    Int32(usize, usize, Vec<i32>),
+    Logical(usize, usize, Vec<bool>),
+    Char(String),
+    Cell(Vec<MatArray>),
+    Struct(Vec<(String, MatArray)>),
+}
+
+/// Appends a data element to `buffer`, the data is padded to a multiple of 8 bytes
+fn element(buffer: &mut Vec<u8>, data_type: u32, data: &[u8]) {
+    buffer.extend((data_type).to_le_bytes());
+    buffer.extend((data.len() as u32).to_le_bytes());
+    buffer.extend(data);
+    buffer.resize(buffer.len().next_multiple_of(8), 0);
+}
+
+fn check_name(name: &str) -> std::result::Result<(), MatError> {
+    let mut chars = name.chars();
+    if name.len() <= 63
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(MatError::Name(name.to_string()))
+    }
+}
+
+impl MatArray {
+    fn check(&self) -> std::result::Result<(), MatError> {
+        let shape = |rows: usize, cols: usize, found: usize| {
+            if rows * cols == found {
+                Ok(())
+            } else {
+                Err(MatError::Shape {
+                    rows,
+                    cols,
+                    expected: rows * cols,
+                    found,
+                })
+            }
+        };
+        match self {
+            MatArray::Double(rows, cols, data) => shape(*rows, *cols, data.len()),
+            MatArray::Int32(rows, cols, data) => shape(*rows, *cols, data.len()),
+            MatArray::Logical(rows, cols, data) => shape(*rows, *cols, data.len()),
+            MatArray::Char(_) => Ok(()),
+            MatArray::Cell(cells) => cells.iter().try_for_each(|cell| cell.check()),
+            MatArray::Struct(fields) => fields.iter().try_for_each(|(name, field)| {
+                check_name(name)?;
+                field.check()
+            }),
+        }
+    }
+    /// Appends the array as a `miMATRIX` data element named `name` to `buffer`
+    fn write(&self, buffer: &mut Vec<u8>, name: &str) {
+        let mut matrix = vec![];
+        let (class, flags, dims) = match self {
+            MatArray::Double(rows, cols, _) => (MX_DOUBLE_CLASS, 0, [*rows, *cols]),
+            MatArray::Int32(rows, cols, _) => (MX_INT32_CLASS, 0, [*rows, *cols]),
+            MatArray::Logical(rows, cols, _) => (MX_UINT8_CLASS, LOGICAL_FLAG, [*rows, *cols]),
+            MatArray::Char(text) => (MX_CHAR_CLASS, 0, [1, text.encode_utf16().count()]),
+            MatArray::Cell(cells) => (MX_CELL_CLASS, 0, [cells.len(), 1]),
+            MatArray::Struct(_) => (MX_STRUCT_CLASS, 0, [1, 1]),
+        };
+        element(
+            &mut matrix,
+            MI_UINT32,
+            &[(class | flags).to_le_bytes(), 0u32.to_le_bytes()].concat(),
+        );
+        element(
+            &mut matrix,
+            MI_INT32,
+            &dims
+                .iter()
+                .flat_map(|&d| (d as i32).to_le_bytes())
+                .collect::<Vec<_>>(),
+        );
+        element(&mut matrix, MI_INT8, name.as_bytes());
+        match self {
+            MatArray::Double(_, _, data) => element(
+                &mut matrix,
+                MI_DOUBLE,
+                &data
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+            MatArray::Int32(_, _, data) => element(
+                &mut matrix,
+                MI_INT32,
+                &data
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+            MatArray::Logical(_, _, data) => element(
+                &mut matrix,
+                MI_UINT8,
+                &data.iter().map(|&x| x as u8).collect::<Vec<_>>(),
+            ),
+            MatArray::Char(text) => element(
+                &mut matrix,
+                MI_UINT16,
+                &text
+                    .encode_utf16()
+                    .flat_map(|c| c.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+            MatArray::Cell(cells) => cells.iter().for_each(|cell| cell.write(&mut matrix, "")),
+            MatArray::Struct(fields) => {
+                let length = fields
+                    .iter()
+                    .map(|(name, _)| name.len() + 1)
+                    .max()
+                    .unwrap_or(1);
+                // field name length as a small data element
+                matrix.extend((4 << 16 | MI_INT32).to_le_bytes());
+                matrix.extend((length as i32).to_le_bytes());
+                let mut names = vec![0u8; length * fields.len()];
+                for (chunk, (name, _)) in names.chunks_mut(length).zip(fields) {
+                    chunk[..name.len()].copy_from_slice(name.as_bytes());
+                }
+                element(&mut matrix, MI_INT8, &names);
+                fields
+                    .iter()
+                    .for_each(|(_, field)| field.write(&mut matrix, ""));
+            }
+        }
+        element(buffer, MI_MATRIX, &matrix);
+    }
+}
+
+/// MATLAB MAT-file (version 5) writer
+///
+/// The variables are added with the builder methods and written to the file with [MatFile::save]
+#[derive(Debug, Clone, Default)]
+pub struct MatFile {
+    variables: Vec<(String, MatArray)>,
+}
+impl MatFile {
+    /// Adds a `[rows,cols]` matrix from the `data` in column major order
+    pub fn matrix(mut self, name: &str, rows: usize, cols: usize, data: &[f64]) -> Self {
+        self.variables.push((
+            name.to_string(),
+            MatArray::Double(rows, cols, data.to_vec()),
+        ));
+        self
+    }
+    /// Adds a scalar
+    pub fn scalar(self, name: &str, value: f64) -> Self {
+        self.matrix(name, 1, 1, &[value])
+    }
+    /// Adds the optical sensitivities, each one named after its [variant](OpticalSensitivity)
+    ///
+    /// The sensitivity matrices are `[n,N]`, the pupil mask is a `[n,1]` logical vector
+    /// and the segment mask is a `[n,1]` int32 vector
+    pub fn sensitivities<const N: usize>(
+        mut self,
+        sensitivities: &OpticalSensitivities<N>,
+    ) -> Self {
+        for sens in sensitivities.iter() {
+            let array = match sens {
+                OpticalSensitivity::SegmentMask(mask) => {
+                    MatArray::Int32(mask.len(), 1, mask.clone())
+                }
+                OpticalSensitivity::PupilMask(mask) => {
+                    MatArray::Logical(mask.len(), 1, mask.clone())
+                }
+                sens => {
+                    let data: &[f64] = sens.into();
+                    MatArray::Double(data.len() / N, N, data.to_vec())
+                }
+            };
+            self.variables.push((sens.to_string(), array));
+        }
+        self
+    }
+    /// Adds the `[84,n_sample]` rigid body motions `rbm`, the `[1,n_sample]` `time` `[s]`
+    /// and the `sampling_frequency` `[Hz]`, if known
+    pub fn rigid_body_motions(self, rbm: &RigidBodyMotions) -> Self {
+        let data = rbm.data();
+        let time = rbm.time();
+        let this = self
+            .matrix("rbm", data.nrows(), data.ncols(), data.as_slice())
+            .matrix("time", 1, time.len(), &time);
+        match rbm.sampling_frequency() {
+            Some(sampling_frequency) => this.scalar("sampling_frequency", sampling_frequency),
+            None => this,
+        }
+    }
+    /// Adds an optical metric as a struct named `name`
+    ///
+    /// The struct fields are the `[n_item,n_sample]` `data`, the `[1,n_sample]` `time` `[s]`,
+    /// the `[n_item,1]` `labels` cell array and the `units`
+    pub fn metric<T>(mut self, name: &str, metric: &T, time: &[f64]) -> Result<Self>
+    where
+        T: Deref<Target = Vec<f64>> + OpticalMetrics,
+    {
+        let n_item = metric.n_item();
+        if n_item == 0 {
+            return Err(MatError::EmptyMetric(name.to_string()).into());
+        }
+        let n_sample = metric.len() / n_item;
+        if metric.len() != n_item * n_sample {
+            return Err(MatError::Shape {
+                rows: n_item,
+                cols: n_sample,
+                expected: n_item * n_sample,
+                found: metric.len(),
+            }
+            .into());
+        }
+        if time.len() != n_sample {
+            return Err(LinearOpticalModelError::TimeLength {
+                expected: n_sample,
+                found: time.len(),
+            });
+        }
+        let fields = vec![
+            (
+                "data".to_string(),
+                MatArray::Double(n_item, n_sample, metric.to_vec()),
+            ),
+            (
+                "time".to_string(),
+                MatArray::Double(1, n_sample, time.to_vec()),
+            ),
+            (
+                "labels".to_string(),
+                MatArray::Cell(
+                    metric
+                        .item_labels()
+                        .into_iter()
+                        .map(MatArray::Char)
+                        .collect(),
+                ),
+            ),
+            (
+                "units".to_string(),
+                MatArray::Char(metric.units().to_string()),
+            ),
+        ];
+        self.variables
+            .push((name.to_string(), MatArray::Struct(fields)));
+        Ok(self)
+    }
+    /// Writes the variables to a MAT-file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        for (name, array) in &self.variables {
+            check_name(name)?;
+            array.check()?;
+        }
+        let mut buffer = format!(
+            "MATLAB 5.0 MAT-file, Created by: gmt-lom v{}",
+            env!("CARGO_PKG_VERSION")
+        )
+        .into_bytes();
+        buffer.resize(116, b' ');
+        buffer.extend([0u8; 8]);
+        buffer.extend(0x0100u16.to_le_bytes());
+        buffer.extend(b"IM");
+        for (name, array) in &self.variables {
+            array.write(&mut buffer, name);
+        }
+        let mut file = BufWriter::new(File::create(path).map_err(MatError::from)?);
+        file.write_all(&buffer)
+            .and_then(|_| file.flush())
+            .map_err(MatError::from)?;
+        Ok(())
+    }
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Saves the sensitivities to a MAT-file, see [MatFile::sensitivities]
+    pub fn to_mat<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        MatFile::default().sensitivities(self).save(path)
+    }
+}
+
+impl RigidBodyMotions {
+    /// Saves the rigid body motions to a MAT-file, see [MatFile::rigid_body_motions]
+    pub fn to_mat<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        MatFile::default().rigid_body_motions(self).save(path)
+    }
+}
+
+/// Optical metrics export to MAT-files
+pub trait ToMat: Deref<Target = Vec<f64>> + OpticalMetrics + Sized {
+    /// Saves the optical metrics to a MAT-file as a struct named `name`, see [MatFile::metric]
+    fn to_mat<P: AsRef<Path>>(&self, path: P, name: &str, time: &[f64]) -> Result<()> {
+        MatFile::default().metric(name, self, time)?.save(path)
+    }
+}
+impl ToMat for crate::TipTilt {}
+impl ToMat for crate::SegmentTipTilt {}
+impl ToMat for crate::SegmentPiston {}
+impl ToMat for crate::DifferentialSegmentPiston {}
+impl ToMat for crate::SegmentWfeRms {}
+impl ToMat for crate::WfeRms {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LOM;
+    use nalgebra as na;
+
+    // Reads the data elements of a buffer as (data type, data) pairs
+    fn elements(mut buffer: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut elements = vec![];
+        while buffer.len() >= 8 {
+            let data_type = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+            if data_type >> 16 > 0 {
+                // small data element
+                let n = (data_type >> 16) as usize;
+                elements.push((data_type & 0xffff, &buffer[4..4 + n]));
+                buffer = &buffer[8..];
+                continue;
+            }
+            let n = u32::from_le_bytes(buffer[4..8].try_into().unwrap()) as usize;
+            elements.push((data_type, &buffer[8..8 + n]));
+            buffer = &buffer[(8 + n).next_multiple_of(8)..];
+        }
+        elements
+    }
+
+    #[test]
+    fn mat_file() {
+        let sens = OpticalSensitivities::synthetic();
+        let mut lom = LOM::builder()
+            .optical_sensitivities(sens.clone())
+            .build()
+            .unwrap();
+        lom.rbm = na::DMatrix::<f64>::from_fn(84, 4, |i, j| (i * 10 + j) as f64).into();
+        let time = lom.time();
+        let path = std::env::temp_dir().join(format!("gmt-lom_{}.mat", std::process::id()));
+        MatFile::default()
+            .sensitivities(&sens)
+            .rigid_body_motions(&lom.rbm)
+            .metric("tiptilt", &lom.tiptilt(), &time)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let buffer = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(buffer.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(&buffer[124..128], &[0, 1, b'I', b'M']);
+        let variables = elements(&buffer[128..]);
+        assert!(variables
+            .iter()
+            .all(|(data_type, _)| *data_type == MI_MATRIX));
+        let names: Vec<_> = variables
+            .iter()
+            .map(|(_, matrix)| std::str::from_utf8(elements(matrix)[2].1).unwrap())
+            .collect();
+        let mut expected: Vec<_> = sens.iter().map(|s| s.to_string()).collect();
+        expected.extend(["rbm", "time", "tiptilt"].map(String::from));
+        assert_eq!(names, expected);
+
+        let rbm = elements(variables[sens.len()].1);
+        assert_eq!(rbm[0].1[0] as u32, MX_DOUBLE_CLASS);
+        assert_eq!(rbm[1].1, [84i32, 4].map(i32::to_le_bytes).concat());
+        let data: Vec<f64> = rbm[3]
+            .1
+            .chunks(8)
+            .map(|x| f64::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_eq!(data, lom.rbm.data().as_slice());
+
+        let tiptilt = elements(variables.last().unwrap().1);
+        assert_eq!(tiptilt[0].1[0] as u32, MX_STRUCT_CLASS);
+        assert_eq!(tiptilt[3], (MI_INT32, &7i32.to_le_bytes()[..]));
+        assert_eq!(&tiptilt[4].1[..5], b"data\0");
+        let labels = elements(tiptilt[7].1);
+        assert_eq!(labels[1].1, [2i32, 1].map(i32::to_le_bytes).concat());
+        let tip: Vec<u16> = elements(labels[3].1)[3]
+            .1
+            .chunks(2)
+            .map(|c| u16::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        assert_eq!(String::from_utf16(&tip).unwrap(), "tip");
+
+        assert!(matches!(
+            MatFile::default().scalar("1x", 0.).save(&path),
+            Err(LinearOpticalModelError::Matlab(MatError::Name(_)))
+        ));
+        assert!(matches!(
+            MatFile::default().matrix("x", 2, 2, &[0.; 3]).save(&path),
+            Err(LinearOpticalModelError::Matlab(MatError::Shape { .. }))
+        ));
+        assert!(lom.tiptilt().to_mat(&path, "tt", &time[1..]).is_err());
+        let empty = crate::DifferentialSegmentPiston {
+            data: vec![],
+            pairs: vec![],
+        };
+        assert!(matches!(
+            MatFile::default().metric("dp", &empty, &[]),
+            Err(LinearOpticalModelError::Matlab(MatError::EmptyMetric(_)))
+        ));
+    }
+}
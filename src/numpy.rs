@@ -0,0 +1,522 @@
+//! # NumPy arrays import and export
+//!
+//! The optical sensitivities, the rigid body motions and the optical metrics are saved as
+//! [NumPy](https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html) `.npy` and `.npz` files.
+//! The matrices are written in Fortran order with their `[rows,columns]` shape,
+//! such as `numpy.load` returns arrays with the same shape than the matrices in Rust:
+//!  - the [OpticalSensitivity] matrices are `[n,N]`,
+//!  - the [RigidBodyMotions] are `[84,n_sample]`,
+//!  - the [optical metrics](OpticalMetrics) are `[n_item,n_sample]`.
+//!
+//! Arrays in either C or Fortran order are accepted at import.
+//!
+//! # Example
+//! ```python
+//! import numpy as np
+//! data = np.load("segment_tiptilt.npz")
+//! time, stt = data["time"], data["data"] # stt.shape = (14, time.size)
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use nalgebra as na;
+use npyz::{
+    npz::NpzArchive, npz::NpzWriter, AutoSerialize, NpyFile, Order, WriteOptions, WriterBuilder,
+};
+
+use crate::{
+    DifferentialSegmentPiston, ImportOptions, OpticalMetrics, OpticalSensitivities,
+    OpticalSensitivity, PistonPairs, Result, RigidBodyMotions, SegmentPiston, SegmentTipTilt,
+    SegmentWfeRms, TipTilt, WfeRms,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum NumpyError {
+    #[error("failed to read or write a NumPy file")]
+    Io(#[from] io::Error),
+    #[error("array {0} not found in npz archive")]
+    MissingArray(String),
+    #[error("expected an array of shape {expected}, found {found:?}")]
+    Shape { expected: String, found: Vec<u64> },
+}
+type NpResult<T> = std::result::Result<T, NumpyError>;
+
+/// Writes `data` in Fortran order with the given `shape`
+fn write_array<T: AutoSerialize + Copy, W: Write>(
+    writer: W,
+    shape: &[u64],
+    data: &[T],
+) -> io::Result<()> {
+    let mut npy = WriteOptions::new()
+        .default_dtype()
+        .shape(shape)
+        .order(Order::Fortran)
+        .writer(writer)
+        .begin_nd()?;
+    npy.extend(data.iter().copied())?;
+    npy.finish()
+}
+
+/// Writes an array to an npz archive
+fn write_npz_array<T: AutoSerialize + Copy, W: Write + Seek>(
+    npz: &mut NpzWriter<W>,
+    name: &str,
+    shape: &[u64],
+    data: &[T],
+) -> io::Result<()> {
+    let mut npy = npz
+        .array::<T>(name, Default::default())?
+        .default_dtype()
+        .shape(shape)
+        .order(Order::Fortran)
+        .begin_nd()?;
+    npy.extend(data.iter().copied())?;
+    npy.finish()
+}
+
+/// Reads an array of at most 2 dimensions, returning its shape and data in Fortran order
+fn read_array<R: Read>(npy: NpyFile<R>) -> NpResult<(Vec<u64>, Vec<f64>)> {
+    let shape = npy.shape().to_vec();
+    let order = npy.order();
+    let data: Vec<f64> = npy.into_vec()?;
+    match (shape.as_slice(), order) {
+        ([rows, cols], Order::C) => {
+            let m = na::DMatrix::from_row_slice(*rows as usize, *cols as usize, &data);
+            Ok((shape, m.as_slice().to_vec()))
+        }
+        ([] | [_] | [_, _], _) => Ok((shape, data)),
+        _ => Err(NumpyError::Shape {
+            expected: "[n] or [n,m]".to_string(),
+            found: shape,
+        }),
+    }
+}
+
+/// Reads a `[n,m]` matrix, `[n]` arrays being read as `[n,1]` matrices
+fn read_matrix<R: Read>(npy: NpyFile<R>) -> NpResult<na::DMatrix<f64>> {
+    let (shape, data) = read_array(npy)?;
+    let (rows, cols) = match shape.as_slice() {
+        [rows, cols] => (*rows as usize, *cols as usize),
+        _ => (data.len(), 1),
+    };
+    Ok(na::DMatrix::from_column_slice(rows, cols, &data))
+}
+
+fn open_npy<P: AsRef<Path>>(path: P) -> NpResult<NpyFile<BufReader<File>>> {
+    Ok(NpyFile::new(BufReader::new(File::open(path)?))?)
+}
+
+fn by_name<'a, R: Read + Seek>(
+    npz: &'a mut NpzArchive<R>,
+    name: &str,
+) -> NpResult<NpyFile<impl Read + 'a>> {
+    npz.by_name(name)?
+        .ok_or_else(|| NumpyError::MissingArray(name.to_string()))
+}
+
+impl<const N: usize> OpticalSensitivity<N> {
+    /// Writes the sensitivity to a `.npy` file
+    ///
+    /// The matrices are `[n,N]` arrays of `float64`, the segment and the pupil masks are 1D arrays of `int32` and `bool`
+    pub fn to_npy<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = BufWriter::new(File::create(path).map_err(NumpyError::from)?);
+        Ok(self.write_npy(file).map_err(NumpyError::from)?)
+    }
+    fn write_npy<W: Write>(&self, writer: W) -> io::Result<()> {
+        match self {
+            OpticalSensitivity::SegmentMask(mask) => {
+                write_array(writer, &[mask.len() as u64], mask)
+            }
+            OpticalSensitivity::PupilMask(mask) => write_array(writer, &[mask.len() as u64], mask),
+            sens => {
+                let data: &[f64] = sens.into();
+                write_array(writer, &[(data.len() / N) as u64, N as u64], data)
+            }
+        }
+    }
+    /// Reads a sensitivity of the same variant than `index` from a `.npy` file
+    pub fn from_npy<P: AsRef<Path>>(index: Self, path: P) -> Result<Self> {
+        Ok(Self::read_npy(index, open_npy(path)?)?)
+    }
+    fn read_npy<R: Read>(index: Self, npy: NpyFile<R>) -> NpResult<Self> {
+        Ok(match index {
+            OpticalSensitivity::SegmentMask(_) => OpticalSensitivity::SegmentMask(npy.into_vec()?),
+            OpticalSensitivity::PupilMask(_) => OpticalSensitivity::PupilMask(npy.into_vec()?),
+            index => {
+                let m = read_matrix(npy)?;
+                let rows = match index {
+                    OpticalSensitivity::TipTilt(_) => Some(2),
+                    OpticalSensitivity::SegmentTipTilt(_) => Some(14),
+                    OpticalSensitivity::SegmentPiston(_) => Some(7),
+                    _ => None,
+                };
+                if m.ncols() != N || rows.is_some_and(|rows| rows != m.nrows()) {
+                    return Err(NumpyError::Shape {
+                        expected: format!(
+                            "[{},{N}]",
+                            rows.map_or("n".to_string(), |r| r.to_string())
+                        ),
+                        found: vec![m.nrows() as u64, m.ncols() as u64],
+                    });
+                }
+                let data = m.as_slice().to_vec();
+                match index {
+                    OpticalSensitivity::Wavefront(_) => OpticalSensitivity::Wavefront(data),
+                    OpticalSensitivity::TipTilt(_) => OpticalSensitivity::TipTilt(data),
+                    OpticalSensitivity::SegmentTipTilt(_) => {
+                        OpticalSensitivity::SegmentTipTilt(data)
+                    }
+                    OpticalSensitivity::SegmentPiston(_) => OpticalSensitivity::SegmentPiston(data),
+                    _ => OpticalSensitivity::Zernike(data),
+                }
+            }
+        })
+    }
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Writes the sensitivities to a `.npz` archive, each sensitivity being named after its variant
+    pub fn to_npz<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut npz = NpzWriter::create(path).map_err(NumpyError::from)?;
+        for sens in self.iter() {
+            let name = sens.to_string();
+            match sens {
+                OpticalSensitivity::SegmentMask(mask) => {
+                    write_npz_array(&mut npz, &name, &[mask.len() as u64], mask)
+                }
+                OpticalSensitivity::PupilMask(mask) => {
+                    write_npz_array(&mut npz, &name, &[mask.len() as u64], mask)
+                }
+                sens => {
+                    let data: &[f64] = sens.into();
+                    write_npz_array(&mut npz, &name, &[(data.len() / N) as u64, N as u64], data)
+                }
+            }
+            .map_err(NumpyError::from)?;
+        }
+        Ok(())
+    }
+    /// Reads the sensitivities from a `.npz` archive
+    pub fn from_npz<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut npz = NpzArchive::open(path).map_err(NumpyError::from)?;
+        let mut sens = vec![];
+        for index in [
+            OpticalSensitivity::<N>::Wavefront(vec![]),
+            OpticalSensitivity::TipTilt(vec![]),
+            OpticalSensitivity::SegmentTipTilt(vec![]),
+            OpticalSensitivity::SegmentPiston(vec![]),
+            OpticalSensitivity::SegmentMask(vec![]),
+            OpticalSensitivity::PupilMask(vec![]),
+            OpticalSensitivity::Zernike(vec![]),
+        ] {
+            if let Some(npy) = npz.by_name(&index.to_string()).map_err(NumpyError::from)? {
+                sens.push(OpticalSensitivity::read_npy(index, npy)?);
+            }
+        }
+        Ok(sens.into())
+    }
+}
+
+impl RigidBodyMotions {
+    /// Writes the `[84,n]` rigid body motions to a `.npy` file
+    pub fn to_npy<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = BufWriter::new(File::create(path).map_err(NumpyError::from)?);
+        let data = self.data();
+        write_array(
+            file,
+            &[data.nrows() as u64, data.ncols() as u64],
+            data.as_slice(),
+        )
+        .map_err(NumpyError::from)?;
+        Ok(())
+    }
+    /// Writes the rigid body motions to a `.npz` archive
+    ///
+    /// The archive has the `[84,n]` `rbm` array, the `[n]` `time` array `[s]`
+    /// and, if known, the `sampling_frequency` `[Hz]` scalar
+    pub fn to_npz<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut npz = NpzWriter::create(path).map_err(NumpyError::from)?;
+        let data = self.data();
+        let time = self.time();
+        (|| -> io::Result<()> {
+            write_npz_array(
+                &mut npz,
+                "rbm",
+                &[data.nrows() as u64, data.ncols() as u64],
+                data.as_slice(),
+            )?;
+            write_npz_array(&mut npz, "time", &[time.len() as u64], &time)?;
+            if let Some(fs) = self.sampling_frequency() {
+                write_npz_array(&mut npz, "sampling_frequency", &[], &[fs])?;
+            }
+            Ok(())
+        })()
+        .map_err(NumpyError::from)?;
+        Ok(())
+    }
+    /// Reads the `[84,n]` rigid body motions from a `.npy` file
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = read_matrix(open_npy(path)?)?;
+        Self::from_npy_data(data, None, None)
+    }
+    /// Reads the rigid body motions from a `.npz` archive written with [RigidBodyMotions::to_npz]
+    pub fn from_npz<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut npz = NpzArchive::open(path).map_err(NumpyError::from)?;
+        let data = read_matrix(by_name(&mut npz, "rbm")?)?;
+        let time = match npz.by_name("time").map_err(NumpyError::from)? {
+            Some(npy) => Some(read_array(npy)?.1),
+            None => None,
+        };
+        let sampling_frequency = match npz
+            .by_name("sampling_frequency")
+            .map_err(NumpyError::from)?
+        {
+            Some(npy) => read_array(npy)?.1.first().cloned(),
+            None => None,
+        };
+        Self::from_npy_data(data, time, sampling_frequency)
+    }
+    fn from_npy_data(
+        data: na::DMatrix<f64>,
+        time: Option<Vec<f64>>,
+        sampling_frequency: Option<f64>,
+    ) -> Result<Self> {
+        if data.nrows() != 84 || time.as_ref().is_some_and(|t| t.len() != data.ncols()) {
+            return Err(NumpyError::Shape {
+                expected: "[84,n]".to_string(),
+                found: vec![data.nrows() as u64, data.ncols() as u64],
+            }
+            .into());
+        }
+        let time = time.unwrap_or_else(|| {
+            let fs = sampling_frequency.unwrap_or(1f64);
+            (0..data.ncols()).map(|k| k as f64 / fs).collect()
+        });
+        let samples = time
+            .into_iter()
+            .zip(data.column_iter())
+            .map(|(t, x)| (Some(t), Some(x.iter().map(|x| Some(*x)).collect())))
+            .collect();
+        Ok(Self::from_samples(
+            samples,
+            sampling_frequency,
+            &ImportOptions::default(),
+        )?)
+    }
+}
+
+/// Returns the `[n_item,n_sample]` shape of the optical metrics
+fn metrics_shape<T: ToNpy + ?Sized>(metrics: &T) -> NpResult<(usize, usize)> {
+    let n_item = metrics.n_item();
+    if n_item == 0 || !metrics.len().is_multiple_of(n_item) {
+        return Err(NumpyError::Shape {
+            expected: format!("[{n_item},n] with {n_item} > 0"),
+            found: vec![metrics.len() as u64],
+        });
+    }
+    Ok((n_item, metrics.len() / n_item))
+}
+
+/// Optical metrics serialization into NumPy files
+///
+/// The metrics are saved as `[n_item,n_sample]` arrays
+pub trait ToNpy: std::ops::Deref<Target = Vec<f64>> + OpticalMetrics {
+    /// Writes the optical metrics to a `.npy` file
+    fn to_npy<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let (n_item, n_sample) = metrics_shape(self)?;
+        let file = BufWriter::new(File::create(path).map_err(NumpyError::from)?);
+        write_array(file, &[n_item as u64, n_sample as u64], self).map_err(NumpyError::from)?;
+        Ok(())
+    }
+    /// Writes the optical metrics to a `.npz` archive with the `data` and the `time` `[s]` arrays
+    fn to_npz<P: AsRef<Path>>(&self, path: P, time: &[f64]) -> Result<()> {
+        let (n_item, n_sample) = metrics_shape(self)?;
+        if time.len() != n_sample {
+            return Err(crate::LinearOpticalModelError::TimeLength {
+                expected: n_sample,
+                found: time.len(),
+            });
+        }
+        let mut npz = NpzWriter::create(path).map_err(NumpyError::from)?;
+        write_npz_array(&mut npz, "data", &[n_item as u64, n_sample as u64], self)
+            .and_then(|_| write_npz_array(&mut npz, "time", &[n_sample as u64], time))
+            .map_err(NumpyError::from)?;
+        Ok(())
+    }
+}
+impl ToNpy for TipTilt {}
+impl ToNpy for SegmentTipTilt {}
+impl ToNpy for SegmentPiston {}
+impl ToNpy for DifferentialSegmentPiston {}
+impl ToNpy for SegmentWfeRms {}
+impl ToNpy for WfeRms {}
+
+/// Optical metrics deserialization from NumPy files
+pub trait FromNpy: Sized {
+    /// Creates the optical metrics from a `[n_item,n_sample]` matrix
+    fn from_matrix(m: na::DMatrix<f64>) -> Result<Self>;
+    /// Reads the optical metrics from a `[n_item,n_sample]` array in a `.npy` file
+    fn from_npy<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_matrix(read_matrix(open_npy(path)?)?)
+    }
+    /// Reads the optical metrics and the time `[s]` from a `.npz` archive written with [ToNpy::to_npz]
+    fn from_npz<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<f64>)> {
+        let mut npz = NpzArchive::open(path).map_err(NumpyError::from)?;
+        let m = read_matrix(by_name(&mut npz, "data")?)?;
+        let (_, time) = read_array(by_name(&mut npz, "time")?)?;
+        if time.len() != m.ncols() {
+            return Err(crate::LinearOpticalModelError::TimeLength {
+                expected: m.ncols(),
+                found: time.len(),
+            });
+        }
+        Ok((Self::from_matrix(m)?, time))
+    }
+}
+macro_rules! impl_from_npy {
+    ($($metric:ident: $n_item:expr),*) => {
+        $(
+            impl FromNpy for $metric {
+                fn from_matrix(m: na::DMatrix<f64>) -> Result<Self> {
+                    if m.nrows() != $n_item {
+                        return Err(NumpyError::Shape {
+                            expected: format!("[{},n]", $n_item),
+                            found: vec![m.nrows() as u64, m.ncols() as u64],
+                        }
+                        .into());
+                    }
+                    Ok($metric(m.as_slice().to_vec()))
+                }
+            }
+        )*
+    };
+}
+impl_from_npy!(TipTilt: 2, SegmentTipTilt: 14, SegmentPiston: 7, SegmentWfeRms: 7, WfeRms: 1);
+/// The segment [pairs](PistonPairs) are inferred from the number of rows: 21, 12 or 6
+impl FromNpy for DifferentialSegmentPiston {
+    fn from_matrix(m: na::DMatrix<f64>) -> Result<Self> {
+        let pairs = match m.nrows() {
+            21 => PistonPairs::All,
+            12 => PistonPairs::NearestNeighbours,
+            6 => PistonPairs::CenterRelative,
+            _ => {
+                return Err(NumpyError::Shape {
+                    expected: "[21,n], [12,n] or [6,n]".to_string(),
+                    found: vec![m.nrows() as u64, m.ncols() as u64],
+                }
+                .into())
+            }
+        };
+        Ok(DifferentialSegmentPiston {
+            data: m.as_slice().to_vec(),
+            pairs: pairs.pairs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LOM;
+
+    #[test]
+    fn numpy() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| dir.join(format!("gmt-lom_{}_{name}", std::process::id()));
+
+        let sens = OpticalSensitivities::synthetic();
+        sens.to_npz(path("sens.npz")).unwrap();
+        let npz = OpticalSensitivities::<84>::from_npz(path("sens.npz")).unwrap();
+        assert_eq!(npz.len(), sens.len());
+        for a in sens.iter() {
+            match (a, &npz[a.clone()]) {
+                (OpticalSensitivity::SegmentMask(a), OpticalSensitivity::SegmentMask(b)) => {
+                    assert_eq!(a, b)
+                }
+                (OpticalSensitivity::PupilMask(a), OpticalSensitivity::PupilMask(b)) => {
+                    assert_eq!(a, b)
+                }
+                (a, b) => assert_eq!(<&[f64]>::from(a), <&[f64]>::from(b)),
+            }
+        }
+        let tiptilt = &sens[OpticalSensitivity::TipTilt(vec![])];
+        tiptilt.to_npy(path("tt.npy")).unwrap();
+        let m: na::DMatrix<f64> = (&OpticalSensitivity::<84>::from_npy(
+            OpticalSensitivity::TipTilt(vec![]),
+            path("tt.npy"),
+        )
+        .unwrap())
+            .into();
+        assert_eq!(m, na::DMatrix::<f64>::from(tiptilt));
+        assert!(OpticalSensitivity::<84>::from_npy(
+            OpticalSensitivity::SegmentPiston(vec![]),
+            path("tt.npy")
+        )
+        .is_err());
+
+        let data = na::DMatrix::<f64>::from_fn(84, 5, |i, j| (i * 10 + j) as f64);
+        let mut lom = LOM::builder().optical_sensitivities(sens).build().unwrap();
+        lom.rbm = data.clone().into();
+        lom.rbm.to_npz(path("rbm.npz")).unwrap();
+        let rbm = RigidBodyMotions::from_npz(path("rbm.npz")).unwrap();
+        assert_eq!(*rbm.data(), data);
+        assert_eq!(rbm.time(), lom.time());
+        lom.rbm.to_npy(path("rbm.npy")).unwrap();
+        assert_eq!(
+            *RigidBodyMotions::from_npy(path("rbm.npy")).unwrap().data(),
+            data
+        );
+
+        let segment_tiptilt = lom.segment_tiptilt();
+        segment_tiptilt.to_npy(path("stt.npy")).unwrap();
+        let stt = SegmentTipTilt::from_npy(path("stt.npy")).unwrap();
+        assert_eq!(*stt, *segment_tiptilt);
+        assert!(TipTilt::from_npy(path("stt.npy")).is_err());
+        segment_tiptilt
+            .to_npz(path("stt.npz"), &lom.time())
+            .unwrap();
+        let (stt, time) = SegmentTipTilt::from_npz(path("stt.npz")).unwrap();
+        assert_eq!(*stt, *segment_tiptilt);
+        assert_eq!(time, lom.time());
+
+        let dp = lom.differential_segment_piston(PistonPairs::NearestNeighbours);
+        dp.to_npz(path("dp.npz"), &lom.time()).unwrap();
+        let (npz_dp, _) = DifferentialSegmentPiston::from_npz(path("dp.npz")).unwrap();
+        assert_eq!(*npz_dp, *dp);
+        assert_eq!(npz_dp.pairs(), dp.pairs());
+        assert!(DifferentialSegmentPiston::from_npy(path("stt.npy")).is_err());
+
+        // no item
+        let empty = DifferentialSegmentPiston {
+            data: vec![],
+            pairs: vec![],
+        };
+        assert!(empty.to_npy(path("empty.npy")).is_err());
+
+        // C order array
+        let c_order = path("c.npy");
+        let mut npy = WriteOptions::new()
+            .default_dtype()
+            .shape(&[2, 3])
+            .writer(BufWriter::new(File::create(&c_order).unwrap()))
+            .begin_nd()
+            .unwrap();
+        npy.extend([1f64, 2., 3., 4., 5., 6.]).unwrap();
+        npy.finish().unwrap();
+        let m = read_matrix(open_npy(&c_order).unwrap()).unwrap();
+        assert_eq!(
+            m,
+            na::DMatrix::from_row_slice(2, 3, &[1., 2., 3., 4., 5., 6.])
+        );
+
+        for name in [
+            "sens.npz", "tt.npy", "rbm.npz", "rbm.npy", "stt.npy", "stt.npz", "dp.npz", "c.npy",
+        ] {
+            std::fs::remove_file(path(name)).unwrap();
+        }
+    }
+}
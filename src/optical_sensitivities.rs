@@ -6,44 +6,213 @@ use faer_ext::IntoFaer;
 use nalgebra as na;
 use serde::{Deserialize, Serialize};
 
+mod header;
+pub use header::{Provenance, SensitivitiesHeader, SensitivitiesMetadata, FORMAT_VERSION};
+mod pupil;
+pub use pupil::{PupilGeometry, PupilOrientation};
+
 /// Optical sensitivities
 ///
 /// Linear transformation of M1 and M2 rigid body motions into wavefront and wavefront piston and tip-tilt modes
+///
+/// The metadata are not part of the sensitivities serialization, they are saved in the file header
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct OpticalSensitivities<const N: usize = 84>(Vec<OpticalSensitivity<N>>);
-impl Deref for OpticalSensitivities {
-    type Target = [OpticalSensitivity];
+pub struct OpticalSensitivities<const N: usize = 84>(
+    Vec<OpticalSensitivity<N>>,
+    #[serde(skip)] Option<SensitivitiesMetadata>,
+);
+impl<const N: usize> Deref for OpticalSensitivities<N> {
+    type Target = [OpticalSensitivity<N>];
     fn deref(&self) -> &Self::Target {
         self.0.as_slice()
     }
 }
+impl<const N: usize> From<Vec<OpticalSensitivity<N>>> for OpticalSensitivities<N> {
+    fn from(sens: Vec<OpticalSensitivity<N>>) -> Self {
+        Self(sens, None)
+    }
+}
 impl<const N: usize> OpticalSensitivities<N> {
+    /// Returns the optical model metadata, if any
+    pub fn metadata(&self) -> Option<&SensitivitiesMetadata> {
+        self.1.as_ref()
+    }
+    /// Sets the optical model metadata
+    pub fn with_metadata(self, metadata: SensitivitiesMetadata) -> Self {
+        Self(self.0, Some(metadata))
+    }
+    /// Returns the optical sensitivity of the same variant than `index` or `None` if it is missing
+    pub fn get(&self, index: OpticalSensitivity<N>) -> Option<&OpticalSensitivity<N>> {
+        self.0.iter().find(|&s| index == *s)
+    }
+    /// Returns the optical sensitivity of the same variant than `index`
+    ///
+    /// Fails with [LinearOpticalModelError::MissingSensitivity] if the sensitivity is missing
+    pub fn try_get(&self, index: OpticalSensitivity<N>) -> Result<&OpticalSensitivity<N>> {
+        let name = index.to_string();
+        self.get(index)
+            .ok_or(LinearOpticalModelError::MissingSensitivity(name))
+    }
+    /// Returns the exit pupil mask
+    pub fn pupil_mask(&self) -> Result<&[bool]> {
+        match self.try_get(OpticalSensitivity::PupilMask(vec![]))? {
+            OpticalSensitivity::PupilMask(mask) => Ok(mask.as_slice()),
+            _ => unreachable!(),
+        }
+    }
+    /// Returns the segment mask within the exit pupil
+    pub fn segment_mask(&self) -> Result<&[i32]> {
+        match self.try_get(OpticalSensitivity::SegmentMask(vec![]))? {
+            OpticalSensitivity::SegmentMask(mask) => Ok(mask.as_slice()),
+            _ => unreachable!(),
+        }
+    }
+    /// Checks that the [Wavefront](OpticalSensitivity::Wavefront) rows and the [SegmentMask](OpticalSensitivity::SegmentMask) length
+    /// match the number of pixels within the [PupilMask](OpticalSensitivity::PupilMask)
+    pub(crate) fn check_pupil(&self) -> Result<()> {
+        let Ok(mask) = self.pupil_mask() else {
+            return Ok(());
+        };
+        let expected = mask.iter().filter(|&&m| m).count();
+        for s in self.0.iter() {
+            let found = match s {
+                OpticalSensitivity::Wavefront(val) => val.len() / N,
+                OpticalSensitivity::SegmentMask(val) => val.len(),
+                _ => continue,
+            };
+            if found != expected {
+                return Err(LinearOpticalModelError::PupilPixels {
+                    name: s.to_string(),
+                    expected,
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Sets the exit pupil sampling grid in the metadata
+    pub fn with_pupil_geometry(self, pupil: PupilGeometry) -> Self {
+        let metadata = self.1.unwrap_or_default();
+        Self(
+            self.0,
+            Some(SensitivitiesMetadata {
+                pupil: Some(pupil),
+                ..metadata
+            }),
+        )
+    }
+    /// Returns the exit pupil sampling grid
+    ///
+    /// Sensitivities without a [PupilGeometry] in the metadata are assumed to be sampled
+    /// on a square grid with the default orientation and a pixel size derived from the metadata pupil size, if any, or 1m otherwise.
+    /// The pupil size spans the centres of the edge pixels, consistently with [PupilGeometry::xy]
+    pub fn pupil_geometry(&self) -> Result<PupilGeometry> {
+        let n = self.pupil_mask()?.len();
+        match self.metadata().and_then(|metadata| metadata.pupil.clone()) {
+            Some(pupil) if pupil.n_pixel() != n => Err(LinearOpticalModelError::PupilGeometry {
+                side: pupil.side,
+                n_pixel: n,
+            }),
+            Some(pupil) => Ok(pupil),
+            None => {
+                let side = (n as f64).sqrt().round() as usize;
+                if side * side != n {
+                    return Err(LinearOpticalModelError::PupilGeometry { side, n_pixel: n });
+                }
+                let pixel_size = self
+                    .metadata()
+                    .and_then(|metadata| metadata.pupil_size)
+                    .map_or(1f64, |size| size / side.saturating_sub(1).max(1) as f64);
+                Ok(PupilGeometry::new(side, pixel_size))
+            }
+        }
+    }
+    /// Returns the `(x,y)` pupil grid coordinates `[m]` of the wavefront samples within the exit pupil
+    pub(crate) fn masked_pixel_coordinates(&self) -> Result<Vec<(f64, f64)>> {
+        let mask = self.pupil_mask()?;
+        self.check_pupil()?;
+        let pupil = self.pupil_geometry()?;
+        Ok(mask
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| m)
+            .map(|(k, _)| pupil.xy(k))
+            .collect())
+    }
     /// Returns the wavefront within the exit pupil in `[m]`
     pub fn masked_wavefront(&self, data: &na::DMatrix<f64>) -> Vec<f64> {
-        self[OpticalSensitivity::<N>::Wavefront(vec![])].into_optics(data)
+        self.try_masked_wavefront(data)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront within the exit pupil in `[m]`
+    ///
+    /// Fails if the [Wavefront](OpticalSensitivity::Wavefront) sensitivity is missing
+    pub fn try_masked_wavefront(&self, data: &na::DMatrix<f64>) -> Result<Vec<f64>> {
+        Ok(self
+            .try_get(OpticalSensitivity::Wavefront(vec![]))?
+            .into_optics(data))
     }
     /// Returns the wavefront in the exit pupil in `[rmm]`
+    ///
+    /// The wavefront vector is given as `[w1,...,wi,...,wn]` where `wi` is the pupil map at time index i
     pub fn wavefront(&self, data: &na::DMatrix<f64>) -> Vec<f64> {
-        let mut wavefront = self[OpticalSensitivity::<N>::Wavefront(vec![])]
-            .into_optics(data)
-            .into_iter();
-        if let OpticalSensitivity::PupilMask(mask) =
-            &self[OpticalSensitivity::<N>::PupilMask(vec![])]
-        {
-            mask.into_iter()
-                .map(|&mask| {
-                    if mask {
-                        wavefront.next().unwrap()
-                    } else {
-                        0f64
-                    }
-                })
-                .collect()
-        } else {
-            panic!("`PupilMask` is missing from `OpticalSensitivities`")
+        self.try_wavefront(data).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront in the exit pupil in `[rmm]`
+    ///
+    /// Fails if either the [Wavefront](OpticalSensitivity::Wavefront) or
+    /// the [PupilMask](OpticalSensitivity::PupilMask) sensitivities are missing
+    pub fn try_wavefront(&self, data: &na::DMatrix<f64>) -> Result<Vec<f64>> {
+        Ok(self.try_wavefront_cube(data)?.concat())
+    }
+    /// Returns the wavefront in the exit pupil in `[rmm]`, one pupil map per time step
+    ///
+    /// Fails if either the [Wavefront](OpticalSensitivity::Wavefront) or
+    /// the [PupilMask](OpticalSensitivity::PupilMask) sensitivities are missing,
+    /// if the pupil mask is empty or if it does not match the wavefront
+    pub fn try_wavefront_cube(&self, data: &na::DMatrix<f64>) -> Result<Vec<Vec<f64>>> {
+        let mask = self.pupil_mask()?;
+        let n = mask.iter().filter(|&&m| m).count();
+        if n == 0 {
+            return Err(LinearOpticalModelError::EmptyPupil);
         }
+        self.check_pupil()?;
+        Ok(self
+            .try_masked_wavefront(data)?
+            .chunks(n)
+            .map(|wavefront| into_pupil(mask, wavefront))
+            .collect())
+    }
+    /// Returns an iterator over the wavefront pupil maps in `[rmm]`, one per time step
+    ///
+    /// The pupil maps are computed on demand so the whole wavefront cube is never stored in memory
+    pub fn try_wavefront_iter<'a>(
+        &'a self,
+        data: &'a na::DMatrix<f64>,
+    ) -> Result<impl Iterator<Item = Vec<f64>> + 'a> {
+        let mask = self.pupil_mask()?;
+        self.check_pupil()?;
+        let OpticalSensitivity::Wavefront(sens) =
+            self.try_get(OpticalSensitivity::Wavefront(vec![]))?
+        else {
+            unreachable!()
+        };
+        let sensitivity = na::DMatrixView::from_slice(sens, sens.len() / N, N);
+        Ok(data
+            .column_iter()
+            .map(move |rbm| into_pupil(mask, (sensitivity * rbm).as_slice())))
     }
 }
+/// Inserts the wavefront within the exit pupil into the full pupil map according to `mask`
+pub(crate) fn into_pupil(mask: &[bool], wavefront: &[f64]) -> Vec<f64> {
+    let mut map = vec![0f64; mask.len()];
+    mask.iter()
+        .enumerate()
+        .filter_map(|(k, &m)| m.then_some(k))
+        .zip(wavefront)
+        .for_each(|(k, &w)| map[k] = w);
+    map
+}
 impl<const N: usize> Display for OpticalSensitivities<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Optical Sensitivities:")?;
@@ -68,6 +237,8 @@ pub enum OpticalSensitivity<const N: usize = 84> {
     SegmentPiston(Vec<f64>),
     SegmentMask(Vec<i32>),
     PupilMask(Vec<bool>),
+    /// Zernike coefficients `[nxN]` of the wavefront sensitivity in a [ZernikeBasis](crate::zernike::ZernikeBasis)
+    Zernike(Vec<f64>),
 }
 impl<const N: usize> Display for OpticalSensitivity<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -78,6 +249,7 @@ impl<const N: usize> Display for OpticalSensitivity<N> {
             OpticalSensitivity::SegmentPiston(_) => write!(f, "SegmentPiston"),
             OpticalSensitivity::SegmentMask(_) => write!(f, "SegmentMask"),
             OpticalSensitivity::PupilMask(_) => write!(f, "PupilMask"),
+            OpticalSensitivity::Zernike(_) => write!(f, "Zernike"),
         }
     }
 }
@@ -85,7 +257,9 @@ impl<'a, const N: usize> From<&'a OpticalSensitivity<N>> for na::DMatrix<f64> {
     fn from(sens: &'a OpticalSensitivity<N>) -> Self {
         use OpticalSensitivity::*;
         match sens {
-            Wavefront(val) => Some(na::DMatrix::from_column_slice(val.len() / N, N, val)),
+            Wavefront(val) | Zernike(val) => {
+                Some(na::DMatrix::from_column_slice(val.len() / N, N, val))
+            }
             TipTilt(val) => Some(na::DMatrix::from_column_slice(2, N, val)),
             SegmentTipTilt(val) => Some(na::DMatrix::from_column_slice(14, N, val)),
             SegmentPiston(val) => Some(na::DMatrix::from_column_slice(7, N, val)),
@@ -96,36 +270,22 @@ impl<'a, const N: usize> From<&'a OpticalSensitivity<N>> for na::DMatrix<f64> {
 }
 impl<const N: usize> PartialEq<OpticalSensitivity<N>> for OpticalSensitivity<N> {
     fn eq(&self, other: &OpticalSensitivity<N>) -> bool {
-        use OpticalSensitivity::*;
-        match (self, other) {
-            (Wavefront(_), Wavefront(_)) => true,
-            (TipTilt(_), TipTilt(_)) => true,
-            (SegmentTipTilt(_), SegmentTipTilt(_)) => true,
-            (SegmentPiston(_), SegmentPiston(_)) => true,
-            (SegmentMask(_), SegmentMask(_)) => true,
-            (PupilMask(_), PupilMask(_)) => true,
-            _ => false,
-        }
+        std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
 impl<const N: usize> std::ops::Index<OpticalSensitivity<N>> for OpticalSensitivities<N> {
     type Output = OpticalSensitivity<N>;
 
     fn index(&self, index: OpticalSensitivity<N>) -> &Self::Output {
-        self.0
-            .iter()
-            // .find_map(|s| if index == *s { Some(s) } else { None })
-            .find(|&s| index == *s)
-            .expect(&format!("cannot find optical sensitivity: {}", index))
+        self.try_get(index).unwrap_or_else(|e| panic!("{e}"))
     }
 }
-impl<'a> From<&'a OpticalSensitivity> for &'a [f64] {
-    fn from(sens: &'a OpticalSensitivity) -> Self {
+impl<'a, const N: usize> From<&'a OpticalSensitivity<N>> for &'a [f64] {
+    fn from(sens: &'a OpticalSensitivity<N>) -> Self {
         use OpticalSensitivity::*;
         match sens {
-            Wavefront(val) | TipTilt(val) | SegmentTipTilt(val) | SegmentPiston(val) => {
-                Some(val.as_slice())
-            }
+            Wavefront(val) | TipTilt(val) | SegmentTipTilt(val) | SegmentPiston(val)
+            | Zernike(val) => Some(val.as_slice()),
             _ => None,
         }
         .unwrap()
@@ -160,7 +320,7 @@ impl<const N: usize> OpticalSensitivity<N> {
                         .chunks(14 * 3)
                         .skip(1)
                         .step_by(2)
-                        .flat_map(|x| (&x[..14 * 2]).to_vec()),
+                        .flat_map(|x| x[..14 * 2].to_vec()),
                 ))
             }
             _ => Err(LinearOpticalModelError::SegmentTipTilt),
@@ -214,19 +374,9 @@ impl<const N: usize> OpticalSensitivity<N> {
             OpticalSensitivity::SegmentPiston(sens) => {
                 let sensitivity = na::DMatrix::from_column_slice(7, N, sens);
                 let segment_piston = sensitivity * rbm;
-                let mut v: Vec<f64> = vec![];
-                for (k, row) in segment_piston.row_iter().take(6).enumerate() {
-                    //println!("{}: {:?}", k, row.shape());
-                    v.extend(
-                        &mut segment_piston
-                            .rows(k + 1, 6 - k)
-                            .row_iter()
-                            .flat_map(|y| (y - row).as_slice().to_owned()),
-                    );
-                }
                 segment_piston.as_slice().to_owned()
             }
-            OpticalSensitivity::Wavefront(sens) => {
+            OpticalSensitivity::Wavefront(sens) | OpticalSensitivity::Zernike(sens) => {
                 let sensitivity = na::DMatrix::from_column_slice(sens.len() / N, N, sens);
                 let wavefront = sensitivity * rbm;
                 wavefront.as_slice().to_owned()
@@ -282,18 +432,8 @@ impl<const N: usize> OpticalSensitivity<N> {
             OpticalSensitivity::SegmentPiston(sens) => {
                 let sensitivity = faer::MatRef::from_column_major_slice(sens, 7, N);
                 sensitivity * rbm.view_range(.., ..).into_faer()
-                // let mut v: Vec<f64> = vec![];
-                // for (k, row) in segment_piston.row_iter().take(6).enumerate() {
-                //     //println!("{}: {:?}", k, row.shape());
-                //     v.extend(
-                //         &mut segment_piston
-                //             .rows(k + 1, 6 - k)
-                //             .row_iter()
-                //             .flat_map(|y| (y - row).as_slice().to_owned()),
-                //     );
-                // }
             }
-            OpticalSensitivity::Wavefront(sens) => {
+            OpticalSensitivity::Wavefront(sens) | OpticalSensitivity::Zernike(sens) => {
                 let sensitivity = faer::MatRef::from_column_major_slice(sens, sens.len() / N, N);
                 sensitivity * rbm.view_range(.., ..).into_faer()
             }
@@ -564,8 +704,24 @@ impl OpticalSensitivities {
             ),
             OpticalSensitivity::PupilMask(amplitude),
         ];
+        let metadata = SensitivitiesMetadata {
+            pupil_sampling: Some(src.pupil_sampling as usize),
+            pupil_size: Some(src.pupil_size),
+            wavelength: Some(src.wavelength()),
+            field: Some((src.zenith[0] as f64, src.azimuth[0] as f64)),
+            stroke: Some((stroke_fn(0), stroke_fn(3))),
+            provenance: Provenance {
+                description: "crseo".to_string(),
+                ..Default::default()
+            },
+            pupil: Some(PupilGeometry::new(
+                src.pupil_sampling as usize,
+                src.pupil_size / (src.pupil_sampling - 1) as f64,
+            )),
+            ..Default::default()
+        };
         println!(" ... done in {:.3}s", now.elapsed().as_secs_f64());
-        Ok(Self(optical_sensitivities))
+        Ok(Self(optical_sensitivities, Some(metadata)))
     }
 }
 pub fn from_opticals<const N: usize>(senses: &[OpticalSensitivity<N>]) -> na::DMatrix<f64> {
@@ -588,3 +744,160 @@ pub fn from_opticals<const N: usize>(senses: &[OpticalSensitivity<N>]) -> na::DM
         .collect();
     na::DMatrix::from_column_slice(n_rows, N, &data)
 }
+
+#[cfg(test)]
+impl OpticalSensitivities {
+    /// Synthetic sensitivities on a `8x8` pupil for unit tests
+    ///
+    /// The 4 corners of the pupil are masked and the remaining pixels are assigned to the segments in turn
+    pub(crate) fn synthetic() -> Self {
+        let n_px = 8;
+        let pupil_mask: Vec<bool> = (0..n_px * n_px)
+            .map(|k| {
+                let (i, j) = (k % n_px, k / n_px);
+                !((i == 0 || i == n_px - 1) && (j == 0 || j == n_px - 1))
+            })
+            .collect();
+        let xy: Vec<(f64, f64)> = pupil_mask
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| **m)
+            .map(|(k, _)| ((k % n_px) as f64 - 3.5, (k / n_px) as f64 - 3.5))
+            .collect();
+        let segment_mask: Vec<i32> = (0..xy.len()).map(|k| (k % 7) as i32 + 1).collect();
+        let dof = |j: usize| ((j % 42) / 6, j % 6, if j < 42 { 1f64 } else { -0.5 });
+        let wavefront: Vec<f64> = (0..84)
+            .flat_map(|j| {
+                let (sid, d, g) = dof(j);
+                xy.iter()
+                    .zip(&segment_mask)
+                    .map(|((x, y), &m)| {
+                        if m as usize != sid + 1 {
+                            return 0f64;
+                        }
+                        g * match d {
+                            2 => 2f64,
+                            3 => 2f64 * y,
+                            4 => -2f64 * x,
+                            _ => 0.1 * (d + 1) as f64 * x * y,
+                        }
+                    })
+                    .collect::<Vec<f64>>()
+            })
+            .collect();
+        let tip_tilt: Vec<f64> = (0..84)
+            .flat_map(|j| {
+                let (_, d, g) = dof(j);
+                (0..2).map(move |i| if d == 3 + i { g / 7f64 } else { 0f64 })
+            })
+            .collect();
+        let segment_tip_tilt: Vec<f64> = (0..84)
+            .flat_map(|j| {
+                let (sid, d, g) = dof(j);
+                (0..14).map(move |r| {
+                    if r % 7 == sid && d == 3 + r / 7 {
+                        g
+                    } else {
+                        0f64
+                    }
+                })
+            })
+            .collect();
+        let segment_piston: Vec<f64> = (0..84)
+            .flat_map(|j| {
+                let (sid, d, g) = dof(j);
+                (0..7).map(move |r| if r == sid && d == 2 { 2f64 * g } else { 0f64 })
+            })
+            .collect();
+        Self::from(vec![
+            OpticalSensitivity::Wavefront(wavefront),
+            OpticalSensitivity::TipTilt(tip_tilt),
+            OpticalSensitivity::SegmentPiston(segment_piston),
+            OpticalSensitivity::SegmentTipTilt(segment_tip_tilt),
+            OpticalSensitivity::SegmentMask(segment_mask),
+            OpticalSensitivity::PupilMask(pupil_mask),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_sensitivity() {
+        let sens: OpticalSensitivities = OpticalSensitivities::synthetic()
+            .iter()
+            .filter(|s| **s != OpticalSensitivity::PupilMask(vec![]))
+            .cloned()
+            .collect::<Vec<_>>()
+            .into();
+        assert!(sens.get(OpticalSensitivity::TipTilt(vec![])).is_some());
+        assert!(sens.get(OpticalSensitivity::PupilMask(vec![])).is_none());
+        let rbm = na::DMatrix::<f64>::zeros(84, 1);
+        match sens.try_wavefront(&rbm) {
+            Err(LinearOpticalModelError::MissingSensitivity(name)) => {
+                assert_eq!(name, "PupilMask")
+            }
+            _ => panic!("expected a missing `PupilMask` error"),
+        }
+    }
+
+    #[test]
+    fn empty_pupil() {
+        let sens: OpticalSensitivities = OpticalSensitivities::synthetic()
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::Wavefront(_) => OpticalSensitivity::Wavefront(vec![]),
+                OpticalSensitivity::PupilMask(mask) => {
+                    OpticalSensitivity::PupilMask(vec![false; mask.len()])
+                }
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        let rbm = na::DMatrix::<f64>::zeros(84, 2);
+        assert!(matches!(
+            sens.try_wavefront_cube(&rbm),
+            Err(LinearOpticalModelError::EmptyPupil)
+        ));
+
+        let sens: OpticalSensitivities = vec![OpticalSensitivity::PupilMask(vec![])].into();
+        let sens = sens.with_metadata(SensitivitiesMetadata {
+            pupil_size: Some(4.),
+            ..Default::default()
+        });
+        assert_eq!(sens.pupil_geometry().unwrap(), PupilGeometry::new(0, 4.));
+    }
+
+    #[test]
+    fn pupil_pixels() {
+        let sens: OpticalSensitivities = OpticalSensitivities::synthetic()
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::PupilMask(mask) => {
+                    OpticalSensitivity::PupilMask(vec![true; mask.len()])
+                }
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        let rbm = na::DMatrix::<f64>::zeros(84, 2);
+        assert!(matches!(
+            sens.try_wavefront_cube(&rbm),
+            Err(LinearOpticalModelError::PupilPixels {
+                expected: 64,
+                found: 60,
+                ..
+            })
+        ));
+        assert!(sens.try_wavefront_iter(&rbm).is_err());
+        assert!(sens
+            .modal_analysis(OpticalSensitivity::Wavefront(vec![]))
+            .is_err());
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&sens.to_bytes().unwrap()),
+            Err(LinearOpticalModelError::PupilPixels { .. })
+        ));
+    }
+}
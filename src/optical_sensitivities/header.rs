@@ -0,0 +1,450 @@
+use std::{
+    io::Cursor,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{OpticalSensitivities, OpticalSensitivity, PupilGeometry};
+use crate::{zernike::ZernikeSupport, LinearOpticalModelError, Result};
+
+/// Magic bytes at the start of a versioned sensitivities file
+pub const MAGIC: &[u8; 8] = b"GMTLOM\x00\x01";
+/// Current version of the sensitivities file format
+///
+/// Version 2 adds the [PupilGeometry] to the metadata, version 3 the Zernike basis,
+/// version 1 and 2 files can still be loaded
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Origin of the optical sensitivities
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Provenance {
+    /// Creation date in seconds since the UNIX epoch
+    pub created: u64,
+    /// Version of the crate that wrote the sensitivities
+    pub crate_version: String,
+    /// Free-form description of the optical model
+    pub description: String,
+}
+impl Default for Provenance {
+    fn default() -> Self {
+        Self {
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            description: String::new(),
+        }
+    }
+}
+
+/// Description of the optical model the sensitivities are derived from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SensitivitiesMetadata {
+    /// Number of pixels across the exit pupil
+    pub pupil_sampling: Option<usize>,
+    /// Size of the exit pupil `[m]`, from the centre of the first pixel to the centre of the last one
+    pub pupil_size: Option<f64>,
+    /// Source wavelength `[m]`
+    pub wavelength: Option<f64>,
+    /// Source field angle as (zenith,azimuth) `[rd]`
+    pub field: Option<(f64, f64)>,
+    /// Rigid body motions strokes as (translations `[m]`, rotations `[rd]`)
+    pub stroke: Option<(f64, f64)>,
+    /// Units of the sensitivities
+    pub units: String,
+    /// Origin of the sensitivities
+    pub provenance: Provenance,
+    /// Exit pupil sampling grid
+    pub pupil: Option<PupilGeometry>,
+    /// Support and number of modes of the basis of the [Zernike](OpticalSensitivity::Zernike) sensitivity
+    pub zernike: Option<(ZernikeSupport, usize)>,
+}
+impl Default for SensitivitiesMetadata {
+    fn default() -> Self {
+        Self {
+            pupil_sampling: None,
+            pupil_size: None,
+            wavelength: None,
+            field: None,
+            stroke: None,
+            units:
+                "wavefront & segment piston: [m/m,m/rd], tip-tilt & segment tip-tilt: [rd/m,rd/rd]"
+                    .to_string(),
+            provenance: Default::default(),
+            pupil: None,
+            zernike: None,
+        }
+    }
+}
+
+/// Version 1 of [SensitivitiesMetadata]
+#[derive(Deserialize)]
+struct SensitivitiesMetadataV1 {
+    pupil_sampling: Option<usize>,
+    pupil_size: Option<f64>,
+    wavelength: Option<f64>,
+    field: Option<(f64, f64)>,
+    stroke: Option<(f64, f64)>,
+    units: String,
+    provenance: Provenance,
+}
+impl From<SensitivitiesMetadataV1> for SensitivitiesMetadata {
+    fn from(metadata: SensitivitiesMetadataV1) -> Self {
+        Self {
+            pupil_sampling: metadata.pupil_sampling,
+            pupil_size: metadata.pupil_size,
+            wavelength: metadata.wavelength,
+            field: metadata.field,
+            stroke: metadata.stroke,
+            units: metadata.units,
+            provenance: metadata.provenance,
+            pupil: None,
+            zernike: None,
+        }
+    }
+}
+
+/// Version 2 of [SensitivitiesMetadata]
+#[derive(Deserialize)]
+struct SensitivitiesMetadataV2 {
+    pupil_sampling: Option<usize>,
+    pupil_size: Option<f64>,
+    wavelength: Option<f64>,
+    field: Option<(f64, f64)>,
+    stroke: Option<(f64, f64)>,
+    units: String,
+    provenance: Provenance,
+    pupil: Option<PupilGeometry>,
+}
+impl From<SensitivitiesMetadataV2> for SensitivitiesMetadata {
+    fn from(metadata: SensitivitiesMetadataV2) -> Self {
+        Self {
+            pupil_sampling: metadata.pupil_sampling,
+            pupil_size: metadata.pupil_size,
+            wavelength: metadata.wavelength,
+            field: metadata.field,
+            stroke: metadata.stroke,
+            units: metadata.units,
+            provenance: metadata.provenance,
+            pupil: metadata.pupil,
+            zernike: None,
+        }
+    }
+}
+
+/// Header of a versioned sensitivities file
+///
+/// The file is written as the [MAGIC] bytes followed by the [bincode] encoded header and sensitivities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensitivitiesHeader {
+    /// File format version
+    pub version: u32,
+    /// Number of degrees of freedom `N`
+    pub n_dof: usize,
+    /// Description of the optical model
+    pub metadata: SensitivitiesMetadata,
+    /// FNV-1a checksum of the encoded sensitivities
+    pub checksum: u64,
+}
+impl SensitivitiesHeader {
+    /// Reads the header from the sensitivities file `bytes`
+    ///
+    /// Returns `None` for legacy files without header
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        Ok(Self::decode(bytes)?.map(|(header, _)| header))
+    }
+    /// Reads the header from the sensitivities file at `path`
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Option<Self>> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+    fn decode(bytes: &[u8]) -> Result<Option<(Self, &[u8])>> {
+        match bytes.strip_prefix(MAGIC.as_slice()) {
+            Some(bytes) => {
+                let version: u32 = bincode::deserialize(bytes)?;
+                let mut cursor = Cursor::new(bytes);
+                let header: Self = match version {
+                    1 => {
+                        let (version, n_dof, metadata, checksum): (
+                            u32,
+                            usize,
+                            SensitivitiesMetadataV1,
+                            u64,
+                        ) = bincode::deserialize_from(&mut cursor)?;
+                        Self {
+                            version,
+                            n_dof,
+                            metadata: metadata.into(),
+                            checksum,
+                        }
+                    }
+                    2 => {
+                        let (version, n_dof, metadata, checksum): (
+                            u32,
+                            usize,
+                            SensitivitiesMetadataV2,
+                            u64,
+                        ) = bincode::deserialize_from(&mut cursor)?;
+                        Self {
+                            version,
+                            n_dof,
+                            metadata: metadata.into(),
+                            checksum,
+                        }
+                    }
+                    FORMAT_VERSION => bincode::deserialize_from(&mut cursor)?,
+                    _ => return Err(LinearOpticalModelError::SensitivityFormat(version)),
+                };
+                let payload = &bytes[cursor.position() as usize..];
+                Ok(Some((header, payload)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// FNV-1a 64 bits hash
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Encodes the sensitivities with a versioned header
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let payload = bincode::serialize(self)?;
+        let header = SensitivitiesHeader {
+            version: FORMAT_VERSION,
+            n_dof: N,
+            metadata: self.1.clone().unwrap_or_default(),
+            checksum: checksum(&payload),
+        };
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &header)?;
+        bytes.extend(payload);
+        Ok(bytes)
+    }
+    /// Decodes the sensitivities from either a versioned or a legacy encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let sens = match SensitivitiesHeader::decode(bytes)? {
+            Some((header, payload)) => {
+                if header.n_dof != N {
+                    return Err(LinearOpticalModelError::SensitivityDof {
+                        expected: N,
+                        found: header.n_dof,
+                    });
+                }
+                if header.checksum != checksum(payload) {
+                    return Err(LinearOpticalModelError::SensitivityChecksum);
+                }
+                let mut sens: Self = bincode::deserialize(payload)?;
+                sens.1 = Some(header.metadata);
+                sens
+            }
+            None => {
+                log::warn!("loading legacy optical sensitivities without header");
+                bincode::deserialize(bytes)?
+            }
+        };
+        sens.check_dof()?;
+        Ok(sens)
+    }
+    /// Checks that the sensitivities dimensions match the number of degrees of freedom `N`
+    ///
+    /// The number of rows of the [Zernike](OpticalSensitivity::Zernike) sensitivity is also checked against the basis in the metadata, if any,
+    /// and the wavefront and the segment mask against the pupil mask
+    fn check_dof(&self) -> Result<()> {
+        let zernike_rows =
+            self.1
+                .as_ref()
+                .and_then(|metadata| metadata.zernike)
+                .map(|(support, n_mode)| match support {
+                    ZernikeSupport::Pupil => n_mode,
+                    ZernikeSupport::Segments => 7 * n_mode,
+                });
+        for s in self.0.iter() {
+            let (n_row, len) = match s {
+                OpticalSensitivity::Wavefront(val) | OpticalSensitivity::Zernike(val) => {
+                    if !val.len().is_multiple_of(N) {
+                        return Err(LinearOpticalModelError::SensitivityLength {
+                            name: s.to_string(),
+                            len: val.len(),
+                            n_dof: N,
+                        });
+                    }
+                    match (s, zernike_rows) {
+                        (OpticalSensitivity::Zernike(_), Some(n_row)) if val.len() != n_row * N => {
+                            return Err(LinearOpticalModelError::SensitivityRows {
+                                name: s.to_string(),
+                                expected: n_row,
+                                found: val.len() / N,
+                            })
+                        }
+                        _ => continue,
+                    }
+                }
+                OpticalSensitivity::TipTilt(val) => (2, val.len()),
+                OpticalSensitivity::SegmentTipTilt(val) => (14, val.len()),
+                OpticalSensitivity::SegmentPiston(val) => (7, val.len()),
+                _ => continue,
+            };
+            if len != n_row * N {
+                return Err(LinearOpticalModelError::SensitivityDof {
+                    expected: N,
+                    found: len / n_row.max(1),
+                });
+            }
+        }
+        self.check_pupil()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versioned() {
+        let sens = OpticalSensitivities::synthetic().with_metadata(SensitivitiesMetadata {
+            pupil_sampling: Some(8),
+            ..Default::default()
+        });
+        let bytes = sens.to_bytes().unwrap();
+        let header = SensitivitiesHeader::from_bytes(&bytes).unwrap().unwrap();
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.n_dof, 84);
+        let sens: OpticalSensitivities = OpticalSensitivities::from_bytes(&bytes).unwrap();
+        assert_eq!(sens.metadata().unwrap().pupil_sampling, Some(8));
+        assert!(matches!(
+            OpticalSensitivities::<42>::from_bytes(&bytes),
+            Err(LinearOpticalModelError::SensitivityDof {
+                expected: 42,
+                found: 84
+            })
+        ));
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&corrupted),
+            Err(LinearOpticalModelError::SensitivityChecksum)
+        ));
+    }
+
+    #[test]
+    fn version_1() {
+        let sens = OpticalSensitivities::synthetic();
+        let payload = bincode::serialize(&sens).unwrap();
+        let metadata = (
+            Some(8usize),
+            Some(4f64),
+            None::<f64>,
+            None::<(f64, f64)>,
+            None::<(f64, f64)>,
+            "units".to_string(),
+            Provenance::default(),
+        );
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &(1u32, 84usize, metadata, checksum(&payload)))
+            .unwrap();
+        bytes.extend(payload);
+        let header = SensitivitiesHeader::from_bytes(&bytes).unwrap().unwrap();
+        assert_eq!(header.version, 1);
+        let sens: OpticalSensitivities = OpticalSensitivities::from_bytes(&bytes).unwrap();
+        let metadata = sens.metadata().unwrap();
+        assert_eq!(metadata.pupil_size, Some(4.));
+        assert!(metadata.pupil.is_none());
+        assert_eq!(sens.pupil_geometry().unwrap().pixel_size, 4. / 7.);
+
+        let sens = sens.with_pupil_geometry(PupilGeometry::new(8, 0.5));
+        let sens: OpticalSensitivities =
+            OpticalSensitivities::from_bytes(&sens.to_bytes().unwrap()).unwrap();
+        assert_eq!(sens.pupil_geometry().unwrap(), PupilGeometry::new(8, 0.5));
+
+        bytes[MAGIC.len()] = FORMAT_VERSION as u8 + 1;
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&bytes),
+            Err(LinearOpticalModelError::SensitivityFormat(v)) if v == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn version_2() {
+        let sens = OpticalSensitivities::synthetic();
+        let payload = bincode::serialize(&sens).unwrap();
+        let metadata = (
+            Some(8usize),
+            Some(4f64),
+            None::<f64>,
+            None::<(f64, f64)>,
+            None::<(f64, f64)>,
+            "units".to_string(),
+            Provenance::default(),
+            Some(PupilGeometry::new(8, 0.5)),
+        );
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &(2u32, 84usize, metadata, checksum(&payload)))
+            .unwrap();
+        bytes.extend(payload);
+        let sens: OpticalSensitivities = OpticalSensitivities::from_bytes(&bytes).unwrap();
+        let metadata = sens.metadata().unwrap();
+        assert_eq!(metadata.pupil, Some(PupilGeometry::new(8, 0.5)));
+        assert!(metadata.zernike.is_none());
+    }
+
+    #[test]
+    fn wavefront_length() {
+        let mut sens = OpticalSensitivities::synthetic();
+        if let Some(OpticalSensitivity::Wavefront(val)) = sens.0.first_mut() {
+            val.pop();
+        }
+        let bytes = sens.to_bytes().unwrap();
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&bytes),
+            Err(LinearOpticalModelError::SensitivityLength { n_dof: 84, .. })
+        ));
+    }
+
+    #[test]
+    fn zernike_length() {
+        let mut sens = OpticalSensitivities::synthetic();
+        sens.0
+            .push(OpticalSensitivity::Zernike(vec![0.; 3 * 84 - 1]));
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&sens.to_bytes().unwrap()),
+            Err(LinearOpticalModelError::SensitivityLength { n_dof: 84, .. })
+        ));
+        if let Some(OpticalSensitivity::Zernike(val)) = sens.0.last_mut() {
+            val.push(0.);
+        }
+        let sens = sens.with_metadata(SensitivitiesMetadata {
+            zernike: Some((ZernikeSupport::Segments, 3)),
+            ..Default::default()
+        });
+        assert!(matches!(
+            OpticalSensitivities::<84>::from_bytes(&sens.to_bytes().unwrap()),
+            Err(LinearOpticalModelError::SensitivityRows {
+                expected: 21,
+                found: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn legacy() {
+        let legacy = bincode::serialize(&OpticalSensitivities::synthetic().0).unwrap();
+        assert!(SensitivitiesHeader::from_bytes(&legacy).unwrap().is_none());
+        let sens: OpticalSensitivities = OpticalSensitivities::from_bytes(&legacy).unwrap();
+        assert!(sens.metadata().is_none());
+        assert_eq!(sens.len(), 6);
+        assert!(matches!(
+            OpticalSensitivities::<42>::from_bytes(&legacy),
+            Err(LinearOpticalModelError::SensitivityDof {
+                expected: 42,
+                found: 84
+            })
+        ));
+    }
+}
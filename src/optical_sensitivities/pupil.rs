@@ -0,0 +1,81 @@
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+/// Ordering of the pupil pixels in the flat wavefront and mask vectors
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum PupilOrientation {
+    /// The pixel index runs along x first: `k = i + j * side` with `(i,j)` the `(x,y)` pixel indices
+    #[default]
+    XFastest,
+    /// The pixel index runs along y first: `k = j + i * side` with `(i,j)` the `(x,y)` pixel indices
+    YFastest,
+}
+
+/// Sampling of the exit pupil on a square grid
+///
+/// The pupil is centered on the origin of the grid and the pupil maps are `[side,side]` matrices
+/// with the x axis along the rows and the y axis along the columns
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PupilGeometry {
+    /// Number of pixels along a side of the grid
+    pub side: usize,
+    /// Size of a pixel `[m]`
+    pub pixel_size: f64,
+    /// Ordering of the pixels
+    pub orientation: PupilOrientation,
+}
+impl PupilGeometry {
+    /// Creates a `[side,side]` grid with pixels of size `pixel_size` `[m]` and the default orientation
+    pub fn new(side: usize, pixel_size: f64) -> Self {
+        Self {
+            side,
+            pixel_size,
+            orientation: Default::default(),
+        }
+    }
+    /// Sets the pixels ordering
+    pub fn orientation(mut self, orientation: PupilOrientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+    /// Returns the number of pixels in the grid
+    pub fn n_pixel(&self) -> usize {
+        self.side * self.side
+    }
+    /// Returns the `(x,y)` indices of the pixel `k`
+    pub fn indices(&self, k: usize) -> (usize, usize) {
+        match self.orientation {
+            PupilOrientation::XFastest => (k % self.side, k / self.side),
+            PupilOrientation::YFastest => (k / self.side, k % self.side),
+        }
+    }
+    /// Returns the index of the pixel with `(x,y)` indices `(i,j)`
+    pub fn index(&self, i: usize, j: usize) -> usize {
+        match self.orientation {
+            PupilOrientation::XFastest => i + j * self.side,
+            PupilOrientation::YFastest => j + i * self.side,
+        }
+    }
+    /// Returns the `(x,y)` coordinates `[m]` of the pixel `k`
+    pub fn xy(&self, k: usize) -> (f64, f64) {
+        let c = 0.5 * (self.side as f64 - 1.);
+        let (i, j) = self.indices(k);
+        (
+            (i as f64 - c) * self.pixel_size,
+            (j as f64 - c) * self.pixel_size,
+        )
+    }
+    /// Returns the `(x,y)` coordinates `[m]` of all the pixels
+    pub fn coordinates(&self) -> Vec<(f64, f64)> {
+        (0..self.n_pixel()).map(|k| self.xy(k)).collect()
+    }
+    /// Reshapes a flat pupil vector into a `[side,side]` map
+    pub fn to_map(&self, data: &[f64]) -> na::DMatrix<f64> {
+        match self.orientation {
+            PupilOrientation::XFastest => {
+                na::DMatrix::from_column_slice(self.side, self.side, data)
+            }
+            PupilOrientation::YFastest => na::DMatrix::from_row_slice(self.side, self.side, data),
+        }
+    }
+}
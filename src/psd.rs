@@ -0,0 +1,242 @@
+//! # Power spectral densities propagation
+//!
+//! Propagation of the rigid body motions cross-spectral density matrices `C(f)` to the
+//! optical metrics power spectral densities as the diagonal of `S C(f) Sᵀ`
+
+use nalgebra as na;
+use num_complex::Complex;
+
+use crate::{LinearOpticalModelError, OpticalSensitivities, OpticalSensitivity, Result, LOM};
+
+/// Cross-spectral density matrices of the rigid body motions
+///
+/// The `[N,N]` matrices rows and columns are given in the same order than [RigidBodyMotions](crate::RigidBodyMotions) rows,
+/// the spectral densities are one-sided in `[unit^2/Hz]`
+#[derive(Debug, Clone)]
+pub struct RbmCsd<const N: usize = 84> {
+    frequencies: Vec<f64>,
+    csd: Vec<na::DMatrix<Complex<f64>>>,
+}
+impl<const N: usize> RbmCsd<N> {
+    /// Creates the rigid body motions cross-spectral densities from one `[N,N]` matrix per frequency
+    ///
+    /// The frequencies `[Hz]` must be sorted in increasing order and there must be at least one frequency
+    pub fn new(frequencies: Vec<f64>, csd: Vec<na::DMatrix<Complex<f64>>>) -> Result<Self> {
+        if frequencies.is_empty()
+            || frequencies.len() != csd.len()
+            || frequencies.windows(2).any(|f| f[1] <= f[0])
+        {
+            return Err(LinearOpticalModelError::CsdFrequencies {
+                n_frequency: frequencies.len(),
+                n_csd: csd.len(),
+            });
+        }
+        if let Some(c) = csd.iter().find(|c| c.shape() != (N, N)) {
+            return Err(LinearOpticalModelError::CovarianceShape {
+                expected: N,
+                found: c.shape(),
+            });
+        }
+        Ok(Self { frequencies, csd })
+    }
+    /// Creates the rigid body motions cross-spectral densities from real valued `[N,N]` matrices
+    pub fn from_real(frequencies: Vec<f64>, csd: Vec<na::DMatrix<f64>>) -> Result<Self> {
+        Self::new(
+            frequencies,
+            csd.into_iter().map(|c| c.map(Complex::from)).collect(),
+        )
+    }
+    /// Returns the frequencies `[Hz]`
+    pub fn frequencies(&self) -> &[f64] {
+        &self.frequencies
+    }
+    /// Returns the cross-spectral density matrices
+    pub fn csd(&self) -> &[na::DMatrix<Complex<f64>>] {
+        &self.csd
+    }
+}
+
+/// Power spectral densities of an optical metric
+#[derive(Debug, Clone)]
+pub struct OpticalPsd {
+    name: String,
+    frequencies: Vec<f64>,
+    // `[n_item,n_frequency]` power spectral densities
+    psd: na::DMatrix<f64>,
+}
+impl OpticalPsd {
+    pub(crate) fn new(name: String, frequencies: Vec<f64>, psd: na::DMatrix<f64>) -> Self {
+        Self {
+            name,
+            frequencies,
+            psd,
+        }
+    }
+    /// Returns the name of the optical metric
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Returns the frequencies `[Hz]`
+    pub fn frequencies(&self) -> &[f64] {
+        &self.frequencies
+    }
+    /// Returns the number of items of the optical metric
+    pub fn n_item(&self) -> usize {
+        self.psd.nrows()
+    }
+    /// Returns the power spectral density of the `i`th item of the optical metric
+    pub fn psd(&self, i: usize) -> Vec<f64> {
+        self.psd.row(i).iter().cloned().collect()
+    }
+    /// Returns the power spectral densities `[n_item,n_frequency]` matrix
+    pub fn matrix(&self) -> &na::DMatrix<f64> {
+        &self.psd
+    }
+    /// Returns the `[n_item,n_frequency]` cumulative power spectral densities
+    ///
+    /// The last column is the variance of each item
+    pub fn cumulative(&self) -> na::DMatrix<f64> {
+        let mut cumulative = na::DMatrix::<f64>::zeros(self.psd.nrows(), self.psd.ncols());
+        for j in 1..self.psd.ncols() {
+            let df = self.frequencies[j] - self.frequencies[j - 1];
+            let step = (self.psd.column(j) + self.psd.column(j - 1)) * (0.5 * df);
+            let previous = cumulative.column(j - 1).into_owned();
+            cumulative.set_column(j, &(previous + step));
+        }
+        cumulative
+    }
+    /// Returns the RMS of each item integrated over all the frequencies
+    pub fn rms(&self) -> Vec<f64> {
+        let (lo, hi) = (
+            self.frequencies.first().cloned().unwrap_or_default(),
+            self.frequencies.last().cloned().unwrap_or_default(),
+        );
+        self.band_rms(lo, hi)
+    }
+    /// Returns the RMS of each item integrated over the frequency band `[f_lo,f_hi]`
+    ///
+    /// The power spectral densities are linearly interpolated at the band edges
+    pub fn band_rms(&self, f_lo: f64, f_hi: f64) -> Vec<f64> {
+        self.psd
+            .row_iter()
+            .map(|psd| integrate(&self.frequencies, psd.iter(), f_lo, f_hi).sqrt())
+            .collect()
+    }
+}
+
+/// Trapezoidal integration of `y(x)` within `[lo,hi]`
+fn integrate<'a>(x: &[f64], y: impl Iterator<Item = &'a f64>, lo: f64, hi: f64) -> f64 {
+    let y: Vec<f64> = y.cloned().collect();
+    x.windows(2)
+        .zip(y.windows(2))
+        .map(|(x, y)| {
+            let (a, b) = (x[0].max(lo), x[1].min(hi));
+            if b <= a {
+                return 0f64;
+            }
+            let interp = |u: f64| y[0] + (y[1] - y[0]) * (u - x[0]) / (x[1] - x[0]);
+            0.5 * (b - a) * (interp(a) + interp(b))
+        })
+        .sum()
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Returns the power spectral densities of the optical metric of the same variant than `index`
+    pub fn psd(&self, index: OpticalSensitivity<N>, csd: &RbmCsd<N>) -> Result<OpticalPsd> {
+        let sens = self.try_get(index)?;
+        let s: na::DMatrix<f64> = match sens {
+            OpticalSensitivity::SegmentMask(_) | OpticalSensitivity::PupilMask(_) => {
+                return Err(LinearOpticalModelError::NotAMeasurement(sens.to_string()))
+            }
+            _ => sens.into(),
+        };
+        let s_c = s.map(Complex::from);
+        let mut psd = na::DMatrix::<f64>::zeros(s.nrows(), csd.frequencies.len());
+        for (mut p, c) in psd.column_iter_mut().zip(&csd.csd) {
+            let s_c_c = &s_c * c;
+            p.iter_mut()
+                .zip(s_c_c.row_iter().zip(s.row_iter()))
+                .for_each(|(p, (sc, s))| {
+                    *p = sc.iter().zip(s.iter()).map(|(sc, s)| sc.re * s).sum()
+                });
+        }
+        Ok(OpticalPsd::new(
+            sens.to_string(),
+            csd.frequencies.clone(),
+            psd,
+        ))
+    }
+}
+
+impl LOM {
+    /// Returns the tip and tilt power spectral densities in `[rd^2/Hz]`
+    pub fn tiptilt_psd(&self, csd: &RbmCsd) -> Result<OpticalPsd> {
+        self.sensitivities()
+            .psd(OpticalSensitivity::TipTilt(vec![]), csd)
+    }
+    /// Returns the segment tip and tilt power spectral densities in `[rd^2/Hz]`
+    ///
+    /// The 7 segment tips are followed by the 7 segment tilts
+    pub fn segment_tiptilt_psd(&self, csd: &RbmCsd) -> Result<OpticalPsd> {
+        self.sensitivities()
+            .psd(OpticalSensitivity::SegmentTipTilt(vec![]), csd)
+    }
+    /// Returns the segment piston power spectral densities in `[m^2/Hz]`
+    pub fn segment_piston_psd(&self, csd: &RbmCsd) -> Result<OpticalPsd> {
+        self.sensitivities()
+            .psd(OpticalSensitivity::SegmentPiston(vec![]), csd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RbmCovariance;
+
+    #[test]
+    fn psd() {
+        let lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        // white noise from 0 to 10Hz
+        let frequencies: Vec<f64> = (0..=100).map(|i| i as f64 * 0.1).collect();
+        let c = na::DMatrix::<f64>::from_fn(84, 84, |i, j| {
+            if i == j {
+                1e-12 * (1. + i as f64)
+            } else if i.abs_diff(j) == 1 {
+                2e-13
+            } else {
+                0f64
+            }
+        });
+        let csd = RbmCsd::from_real(frequencies.clone(), vec![c.clone(); 101]).unwrap();
+        let covariance = RbmCovariance::new(c * 10.).unwrap();
+        for index in [
+            OpticalSensitivity::TipTilt(vec![]),
+            OpticalSensitivity::SegmentTipTilt(vec![]),
+            OpticalSensitivity::SegmentPiston(vec![]),
+        ] {
+            let psd = lom.sensitivities().psd(index.clone(), &csd).unwrap();
+            let std = lom.sensitivities().std(index, &covariance).unwrap();
+            assert_eq!(psd.n_item(), std.len());
+            psd.rms()
+                .iter()
+                .zip(&std)
+                .for_each(|(a, b)| assert!((a - b).abs() <= 1e-9 * b.max(1e-15)));
+            psd.band_rms(2.5, 5.)
+                .iter()
+                .zip(&std)
+                .for_each(|(a, b)| assert!((a - b * 0.5).abs() <= 1e-9 * b.max(1e-15)));
+        }
+        assert_eq!(lom.segment_piston_psd(&csd).unwrap().psd(0).len(), 101);
+        assert!(RbmCsd::<84>::from_real(frequencies, vec![]).is_err());
+        assert!(matches!(
+            RbmCsd::<84>::new(vec![], vec![]),
+            Err(LinearOpticalModelError::CsdFrequencies {
+                n_frequency: 0,
+                n_csd: 0
+            })
+        ));
+    }
+}
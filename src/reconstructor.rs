@@ -0,0 +1,255 @@
+//! # Rigid body motions reconstruction
+//!
+//! Inverse of the linear optical model: estimation of M1 and M2 rigid body motions from
+//! optical measurements like tip-tilt, segment tip-tilt, segment piston or wavefront
+
+use nalgebra as na;
+
+use crate::{
+    analysis::full_svd, from_opticals, LinearOpticalModelError, OpticalSensitivity, Result,
+    RigidBodyMotions,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReconstructorError {
+    #[error("no sensitivity given to the reconstructor")]
+    Empty,
+    #[error("{0} sensitivity is empty")]
+    EmptySensitivity(String),
+    #[error("expected {expected} measurements per sample, found {found}")]
+    Measurements { expected: usize, found: usize },
+    #[error("expected {expected} optical metrics, found {found}")]
+    Optics { expected: usize, found: usize },
+}
+
+/// Regularization of the sensitivity matrix inverse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Regularization {
+    /// Truncated SVD: discards the singular values smaller than `threshold` times the largest singular value
+    TruncatedSvd { threshold: f64 },
+    /// Tikhonov: the singular values `s` are inverted as `s/(s^2+lambda^2)`
+    Tikhonov { lambda: f64 },
+}
+impl Default for Regularization {
+    fn default() -> Self {
+        Regularization::TruncatedSvd { threshold: 1e-6 }
+    }
+}
+
+/// Rigid body motions reconstructor
+///
+/// Regularized inverse of the sensitivity matrices stacked with [from_opticals]
+#[derive(Debug, Clone)]
+pub struct Reconstructor<const N: usize = 84> {
+    // number of measurements of each sensitivity
+    n_rows: Vec<usize>,
+    // singular values in descending order
+    singular_values: Vec<f64>,
+    // `[N,m]` reconstruction matrix
+    matrix: na::DMatrix<f64>,
+    // `[N,k]` rigid body motion modes not seen by the measurements
+    unobservable: na::DMatrix<f64>,
+}
+impl<const N: usize> Reconstructor<N> {
+    /// Creates a reconstructor from the measurements `senses`
+    ///
+    /// The rigid body motion modes with singular values below the truncation threshold
+    /// (or below the numerical precision for [Tikhonov](Regularization::Tikhonov)) are reported as unobservable
+    pub fn new(senses: &[OpticalSensitivity<N>], regularization: Regularization) -> Result<Self> {
+        let n_rows = senses
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::Wavefront(val)
+                | OpticalSensitivity::TipTilt(val)
+                | OpticalSensitivity::SegmentTipTilt(val)
+                | OpticalSensitivity::SegmentPiston(val) => match val.len() / N {
+                    0 => Err(ReconstructorError::EmptySensitivity(s.to_string()).into()),
+                    n => Ok(n),
+                },
+                _ => Err(LinearOpticalModelError::NotAMeasurement(s.to_string())),
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        if n_rows.is_empty() {
+            return Err(ReconstructorError::Empty.into());
+        }
+        let d = from_opticals(senses);
+        let m = d.nrows();
+        let (u, singular_values, v) = full_svd(d);
+        let s_max = singular_values[0];
+        let tolerance = match regularization {
+            Regularization::TruncatedSvd { threshold } => threshold * s_max,
+            Regularization::Tikhonov { .. } => f64::EPSILON * m.max(N) as f64 * s_max,
+        };
+        let gains = na::DVector::from_iterator(
+            N,
+            singular_values.iter().map(|&s| match regularization {
+                Regularization::TruncatedSvd { .. } if s > tolerance => s.recip(),
+                Regularization::TruncatedSvd { .. } => 0f64,
+                Regularization::Tikhonov { lambda } => s / (s * s + lambda * lambda),
+            }),
+        );
+        let matrix = &v * na::DMatrix::from_diagonal(&gains) * u.rows(0, m).transpose();
+        let unobservable_idx: Vec<usize> = singular_values
+            .iter()
+            .enumerate()
+            .filter(|(_, &s)| s <= tolerance)
+            .map(|(i, _)| i)
+            .collect();
+        let unobservable = v.select_columns(unobservable_idx.iter());
+        Ok(Self {
+            n_rows,
+            singular_values,
+            matrix,
+            unobservable,
+        })
+    }
+    /// Returns the singular values of the stacked sensitivity matrices in descending order
+    pub fn singular_values(&self) -> &[f64] {
+        &self.singular_values
+    }
+    /// Returns the `[N,m]` reconstruction matrix
+    pub fn matrix(&self) -> &na::DMatrix<f64> {
+        &self.matrix
+    }
+    /// Returns the `[N,k]` matrix of unobservable rigid body motion modes
+    ///
+    /// The modes are orthonormal and given in the same order than [RigidBodyMotions] rows
+    pub fn unobservable_modes(&self) -> &na::DMatrix<f64> {
+        &self.unobservable
+    }
+    /// Returns the number of measurements per sample
+    pub fn n_measurement(&self) -> usize {
+        self.n_rows.iter().sum()
+    }
+    /// Reconstructs the `[N,n]` rigid body motions from the `[m,n]` measurements
+    pub fn reconstruct(&self, measurements: &na::DMatrix<f64>) -> Result<na::DMatrix<f64>> {
+        if measurements.nrows() != self.n_measurement() {
+            return Err(ReconstructorError::Measurements {
+                expected: self.n_measurement(),
+                found: measurements.nrows(),
+            }
+            .into());
+        }
+        Ok(&self.matrix * measurements)
+    }
+    /// Stacks the optical metrics time series into the `[m,n]` measurements matrix
+    ///
+    /// The optical metrics are given in the same order than the sensitivities of the reconstructor
+    /// and with the same layout than the [LOM](crate::LOM) outputs
+    pub fn stack(&self, optics: &[&[f64]]) -> Result<na::DMatrix<f64>> {
+        if optics.len() != self.n_rows.len() {
+            return Err(ReconstructorError::Optics {
+                expected: self.n_rows.len(),
+                found: optics.len(),
+            }
+            .into());
+        }
+        let n_sample = optics[0].len() / self.n_rows[0];
+        for (o, &n) in optics.iter().zip(&self.n_rows) {
+            if o.len() != n * n_sample {
+                return Err(ReconstructorError::Measurements {
+                    expected: n,
+                    found: o.len() / n_sample.max(1),
+                }
+                .into());
+            }
+        }
+        let data: Vec<f64> = (0..n_sample)
+            .flat_map(|k| {
+                optics
+                    .iter()
+                    .zip(&self.n_rows)
+                    .flat_map(move |(o, &n)| o[k * n..(k + 1) * n].iter().cloned())
+            })
+            .collect();
+        Ok(na::DMatrix::from_vec(self.n_measurement(), n_sample, data))
+    }
+}
+
+impl RigidBodyMotions {
+    /// Estimates M1 and M2 rigid body motions from optical metrics time series
+    ///
+    /// The optical metrics are given in the same order than the sensitivities of the [Reconstructor]
+    ///
+    /// # Example
+    /// ```no_run
+    /// use gmt_lom::{Reconstructor, Regularization, OpticalSensitivity, RigidBodyMotions, LOM};
+    ///
+    /// let lom = LOM::builder().build()?;
+    /// let senses = [
+    ///     lom.sensitivities()[OpticalSensitivity::SegmentTipTilt(vec![])].clone(),
+    ///     lom.sensitivities()[OpticalSensitivity::SegmentPiston(vec![])].clone(),
+    /// ];
+    /// let reconstructor = Reconstructor::new(&senses, Regularization::default())?;
+    /// let rbm = RigidBodyMotions::from_optics(
+    ///     &reconstructor,
+    ///     &[&lom.segment_tiptilt(), &lom.segment_piston()],
+    /// )?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_optics(reconstructor: &Reconstructor, optics: &[&[f64]]) -> Result<Self> {
+        let measurements = reconstructor.stack(optics)?;
+        Ok(reconstructor.reconstruct(&measurements)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpticalSensitivities, LOM};
+
+    #[test]
+    fn from_optics() {
+        let sens = OpticalSensitivities::synthetic();
+        let rbm = na::DMatrix::<f64>::from_fn(84, 3, |i, j| 1e-7 * ((i * 7 + j * 3) % 11) as f64);
+        let mut lom = LOM::builder()
+            .optical_sensitivities(sens.clone())
+            .build()
+            .unwrap();
+        lom.rbm = rbm.clone().into();
+        let senses = [
+            sens[OpticalSensitivity::Wavefront(vec![])].clone(),
+            sens[OpticalSensitivity::SegmentPiston(vec![])].clone(),
+        ];
+        let reconstructor = Reconstructor::new(&senses, Regularization::default()).unwrap();
+        assert!(reconstructor.unobservable_modes().ncols() > 0);
+        let estimate = RigidBodyMotions::from_optics(
+            &reconstructor,
+            &[&lom.masked_wavefront(), &lom.segment_piston()],
+        )
+        .unwrap();
+        // the estimate matches the observable part of the rigid body motions
+        let d = from_opticals(&senses);
+        let residual = &d * (estimate.data() - &rbm);
+        assert!(residual.norm() < 1e-12 * (&d * &rbm).norm());
+        let leak = reconstructor.unobservable_modes().transpose() * estimate.data();
+        assert!(leak.norm() < 1e-12);
+
+        let reconstructor =
+            Reconstructor::new(&senses, Regularization::Tikhonov { lambda: 1e-3 }).unwrap();
+        let estimate = RigidBodyMotions::from_optics(
+            &reconstructor,
+            &[&lom.masked_wavefront(), &lom.segment_piston()],
+        )
+        .unwrap();
+        let residual = &d * (estimate.data() - &rbm);
+        assert!(residual.norm() < 1e-3 * (&d * &rbm).norm());
+
+        assert!(matches!(
+            Reconstructor::new(
+                &[sens[OpticalSensitivity::PupilMask(vec![])].clone()],
+                Regularization::default()
+            ),
+            Err(LinearOpticalModelError::NotAMeasurement(_))
+        ));
+        assert!(matches!(
+            Reconstructor::new(
+                &[OpticalSensitivity::<84>::SegmentPiston(vec![])],
+                Regularization::default()
+            ),
+            Err(LinearOpticalModelError::Reconstructor(
+                ReconstructorError::EmptySensitivity(_)
+            ))
+        ));
+    }
+}
@@ -19,8 +19,28 @@ pub enum RigidBodyMotionsError {
     FromTable(#[from] TableError),
     #[error("failed to save rigid body motions to an Arrow record")]
     ToRecord(#[from] ToRecord),
+    #[error("rigid body motions sample #{0} has missing values")]
+    MissingValues(usize),
+    #[error("rigid body motions column {label} has unsupported type {found}")]
+    ColumnType { label: String, found: String },
+    #[error("rigid body motions column {0} not found in CSV header")]
+    CsvColumn(String),
+    #[error("invalid value {value:?} in CSV record #{record}")]
+    CsvValue { record: usize, value: String },
+    #[error("unknown units: {0}")]
+    Units(String),
+    #[error("rigid body motions column {label} has {found} elements at row #{row} instead of {expected}")]
+    RowLength {
+        label: String,
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
 }
 
+/// Labels of the time column `[s]`, in order of precedence
+pub const TIME_LABELS: [&str; 3] = ["time", "Time", "t"];
+
 /// GMT M1 and M2 segment rigid body motions
 ///
 /// The rigid body motions are saved in a matrix with 84 rows and as many columns as the number of time steps
@@ -33,6 +53,8 @@ pub struct RigidBodyMotions {
     time: Option<Vec<f64>>,
     // `[84,n]` matrix of rigid body motion
     data: nalgebra::DMatrix<f64>,
+    // missing values handling at import
+    report: ImportReport,
     pub format: Formatting,
 }
 impl AsMut<nalgebra::DMatrix<f64>> for RigidBodyMotions {
@@ -54,6 +76,7 @@ impl Default for RigidBodyMotions {
             sampling_frequency: None,
             time: None,
             data: nalgebra::DMatrix::<f64>::zeros(84, 1),
+            report: Default::default(),
             format: Formatting::AdHoc,
         }
     }
@@ -125,12 +148,13 @@ impl FromIterator<(Vec<Vec<f64>>, Vec<Vec<f64>>)> for RigidBodyMotions {
                     m2.into_iter().flatten().collect::<Vec<f64>>(),
                 )
             })
-            .flat_map(|(m1, m2)| m1.into_iter().chain(m2.into_iter()).collect::<Vec<f64>>())
+            .flat_map(|(m1, m2)| m1.into_iter().chain(m2).collect::<Vec<f64>>())
             .collect();
         Self {
             sampling_frequency: None,
             time: None,
             data: nalgebra::DMatrix::from_vec(84, data.len() / 84, data),
+            report: Default::default(),
             format: Formatting::AdHoc,
         }
     }
@@ -173,11 +197,35 @@ impl<'a> FromIterator<(&'a [f64], &'a [f64])> for RigidBodyMotions {
             sampling_frequency: None,
             time: None,
             data: nalgebra::DMatrix::from_vec(84, data.len() / 84, data),
+            report: Default::default(),
             format: Formatting::AdHoc,
         }
     }
 }
 impl RigidBodyMotions {
+    /// Returns the label `Mi_Sj_[TR][xyz]` of each of the 84 rows of the rigid body motions matrix
+    pub fn labels() -> Vec<String> {
+        (1..=2)
+            .flat_map(|i| {
+                (1..=7).flat_map(move |j| {
+                    ["Tx", "Ty", "Tz", "Rx", "Ry", "Rz"]
+                        .into_iter()
+                        .map(move |dof| format!("M{i}_S{j}_{dof}"))
+                })
+            })
+            .collect()
+    }
+    /// Returns the sampling frequency `[Hz]` if known
+    pub fn sampling_frequency(&self) -> Option<f64> {
+        self.sampling_frequency
+    }
+    /// Sets the sampling frequency `[Hz]`
+    pub fn with_sampling_frequency(self, sampling_frequency: f64) -> Self {
+        Self {
+            sampling_frequency: Some(sampling_frequency),
+            ..self
+        }
+    }
     /// Returns the time vector
     pub fn time(&self) -> Vec<f64> {
         if let Some(time) = &self.time {
@@ -187,6 +235,16 @@ impl RigidBodyMotions {
             (0..self.data.ncols()).map(|i| tau * i as f64).collect()
         }
     }
+    /// Returns the indices of the samples dropped at import
+    ///
+    /// The time vector accounts for the dropped samples
+    pub fn dropped_samples(&self) -> &[usize] {
+        &self.report.dropped
+    }
+    /// Returns the summary of the missing values handling at import
+    pub fn import_report(&self) -> &ImportReport {
+        &self.report
+    }
     /// Returns the number of rigidbody motions sample `n`
     pub fn len(&self) -> usize {
         self.data.ncols()
@@ -217,5 +275,11 @@ impl RigidBodyMotions {
     }
 }
 
+mod delimited;
+mod import;
 #[cfg(feature = "apache")]
 pub mod parquet;
+pub use delimited::{AngleUnit, CsvLayout, CsvOptions, LengthUnit};
+pub use import::{ImportOptions, ImportReport, MissingPolicy};
+#[cfg(feature = "apache")]
+pub use parquet::{ArrowLayout, SAMPLING_FREQUENCY_KEY};
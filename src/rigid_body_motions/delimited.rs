@@ -0,0 +1,466 @@
+//! Rigid body motions CSV import and export
+//!
+//! The header of a rigid body motions column is the label of the degree of freedom as given by [RigidBodyMotions::labels],
+//! optionally followed by the units within brackets, e.g. `M1_S3_Rx [mas]`
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use skyangle::Conversion;
+
+use super::{
+    import::{median_sampling_frequency, Sample},
+    ImportOptions, RigidBodyMotions, RigidBodyMotionsError, TIME_LABELS,
+};
+use crate::Result;
+
+/// Units of the rigid body motions translations
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LengthUnit {
+    #[default]
+    Meter,
+    Millimeter,
+    Micrometer,
+    Nanometer,
+}
+impl LengthUnit {
+    /// Returns the unit in `[m]`
+    pub fn scale(&self) -> f64 {
+        match self {
+            Self::Meter => 1f64,
+            Self::Millimeter => 1e-3,
+            Self::Micrometer => 1e-6,
+            Self::Nanometer => 1e-9,
+        }
+    }
+    /// Returns the unit symbol
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Meter => "m",
+            Self::Millimeter => "mm",
+            Self::Micrometer => "um",
+            Self::Nanometer => "nm",
+        }
+    }
+}
+impl FromStr for LengthUnit {
+    type Err = RigidBodyMotionsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "m" => Ok(Self::Meter),
+            "mm" => Ok(Self::Millimeter),
+            "um" | "µm" => Ok(Self::Micrometer),
+            "nm" => Ok(Self::Nanometer),
+            _ => Err(RigidBodyMotionsError::Units(s.to_string())),
+        }
+    }
+}
+
+/// Units of the rigid body motions rotations
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AngleUnit {
+    #[default]
+    Radian,
+    Milliradian,
+    Microradian,
+    Arcsec,
+    Milliarcsec,
+}
+impl AngleUnit {
+    /// Returns the unit in `[rd]`
+    pub fn scale(&self) -> f64 {
+        match self {
+            Self::Radian => 1f64,
+            Self::Milliradian => 1e-3,
+            Self::Microradian => 1e-6,
+            Self::Arcsec => 1f64.from_arcsec(),
+            Self::Milliarcsec => 1f64.from_mas(),
+        }
+    }
+    /// Returns the unit symbol
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Radian => "rd",
+            Self::Milliradian => "mrd",
+            Self::Microradian => "urd",
+            Self::Arcsec => "arcsec",
+            Self::Milliarcsec => "mas",
+        }
+    }
+}
+impl FromStr for AngleUnit {
+    type Err = RigidBodyMotionsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "rd" | "rad" => Ok(Self::Radian),
+            "mrd" | "mrad" => Ok(Self::Milliradian),
+            "urd" | "urad" | "µrad" => Ok(Self::Microradian),
+            "arcsec" => Ok(Self::Arcsec),
+            "mas" => Ok(Self::Milliarcsec),
+            _ => Err(RigidBodyMotionsError::Units(s.to_string())),
+        }
+    }
+}
+
+/// Layout of the rigid body motions in a CSV file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CsvLayout {
+    /// One row per sample with a time column followed by one column per degree of freedom
+    #[default]
+    Columns,
+    /// One row per degree of freedom, the header being the time of each sample
+    Rows,
+}
+
+/// Rigid body motions CSV import and export options
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    layout: CsvLayout,
+    length: LengthUnit,
+    angle: AngleUnit,
+    delimiter: u8,
+    import: ImportOptions,
+}
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            layout: Default::default(),
+            length: Default::default(),
+            angle: Default::default(),
+            delimiter: b',',
+            import: Default::default(),
+        }
+    }
+}
+impl CsvOptions {
+    /// Sets the layout of the rigid body motions
+    pub fn layout(mut self, layout: CsvLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+    /// Sets the units of the translations and of the rotations
+    ///
+    /// At import, the units given in the header take precedence
+    pub fn units(mut self, length: LengthUnit, angle: AngleUnit) -> Self {
+        self.length = length;
+        self.angle = angle;
+        self
+    }
+    /// Sets the field delimiter
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+    /// Sets the import options
+    pub fn import(mut self, import: ImportOptions) -> Self {
+        self.import = import;
+        self
+    }
+    /// Returns the scale from the units of the `i`th degree of freedom to `[m]` or `[rd]`
+    fn scale(
+        &self,
+        i: usize,
+        units: Option<&str>,
+    ) -> std::result::Result<f64, RigidBodyMotionsError> {
+        Ok(match (i % 6 < 3, units) {
+            (true, Some(units)) => units.parse::<LengthUnit>()?.scale(),
+            (true, None) => self.length.scale(),
+            (false, Some(units)) => units.parse::<AngleUnit>()?.scale(),
+            (false, None) => self.angle.scale(),
+        })
+    }
+    /// Returns the units symbol of the `i`th degree of freedom
+    fn symbol(&self, i: usize) -> &'static str {
+        if i % 6 < 3 {
+            self.length.symbol()
+        } else {
+            self.angle.symbol()
+        }
+    }
+}
+
+/// Splits a header into the label and the optional units within brackets
+fn split_units(header: &str) -> (&str, Option<&str>) {
+    header
+        .trim()
+        .strip_suffix(']')
+        .and_then(|h| h.rsplit_once('['))
+        .map_or((header.trim(), None), |(label, units)| {
+            (label.trim(), Some(units.trim()))
+        })
+}
+
+/// Returns the position of the 84 degrees of freedom within `labels`
+///
+/// The labels are matched against [RigidBodyMotions::labels], if none is found
+/// and there are exactly 84 labels, they are taken in order
+fn resolve(labels: &[&str]) -> std::result::Result<Vec<usize>, RigidBodyMotionsError> {
+    let known = RigidBodyMotions::labels();
+    if known.iter().any(|k| labels.contains(&k.as_str())) {
+        known
+            .iter()
+            .map(|k| {
+                labels
+                    .iter()
+                    .position(|l| l == k)
+                    .ok_or_else(|| RigidBodyMotionsError::CsvColumn(k.clone()))
+            })
+            .collect()
+    } else if labels.len() == 84 {
+        Ok((0..84).collect())
+    } else {
+        Err(RigidBodyMotionsError::CsvColumn(known[0].clone()))
+    }
+}
+
+/// Parses a CSV value, empty values are missing
+fn parse(record: usize, value: &str) -> std::result::Result<Option<f64>, RigidBodyMotionsError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse::<f64>()
+        .map(Some)
+        .map_err(|_| RigidBodyMotionsError::CsvValue {
+            record,
+            value: value.to_string(),
+        })
+}
+
+impl RigidBodyMotions {
+    /// Creates a [RigidBodyMotions] from a CSV file
+    ///
+    /// If there is no time column in the [Columns](CsvLayout::Columns) layout, the time is the sample index.
+    /// The header of the [Rows](CsvLayout::Rows) layout must start with one of the [TIME_LABELS]
+    pub fn from_csv<P: AsRef<Path>>(path: P, options: &CsvOptions) -> Result<Self> {
+        Self::from_csv_reader(File::open(path).map_err(csv::Error::from)?, options)
+    }
+    /// Creates a [RigidBodyMotions] from a CSV reader
+    pub fn from_csv_reader<R: Read>(reader: R, options: &CsvOptions) -> Result<Self> {
+        let records = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(options.delimiter)
+            .from_reader(reader)
+            .into_records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let Some((header, records)) = records.split_first() else {
+            return Err(RigidBodyMotionsError::CsvColumn("time".to_string()).into());
+        };
+        let (samples, sampling_frequency): (Vec<Sample>, _) = match options.layout {
+            CsvLayout::Columns => {
+                let header: Vec<_> = header.iter().map(split_units).collect();
+                let time_column = TIME_LABELS
+                    .iter()
+                    .find_map(|t| header.iter().position(|(label, _)| label == t));
+                let columns: Vec<usize> = (0..header.len())
+                    .filter(|&j| Some(j) != time_column)
+                    .collect();
+                let labels: Vec<&str> = columns.iter().map(|&j| header[j].0).collect();
+                let dofs = resolve(&labels)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, k)| Ok((columns[k], options.scale(i, header[columns[k]].1)?)))
+                    .collect::<std::result::Result<Vec<_>, RigidBodyMotionsError>>()?;
+                let samples = records
+                    .iter()
+                    .enumerate()
+                    .map(|(k, record)| {
+                        let time = match time_column {
+                            Some(j) => parse(k, &record[j])?,
+                            None => Some(k as f64),
+                        };
+                        let values = dofs
+                            .iter()
+                            .map(|&(j, scale)| Ok(parse(k, &record[j])?.map(|x| x * scale)))
+                            .collect::<std::result::Result<Vec<_>, RigidBodyMotionsError>>()?;
+                        Ok((time, Some(values)))
+                    })
+                    .collect::<std::result::Result<Vec<Sample>, RigidBodyMotionsError>>()?;
+                let time: Vec<_> = samples.iter().map(|(t, _)| *t).collect();
+                let sampling_frequency = time_column.and_then(|_| median_sampling_frequency(&time));
+                (samples, sampling_frequency)
+            }
+            CsvLayout::Rows => {
+                if !TIME_LABELS.contains(&split_units(&header[0]).0) {
+                    return Err(RigidBodyMotionsError::CsvColumn("time".to_string()).into());
+                }
+                let time = header
+                    .iter()
+                    .skip(1)
+                    .map(|t| parse(0, t))
+                    .collect::<std::result::Result<Vec<_>, RigidBodyMotionsError>>()?;
+                let rows: Vec<_> = records.iter().map(|r| split_units(&r[0])).collect();
+                let labels: Vec<&str> = rows.iter().map(|(label, _)| *label).collect();
+                let dofs = resolve(&labels)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, k)| Ok((k, options.scale(i, rows[k].1)?)))
+                    .collect::<std::result::Result<Vec<_>, RigidBodyMotionsError>>()?;
+                let n = header.len() - 1;
+                let samples = (0..n)
+                    .map(|k| {
+                        let values = dofs
+                            .iter()
+                            .map(|&(j, scale)| {
+                                Ok(parse(j + 1, &records[j][k + 1])?.map(|x| x * scale))
+                            })
+                            .collect::<std::result::Result<Vec<_>, RigidBodyMotionsError>>()?;
+                        Ok((time[k], Some(values)))
+                    })
+                    .collect::<std::result::Result<Vec<Sample>, RigidBodyMotionsError>>()?;
+                let sampling_frequency = median_sampling_frequency(&time);
+                (samples, sampling_frequency)
+            }
+        };
+        Ok(Self::from_samples(
+            samples,
+            sampling_frequency,
+            &options.import,
+        )?)
+    }
+    /// Writes the rigid body motions to a CSV file
+    pub fn to_csv<P: AsRef<Path>>(&self, path: P, options: &CsvOptions) -> Result<()> {
+        self.to_csv_writer(File::create(path).map_err(csv::Error::from)?, options)
+    }
+    /// Writes the rigid body motions to a CSV writer
+    pub fn to_csv_writer<W: Write>(&self, writer: W, options: &CsvOptions) -> Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(options.delimiter)
+            .from_writer(writer);
+        let labels: Vec<String> = Self::labels()
+            .into_iter()
+            .enumerate()
+            .map(|(i, label)| format!("{label} [{}]", options.symbol(i)))
+            .collect();
+        let scales: Vec<f64> = (0..84)
+            .map(|i| options.scale(i, None))
+            .collect::<std::result::Result<_, _>>()?;
+        let time = self.time();
+        match options.layout {
+            CsvLayout::Columns => {
+                writer.write_record(std::iter::once("time [s]".to_string()).chain(labels))?;
+                for (t, sample) in time.iter().zip(self.data.column_iter()) {
+                    writer.write_record(
+                        std::iter::once(t.to_string()).chain(
+                            sample
+                                .iter()
+                                .zip(&scales)
+                                .map(|(x, scale)| (x / scale).to_string()),
+                        ),
+                    )?;
+                }
+            }
+            CsvLayout::Rows => {
+                writer.write_record(
+                    std::iter::once("time [s]".to_string())
+                        .chain(time.iter().map(|t| t.to_string())),
+                )?;
+                for ((label, dof), scale) in
+                    labels.into_iter().zip(self.data.row_iter()).zip(&scales)
+                {
+                    writer.write_record(
+                        std::iter::once(label).chain(dof.iter().map(|x| (x / scale).to_string())),
+                    )?;
+                }
+            }
+        }
+        writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MissingPolicy;
+    use nalgebra as na;
+
+    #[test]
+    fn csv() {
+        let data = na::DMatrix::<f64>::from_fn(84, 4, |i, j| 1e-6 * (i as f64 - 0.5 * j as f64));
+        let rbm: RigidBodyMotions = data.clone().into();
+        for options in [
+            CsvOptions::default(),
+            CsvOptions::default()
+                .layout(CsvLayout::Rows)
+                .units(LengthUnit::Micrometer, AngleUnit::Milliarcsec)
+                .delimiter(b';'),
+        ] {
+            let mut buffer = vec![];
+            rbm.to_csv_writer(&mut buffer, &options).unwrap();
+            let rbm = RigidBodyMotions::from_csv_reader(buffer.as_slice(), &options).unwrap();
+            assert_eq!(rbm.len(), 4);
+            assert_eq!(rbm.sampling_frequency(), Some(1.));
+            assert!((rbm.data() - &data).abs().max() < 1e-18);
+        }
+
+        let mut csv = String::from("t,M1_S1_Tx [mm],M2_S1_Rx [arcsec]\n");
+        csv.push_str("0.0,1,1\n0.1,,2\n0.2,3,3\n");
+        assert!(matches!(
+            RigidBodyMotions::from_csv_reader(csv.as_bytes(), &CsvOptions::default()),
+            Err(crate::LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::CsvColumn(_)
+            ))
+        ));
+        let mut csv = String::from("t");
+        RigidBodyMotions::labels()
+            .iter()
+            .enumerate()
+            .for_each(|(i, label)| {
+                csv.push_str(&format!(",{label}"));
+                if i == 0 {
+                    csv.push_str(" [mm]");
+                }
+            });
+        csv.push('\n');
+        for (k, t) in ["0.0", "0.1", "0.2"].into_iter().enumerate() {
+            csv.push_str(t);
+            for i in 0..84 {
+                csv.push(',');
+                if !(k == 1 && i == 0) {
+                    csv.push_str(&(k + 1).to_string());
+                }
+            }
+            csv.push('\n');
+        }
+        let options = CsvOptions::default()
+            .units(LengthUnit::Meter, AngleUnit::Arcsec)
+            .import(ImportOptions::default().missing(MissingPolicy::Interpolate));
+        let rbm = RigidBodyMotions::from_csv_reader(csv.as_bytes(), &options).unwrap();
+        assert!((rbm.sampling_frequency().unwrap() - 10.).abs() < 1e-9);
+        assert_eq!(rbm.import_report().filled, vec![1]);
+        assert!((rbm.data()[(0, 1)] - 2e-3).abs() < 1e-15);
+        assert_eq!(rbm.data()[(1, 2)], 3.);
+        assert_eq!(rbm.data()[(3, 2)], 3f64.from_arcsec());
+
+        // time header of the rows layout
+        let options = CsvOptions::default().layout(CsvLayout::Rows);
+        let mut buffer = vec![];
+        RigidBodyMotions::from(data)
+            .to_csv_writer(&mut buffer, &options)
+            .unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let header = csv.lines().next().unwrap();
+        let bad_time = csv.replacen(header, "time [s],0,1,x,3", 1);
+        assert!(matches!(
+            RigidBodyMotions::from_csv_reader(bad_time.as_bytes(), &options),
+            Err(crate::LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::CsvValue { record: 0, .. }
+            ))
+        ));
+        let no_time = csv.replacen(header, "M1_S1_Tx,0,1,2,3", 1);
+        assert!(matches!(
+            RigidBodyMotions::from_csv_reader(no_time.as_bytes(), &options),
+            Err(crate::LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::CsvColumn(_)
+            ))
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! Missing values handling when importing rigid body motions
+
+use super::{RigidBodyMotions, RigidBodyMotionsError};
+
+/// Policy applied to missing (null or NaN) rigid body motions
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissingPolicy {
+    /// Returns an error at the first missing value
+    Error,
+    /// Drops the samples with missing values
+    #[default]
+    Drop,
+    /// Replaces missing values with zeros
+    ZeroFill,
+    /// Replaces missing values with the last valid value,
+    /// leading missing values take the first valid value
+    HoldLast,
+    /// Linearly interpolates missing values in time,
+    /// leading and trailing missing values take the nearest valid value
+    Interpolate,
+}
+
+/// Rigid body motions import options
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    missing: MissingPolicy,
+}
+impl ImportOptions {
+    /// Sets the policy applied to missing values
+    pub fn missing(self, missing: MissingPolicy) -> Self {
+        Self { missing }
+    }
+    /// Returns the policy applied to missing values
+    pub fn missing_policy(&self) -> MissingPolicy {
+        self.missing
+    }
+}
+
+/// Summary of the rigid body motions import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Number of samples in the source
+    pub n_sample: usize,
+    /// Indices of the samples dropped
+    pub dropped: Vec<usize>,
+    /// Indices of the samples with missing values replaced according to the [MissingPolicy]
+    pub filled: Vec<usize>,
+    /// Number of values replaced according to the [MissingPolicy]
+    pub n_filled_value: usize,
+}
+impl ImportReport {
+    /// Returns the number of samples dropped or filled
+    pub fn n_affected(&self) -> usize {
+        self.dropped.len() + self.filled.len()
+    }
+    /// Appends the report of the following samples of the same source
+    pub(crate) fn append(&mut self, other: &ImportReport) {
+        self.n_sample += other.n_sample;
+        self.dropped.extend_from_slice(&other.dropped);
+        self.filled.extend_from_slice(&other.filled);
+        self.n_filled_value += other.n_filled_value;
+    }
+}
+
+/// One sample of rigid body motions: the time and the 84 values, `None` if missing
+pub(crate) type Sample = (Option<f64>, Option<Vec<Option<f64>>>);
+
+impl RigidBodyMotions {
+    /// Creates [RigidBodyMotions] from samples with missing values handled according to `options`
+    ///
+    /// Samples with missing time are always dropped, unless the policy is [MissingPolicy::Error]
+    pub(crate) fn from_samples(
+        samples: Vec<Sample>,
+        sampling_frequency: Option<f64>,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, RigidBodyMotionsError> {
+        let policy = options.missing;
+        let mut report = ImportReport {
+            n_sample: samples.len(),
+            ..Default::default()
+        };
+        let mut time = vec![];
+        let mut rows: Vec<Vec<Option<f64>>> = vec![];
+        for (k, (t, values)) in samples.into_iter().enumerate() {
+            let values = values.map(|v| {
+                v.into_iter()
+                    .map(|x| x.filter(|x| !x.is_nan()))
+                    .collect::<Vec<_>>()
+            });
+            let complete = values
+                .as_ref()
+                .is_some_and(|v| v.iter().all(Option::is_some));
+            if policy == MissingPolicy::Error && !(complete && t.is_some()) {
+                return Err(RigidBodyMotionsError::MissingValues(k));
+            }
+            match (t, values) {
+                (Some(t), values) if complete || policy != MissingPolicy::Drop => {
+                    if !complete {
+                        report.filled.push(k);
+                    }
+                    time.push(t);
+                    rows.push(values.unwrap_or_else(|| vec![None; 84]));
+                }
+                _ => report.dropped.push(k),
+            }
+        }
+        let n = rows.len();
+        let mut data = nalgebra::DMatrix::<f64>::zeros(84, n);
+        for i in 0..84 {
+            let dof: Vec<Option<f64>> = rows.iter().map(|row| row[i]).collect();
+            report.n_filled_value += dof.iter().filter(|x| x.is_none()).count();
+            let filled = fill(&dof, &time, policy);
+            data.row_mut(i)
+                .iter_mut()
+                .zip(filled)
+                .for_each(|(d, x)| *d = x);
+        }
+        if report.n_affected() > 0 {
+            log::warn!(
+                "{} out of {} rigid body motions samples with missing values ({:?} policy)",
+                report.n_affected(),
+                report.n_sample,
+                policy
+            );
+        }
+        Ok(Self {
+            sampling_frequency,
+            time: Some(time),
+            data,
+            report,
+            format: super::Formatting::AdHoc,
+        })
+    }
+}
+
+/// Returns the sampling frequency from the median time step
+pub(crate) fn median_sampling_frequency(time: &[Option<f64>]) -> Option<f64> {
+    let mut steps: Vec<f64> = time
+        .windows(2)
+        .filter_map(|t| Some(t[1]? - t[0]?))
+        .collect();
+    steps.sort_by(f64::total_cmp);
+    steps
+        .get(steps.len() / 2)
+        .filter(|&&tau| tau > 0f64)
+        .map(|tau| tau.recip())
+}
+
+/// Fills the missing values of the time series `x` sampled at `time`
+fn fill(x: &[Option<f64>], time: &[f64], policy: MissingPolicy) -> Vec<f64> {
+    let valid: Vec<usize> = (0..x.len()).filter(|&k| x[k].is_some()).collect();
+    let (Some(&first), Some(&last)) = (valid.first(), valid.last()) else {
+        return vec![0f64; x.len()];
+    };
+    let mut previous = first;
+    (0..x.len())
+        .map(|k| match x[k] {
+            Some(x) => {
+                previous = k;
+                x
+            }
+            None => match policy {
+                MissingPolicy::HoldLast if k < first => x[first].unwrap(),
+                MissingPolicy::HoldLast => x[previous].unwrap(),
+                MissingPolicy::Interpolate if k < first => x[first].unwrap(),
+                MissingPolicy::Interpolate if k > last => x[last].unwrap(),
+                MissingPolicy::Interpolate => {
+                    let next = valid[valid.partition_point(|&j| j < k)];
+                    let (x0, x1) = (x[previous].unwrap(), x[next].unwrap());
+                    let (t0, t1) = (time[previous], time[next]);
+                    x0 + (x1 - x0) * (time[k] - t0) / (t1 - t0)
+                }
+                _ => 0f64,
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Sample> {
+        (0..5)
+            .map(|k| {
+                let mut values = vec![Some(k as f64); 84];
+                if k == 1 {
+                    values[3] = None;
+                }
+                if k == 2 {
+                    values[3] = Some(f64::NAN);
+                }
+                (Some(k as f64 * 0.1), (k != 4).then_some(values))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn missing_policies() {
+        let import = |policy| {
+            RigidBodyMotions::from_samples(
+                samples(),
+                Some(10.),
+                &ImportOptions::default().missing(policy),
+            )
+        };
+        assert!(matches!(
+            import(MissingPolicy::Error),
+            Err(RigidBodyMotionsError::MissingValues(1))
+        ));
+
+        let rbm = import(MissingPolicy::Drop).unwrap();
+        assert_eq!(rbm.len(), 2);
+        assert_eq!(rbm.import_report().dropped, vec![1, 2, 4]);
+        assert_eq!(rbm.time(), vec![0., 0.30000000000000004]);
+
+        let rbm = import(MissingPolicy::ZeroFill).unwrap();
+        let report = rbm.import_report();
+        assert_eq!(report.filled, vec![1, 2, 4]);
+        assert_eq!(report.n_filled_value, 2 + 84);
+        assert_eq!(rbm.data()[(3, 1)], 0.);
+        assert_eq!(rbm.data()[(0, 4)], 0.);
+
+        let rbm = import(MissingPolicy::HoldLast).unwrap();
+        assert_eq!(rbm.data()[(3, 2)], 0.);
+        assert_eq!(rbm.data()[(0, 4)], 3.);
+
+        let rbm = import(MissingPolicy::Interpolate).unwrap();
+        assert!((rbm.data()[(3, 1)] - 1.).abs() < 1e-12);
+        assert!((rbm.data()[(3, 2)] - 2.).abs() < 1e-12);
+        assert_eq!(rbm.data()[(10, 4)], 3.);
+    }
+}
@@ -1,16 +1,167 @@
-use super::RigidBodyMotions;
-use crate::Table;
+use super::{
+    import::{median_sampling_frequency, Sample},
+    ImportOptions, RigidBodyMotions, TIME_LABELS,
+};
+use crate::table::{field_units, units_scales, RbmColumns, RbmSchema, Table, RBM_CONVENTIONS};
 use crate::{rigid_body_motions::RigidBodyMotionsError, LinearOpticalModelError};
 use arrow::{
-    array::{Float64Array, ListArray},
+    array::{Array, ArrayRef, Float64Array, ListArray},
+    compute::cast,
     datatypes::{DataType, Field, Float64Type, Schema},
+    error::ArrowError,
     record_batch::RecordBatch,
 };
-use nalgebra as na;
 use std::path::Path;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 type Result<T> = std::result::Result<T, LinearOpticalModelError>;
+// rigid body motions of each row, `None` if missing
+type Rows = Vec<Option<Vec<Option<f64>>>>;
+
+/// Key of the sampling frequency `[Hz]` in the Arrow schema or parquet key/value metadata
+pub const SAMPLING_FREQUENCY_KEY: &str = "sampling_frequency";
+
+/// Layout of the rigid body motions in an Arrow table
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ArrowLayout {
+    /// One `List<Float64>` column of 42 elements per mirror
+    #[default]
+    List,
+    /// One `FixedSizeList<Float64, 42>` column per mirror
+    FixedSizeList,
+    /// One `Float64` column per degree of freedom labeled as in [RigidBodyMotions::labels]
+    Scalar,
+    /// A single `FixedSizeList<Float64, 84>` column with M1 followed by M2
+    Wide,
+}
+
+/// Returns the `n` rigid body motions of each row of the list-like column `label`, `None` if missing
+///
+/// `List`, `LargeList` and `FixedSizeList` columns of any numeric type are accepted
+fn list_column(table: &RecordBatch, label: &str, n: usize) -> Result<Rows> {
+    let idx = table
+        .schema()
+        .index_of(label)
+        .map_err(|e| RigidBodyMotionsError::FromRecord(e.into()))?;
+    let column = table.column(idx);
+    let column_type = || RigidBodyMotionsError::ColumnType {
+        label: label.to_string(),
+        found: column.data_type().to_string(),
+    };
+    match column.data_type() {
+        DataType::List(item) | DataType::LargeList(item) | DataType::FixedSizeList(item, _)
+            if item.data_type().is_numeric() => {}
+        _ => return Err(column_type().into()),
+    }
+    let list = cast(column, &list_type()).map_err(|_| column_type())?;
+    let list = list
+        .as_any()
+        .downcast_ref::<ListArray>()
+        .ok_or_else(column_type)?;
+    list.iter()
+        .enumerate()
+        .map(|(row, values)| {
+            values
+                .map(|values| {
+                    let values: Vec<Option<f64>> = values
+                        .as_any()
+                        .downcast_ref::<Float64Array>()
+                        .ok_or_else(column_type)?
+                        .iter()
+                        .collect();
+                    if values.len() != n {
+                        return Err(RigidBodyMotionsError::RowLength {
+                            label: label.to_string(),
+                            row,
+                            expected: n,
+                            found: values.len(),
+                        }
+                        .into());
+                    }
+                    Ok(values)
+                })
+                .transpose()
+        })
+        .collect()
+}
+
+/// Returns the 84 rigid body motions of each row from one numeric column per degree of freedom
+fn scalar_columns(table: &RecordBatch) -> Result<Rows> {
+    let schema = table.schema();
+    let columns = RigidBodyMotions::labels()
+        .into_iter()
+        .map(|label| {
+            schema
+                .index_of(&label)
+                .map(|idx| (label, idx))
+                .map_err(|e| RigidBodyMotionsError::FromRecord(e.into()))
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let columns: Result<Vec<Vec<Option<f64>>>> = columns
+        .into_iter()
+        .map(|(label, idx)| {
+            let column = table.column(idx);
+            let column_type = || RigidBodyMotionsError::ColumnType {
+                label: label.clone(),
+                found: column.data_type().to_string(),
+            };
+            if !column.data_type().is_numeric() {
+                return Err(column_type().into());
+            }
+            let column = cast(column, &DataType::Float64).map_err(|_| column_type())?;
+            Ok(column
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .ok_or_else(column_type)?
+                .iter()
+                .collect())
+        })
+        .collect();
+    columns.map(|columns| {
+        (0..table.num_rows())
+            .map(|row| Some(columns.iter().map(|c| c[row]).collect()))
+            .collect()
+    })
+}
+
+fn list_type() -> DataType {
+    DataType::List(Arc::new(Field::new("item", DataType::Float64, true)))
+}
+
+/// Returns the time of each row and the sampling frequency
+///
+/// The time is read from one of the [TIME_LABELS] column or derived from the [SAMPLING_FREQUENCY_KEY] metadata,
+/// otherwise the time is the row index, `first_row` being the index of the table first row in the source
+fn time_axis(table: &RecordBatch, first_row: usize) -> Result<(Vec<Option<f64>>, Option<f64>)> {
+    let schema = table.schema();
+    let n = table.num_rows();
+    if let Some(idx) = TIME_LABELS
+        .iter()
+        .find_map(|label| schema.index_of(label).ok())
+    {
+        let column = cast(table.column(idx), &DataType::Float64)
+            .map_err(|e| RigidBodyMotionsError::FromRecord(e.into()))?;
+        let time: Vec<Option<f64>> = column
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .expect("time column cast to Float64")
+            .iter()
+            .collect();
+        let sampling_frequency = median_sampling_frequency(&time);
+        return Ok((time, sampling_frequency));
+    }
+    let sampling_frequency = schema
+        .metadata()
+        .get(SAMPLING_FREQUENCY_KEY)
+        .and_then(|fs| fs.parse::<f64>().ok());
+    let fs = sampling_frequency.unwrap_or(1f64);
+    Ok((
+        (first_row..first_row + n)
+            .map(|k| Some(k as f64 / fs))
+            .collect(),
+        sampling_frequency,
+    ))
+}
 
 impl RigidBodyMotions {
     /// Creates a [RigidBodyMotions] from M1 and M2 rigid body motions saved in a [parquet](https://docs.rs/parquet) file
@@ -31,111 +182,204 @@ impl RigidBodyMotions {
         m1_rbm_label: Option<&str>,
         m2_rbm_label: Option<&str>,
     ) -> Result<Self> {
-        Self::from_record(&t.table(), m1_rbm_label, m2_rbm_label)
+        Self::from_record(t.table(), m1_rbm_label, m2_rbm_label)
+    }
+    /// Creates a [RigidBodyMotions] from a [Table] with the given import options
+    pub fn from_table_with(
+        t: &Table,
+        m1_rbm_label: Option<&str>,
+        m2_rbm_label: Option<&str>,
+        options: &ImportOptions,
+    ) -> Result<Self> {
+        Self::from_record_with(t.table(), m1_rbm_label, m2_rbm_label, options)
     }
     /// Creates a [RigidBodyMotions] from an Arrow table
+    ///
+    /// Samples with missing values are dropped
     pub fn from_record(
         table: &RecordBatch,
         m1_rbm_label: Option<&str>,
         m2_rbm_label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_record_with(table, m1_rbm_label, m2_rbm_label, &Default::default())
+    }
+    /// Creates a [RigidBodyMotions] from an Arrow table with the given import options
+    ///
+    /// The rigid body motions are read from either:
+    ///  - one list-like column per mirror, `m1_rbm_label` and `m2_rbm_label`,
+    ///  - a single list-like column of 84 elements if both labels are the same,
+    ///  - the columns found with [RbmSchema::discover] if both labels are `None`.
+    ///
+    /// List-like columns are either `List`, `LargeList` or `FixedSizeList` of any numeric type.
+    /// The translations and rotations are converted to `[m]` and `[rd]` according to the units
+    /// in the [UNITS_KEY](crate::UNITS_KEY) metadata of the (first) rigid body motions column.
+    ///
+    /// The missing values are handled according to the [MissingPolicy](super::MissingPolicy) of the `options`,
+    /// see [import_report](RigidBodyMotions::import_report) for the samples that have been affected
+    pub fn from_record_with(
+        table: &RecordBatch,
+        m1_rbm_label: Option<&str>,
+        m2_rbm_label: Option<&str>,
+        options: &ImportOptions,
+    ) -> Result<Self> {
+        Self::from_record_at(table, m1_rbm_label, m2_rbm_label, options, 0)
+    }
+    /// Creates a [RigidBodyMotions] from an Arrow table which first row is the `first_row` sample of the source
+    ///
+    /// The time derived from the sampling frequency and the indices of the [ImportReport](super::ImportReport)
+    /// are offset by `first_row`
+    pub(crate) fn from_record_at(
+        table: &RecordBatch,
+        m1_rbm_label: Option<&str>,
+        m2_rbm_label: Option<&str>,
+        options: &ImportOptions,
+        first_row: usize,
     ) -> Result<Self> {
         let schema = table.schema();
-        // println!("{:#?}", schema.metadata());
-        let idx = schema
-            .index_of(m1_rbm_label.unwrap_or("OSSM1Lcl"))
-            .map_err(|e| RigidBodyMotionsError::FromRecord(e.into()))?;
-        let m1_rbm = table
-            .column(idx)
-            .as_any()
-            .downcast_ref::<ListArray>()
-            .unwrap();
-        let idx = schema
-            .index_of(m2_rbm_label.unwrap_or("MCM2Lcl6D"))
-            .map_err(|e| RigidBodyMotionsError::FromRecord(e.into()))?;
-        let m2_rbm = table
-            .column(idx)
-            .as_any()
-            .downcast_ref::<ListArray>()
-            .unwrap();
-        let (time, rbm): (Vec<f64>, Vec<Vec<f64>>) = m1_rbm
-            .iter()
-            .zip(m2_rbm.iter())
-            .enumerate()
-            .filter_map(|(k, (m1, m2))| match (m1, m2) {
-                (Some(m1_rbm), Some(m2_rbm)) => {
-                    let m1_rbm_data = m1_rbm
-                        .as_any()
-                        .downcast_ref::<Float64Array>()
-                        .unwrap()
-                        .iter()
-                        .collect::<Option<Vec<f64>>>();
-                    let m2_rbm_data = m2_rbm
-                        .as_any()
-                        .downcast_ref::<Float64Array>()
-                        .unwrap()
-                        .iter()
-                        .collect::<Option<Vec<f64>>>();
-                    if let (Some(m1_rbm_data), Some(m2_rbm_data)) = (m1_rbm_data, m2_rbm_data) {
-                        Some((
-                            k as f64,
-                            m1_rbm_data
-                                .into_iter()
-                                .chain(m2_rbm_data.into_iter())
-                                .collect::<Vec<f64>>(),
-                        ))
-                    } else {
-                        None
-                    }
+        let (columns, units) = match (m1_rbm_label, m2_rbm_label) {
+            (None, None) => {
+                let RbmSchema { columns, units } = RbmSchema::discover(&schema)?;
+                (columns, Some(units))
+            }
+            (Some(m1), Some(m2)) if m1 == m2 => {
+                (RbmColumns::Wide(m1.to_string()), field_units(&schema, m1))
+            }
+            (m1, m2) => {
+                let m1 = m1.unwrap_or(RBM_CONVENTIONS[0].0);
+                (
+                    RbmColumns::Mirrors {
+                        m1: m1.to_string(),
+                        m2: m2.unwrap_or(RBM_CONVENTIONS[0].1).to_string(),
+                    },
+                    field_units(&schema, m1),
+                )
+            }
+        };
+        let (length, angle) = units
+            .as_deref()
+            .map(units_scales)
+            .transpose()?
+            .unwrap_or((1f64, 1f64));
+        let mut rbm: Rows = match columns {
+            RbmColumns::Mirrors { m1, m2 } => list_column(table, &m1, 42)?
+                .into_iter()
+                .zip(list_column(table, &m2, 42)?)
+                .map(|(m1, m2)| m1.zip(m2).map(|(m1, m2)| [m1, m2].concat()))
+                .collect(),
+            RbmColumns::Wide(label) => list_column(table, &label, 84)?,
+            RbmColumns::Scalar => scalar_columns(table)?,
+        };
+        if (length, angle) != (1f64, 1f64) {
+            rbm.iter_mut().flatten().for_each(|rbm| {
+                rbm.iter_mut()
+                    .enumerate()
+                    .filter_map(|(i, x)| x.as_mut().map(|x| (i, x)))
+                    .for_each(|(i, x)| *x *= if i % 6 < 3 { length } else { angle })
+            });
+        }
+        let (time, sampling_frequency) = time_axis(table, first_row)?;
+        let samples: Vec<Sample> = rbm.into_iter().zip(time).map(|(rbm, t)| (t, rbm)).collect();
+        let mut rbm =
+            Self::from_samples(samples, sampling_frequency, options).map_err(|e| match e {
+                RigidBodyMotionsError::MissingValues(k) => {
+                    RigidBodyMotionsError::MissingValues(first_row + k)
                 }
-                _ => None,
-            })
-            .unzip();
-        let n = time.len();
-        Ok(Self {
-            sampling_frequency: Some((time[1] - time[0]).recip()),
-            time: Some(time),
-            data: na::DMatrix::from_iterator(84, n, rbm.into_iter().flatten()),
-            format: super::Formatting::AdHoc,
-        })
+                e => e,
+            })?;
+        rbm.report
+            .dropped
+            .iter_mut()
+            .chain(rbm.report.filled.iter_mut())
+            .for_each(|k| *k += first_row);
+        Ok(rbm)
     }
     /// Writes rigid body modtions to an Arrow table
+    ///
+    /// The time vector is written in the `time` column and the sampling frequency, if known, in the schema metadata
     pub fn to_record(
         &self,
         m1_rbm_label: Option<&str>,
         m2_rbm_label: Option<&str>,
     ) -> Result<RecordBatch> {
-        let m1_rbm: Vec<_> = self
-            .data
-            .rows(0, 42)
-            .column_iter()
-            .map(|x| Some(x.as_slice().iter().map(|x| Some(*x)).collect::<Vec<_>>()))
-            .collect();
-        let m2_rbm: Vec<_> = self
-            .data
-            .rows(42, 42)
-            .column_iter()
-            .map(|x| Some(x.as_slice().iter().map(|x| Some(*x)).collect::<Vec<_>>()))
+        self.to_record_with(m1_rbm_label, m2_rbm_label, ArrowLayout::default())
+    }
+    /// Writes rigid body modtions to an Arrow table with the given layout
+    ///
+    /// For the [Wide](ArrowLayout::Wide) layout, the column is labeled `m1_rbm_label`
+    /// and for the [Scalar](ArrowLayout::Scalar) layout, both labels are ignored
+    pub fn to_record_with(
+        &self,
+        m1_rbm_label: Option<&str>,
+        m2_rbm_label: Option<&str>,
+        layout: ArrowLayout,
+    ) -> Result<RecordBatch> {
+        let to_record = |e: ArrowError| RigidBodyMotionsError::ToRecord(e.into());
+        let m1_rbm_label = m1_rbm_label.unwrap_or("M1RigidBodyMotions");
+        let m2_rbm_label = m2_rbm_label.unwrap_or("M2RigidBodyMotions");
+        let list = |first: usize, n: usize| -> std::result::Result<ArrayRef, ArrowError> {
+            let rows = self
+                .data
+                .rows(first, n)
+                .column_iter()
+                .map(|x| Some(x.iter().map(|x| Some(*x)).collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            let list: ArrayRef =
+                Arc::new(ListArray::from_iter_primitive::<Float64Type, _, _>(rows));
+            match layout {
+                ArrowLayout::List => Ok(list),
+                _ => cast(
+                    &list,
+                    &DataType::FixedSizeList(
+                        Arc::new(Field::new("item", DataType::Float64, true)),
+                        n as i32,
+                    ),
+                ),
+            }
+        };
+        let mut columns: Vec<(String, ArrayRef)> = vec![(
+            TIME_LABELS[0].to_string(),
+            Arc::new(Float64Array::from(self.time())),
+        )];
+        match layout {
+            ArrowLayout::List | ArrowLayout::FixedSizeList => {
+                columns.push((m1_rbm_label.to_string(), list(0, 42).map_err(to_record)?));
+                columns.push((m2_rbm_label.to_string(), list(42, 42).map_err(to_record)?));
+            }
+            ArrowLayout::Wide => {
+                columns.push((m1_rbm_label.to_string(), list(0, 84).map_err(to_record)?));
+            }
+            ArrowLayout::Scalar => {
+                columns.extend(
+                    RigidBodyMotions::labels()
+                        .into_iter()
+                        .zip(self.data.row_iter())
+                        .map(|(label, row)| {
+                            (
+                                label,
+                                Arc::new(Float64Array::from_iter_values(row.iter().cloned()))
+                                    as ArrayRef,
+                            )
+                        }),
+                );
+            }
+        }
+        let metadata: HashMap<String, String> = self
+            .sampling_frequency
+            .map(|fs| (SAMPLING_FREQUENCY_KEY.to_string(), fs.to_string()))
+            .into_iter()
             .collect();
-        let schema = Schema::new(vec![
-            Field::new(
-                m1_rbm_label.unwrap_or("M1RigidBodyMotions"),
-                DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
-                false,
-            ),
-            Field::new(
-                m2_rbm_label.unwrap_or("M2RigidBodyMotions"),
-                DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
-                false,
-            ),
-        ]);
+        let schema = Schema::new(
+            columns
+                .iter()
+                .map(|(label, column)| Field::new(label, column.data_type().clone(), false))
+                .collect::<Vec<_>>(),
+        )
+        .with_metadata(metadata);
         Ok(RecordBatch::try_new(
             Arc::new(schema),
-            vec![
-                Arc::new(ListArray::from_iter_primitive::<Float64Type, _, _>(m1_rbm)),
-                Arc::new(ListArray::from_iter_primitive::<Float64Type, _, _>(m2_rbm)),
-            ],
+            columns.into_iter().map(|(_, column)| column).collect(),
         )
-        .map_err(|e| RigidBodyMotionsError::ToRecord(e.into()))?)
+        .map_err(to_record)?)
     }
     /// Writes rigid body motions to a [Table]
     pub fn to_table(
@@ -158,3 +402,147 @@ impl RigidBodyMotions {
             .to_parquet(path)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra as na;
+
+    #[test]
+    fn time_axis() {
+        let data = na::DMatrix::<f64>::from_fn(84, 5, |i, j| (i + j) as f64);
+        let mut rbm: RigidBodyMotions = data.into();
+        rbm.sampling_frequency = Some(200.);
+        let record = rbm.to_record(None, None).unwrap();
+        let rbm = RigidBodyMotions::from_record(
+            &record,
+            Some("M1RigidBodyMotions"),
+            Some("M2RigidBodyMotions"),
+        )
+        .unwrap();
+        assert_eq!(rbm.sampling_frequency(), Some(200.));
+        assert_eq!(rbm.time(), vec![0., 0.005, 0.01, 0.015, 0.02]);
+
+        // sampling frequency from the metadata and a dropped sample
+        let mut m1: Vec<_> = (0..5).map(|k| Some(vec![Some(k as f64); 42])).collect();
+        m1[2] = None;
+        let m2: Vec<_> = (0..5).map(|_| Some(vec![Some(0f64); 42])).collect();
+        let list = DataType::List(Arc::new(Field::new("item", DataType::Float64, true)));
+        let schema = Schema::new(vec![
+            Field::new("OSSM1Lcl", list.clone(), true),
+            Field::new("MCM2Lcl6D", list, true),
+        ])
+        .with_metadata(HashMap::from([(
+            SAMPLING_FREQUENCY_KEY.to_string(),
+            "10".to_string(),
+        )]));
+        let record = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(ListArray::from_iter_primitive::<Float64Type, _, _>(m1)),
+                Arc::new(ListArray::from_iter_primitive::<Float64Type, _, _>(m2)),
+            ],
+        )
+        .unwrap();
+        let rbm = RigidBodyMotions::from_record(&record, None, None).unwrap();
+        assert_eq!(rbm.len(), 4);
+        assert_eq!(rbm.dropped_samples(), &[2]);
+        assert_eq!(rbm.time(), vec![0., 0.1, 0.3, 0.4]);
+        assert_eq!(rbm.sampling_frequency(), Some(10.));
+        assert!(matches!(
+            RigidBodyMotions::from_record(&record, Some("OSSM1Lcl"), Some("time")),
+            Err(LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::FromRecord(_)
+            ))
+        ));
+        let record = rbm.to_record(None, None).unwrap();
+        assert!(matches!(
+            RigidBodyMotions::from_record(&record, Some("M1RigidBodyMotions"), Some("time")),
+            Err(LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::ColumnType { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let data = na::DMatrix::<f64>::from_fn(84, 4, |i, j| (i + j) as f64);
+        let mut rbm: RigidBodyMotions = data.clone().into();
+        rbm.sampling_frequency = Some(250.);
+        let path = std::env::temp_dir().join(format!("gmt-lom_rbm_{}.parquet", std::process::id()));
+        rbm.to_parquet(&path, None, None).unwrap();
+        let table = Table::from_parquet(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            table
+                .table()
+                .schema()
+                .metadata()
+                .get(SAMPLING_FREQUENCY_KEY)
+                .map(String::as_str),
+            Some("250")
+        );
+        let rbm = RigidBodyMotions::from_table(
+            &table,
+            Some("M1RigidBodyMotions"),
+            Some("M2RigidBodyMotions"),
+        )
+        .unwrap();
+        assert_eq!(*rbm.data(), data);
+        assert_eq!(rbm.sampling_frequency(), Some(250.));
+        assert_eq!(rbm.time(), vec![0., 0.004, 0.008, 0.012]);
+    }
+
+    #[test]
+    fn layouts() {
+        let data = na::DMatrix::<f64>::from_fn(84, 3, |i, j| (i * 3 + j) as f64);
+        let rbm: RigidBodyMotions = data.clone().into();
+        for layout in [
+            ArrowLayout::List,
+            ArrowLayout::FixedSizeList,
+            ArrowLayout::Scalar,
+            ArrowLayout::Wide,
+        ] {
+            let record = rbm.to_record_with(Some("M1"), Some("M2"), layout).unwrap();
+            let rbm = match layout {
+                ArrowLayout::Wide => RigidBodyMotions::from_record(&record, Some("M1"), Some("M1")),
+                ArrowLayout::Scalar => RigidBodyMotions::from_record(&record, None, None),
+                _ => RigidBodyMotions::from_record(&record, Some("M1"), Some("M2")),
+            }
+            .unwrap();
+            assert_eq!(*rbm.data(), data, "{layout:?}");
+        }
+
+        // LargeList<Float32>
+        let record = rbm.to_record(Some("M1"), Some("M2")).unwrap();
+        let large = DataType::LargeList(Arc::new(Field::new("item", DataType::Float32, true)));
+        let columns: Vec<ArrayRef> = record
+            .columns()
+            .iter()
+            .skip(1)
+            .map(|c| cast(c, &large).unwrap())
+            .collect();
+        let schema = Schema::new(vec![
+            Field::new("M1", large.clone(), true),
+            Field::new("M2", large, true),
+        ]);
+        let record = RecordBatch::try_new(Arc::new(schema), columns).unwrap();
+        let rbm = RigidBodyMotions::from_record(&record, Some("M1"), Some("M2")).unwrap();
+        assert_eq!(*rbm.data(), data);
+
+        // 84-wide column read as a mirror column
+        let record = rbm
+            .to_record_with(Some("M1"), None, ArrowLayout::Wide)
+            .unwrap();
+        assert!(matches!(
+            RigidBodyMotions::from_record(&record, Some("M1"), Some("time")),
+            Err(LinearOpticalModelError::RigidBodyMotions(
+                RigidBodyMotionsError::RowLength {
+                    expected: 42,
+                    found: 84,
+                    ..
+                }
+            ))
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+//! # Streaming of long rigid body motions time series
+//!
+//! The rigid body motions are read from a parquet file one record batch at a time,
+//! each batch is propagated through the [LOM] and the statistics of the requested optical metrics
+//! are updated incrementally, so the whole time series never has to fit in memory.
+//! Optionally, the requested optical metrics are written batch by batch to parquet files
+//!
+//! # Example
+//! ```no_run
+//! use gmt_lom::{streaming::StreamOptions, OpticalSensitivity, LOM};
+//!
+//! let lom = LOM::builder().build().unwrap();
+//! let options = StreamOptions::default()
+//!     .batch_size(4096)
+//!     .output(OpticalSensitivity::TipTilt(vec![]))
+//!     .output(OpticalSensitivity::SegmentPiston(vec![]))
+//!     .write_to("lom_outputs");
+//! let results = lom.stream_parquet("rbm.parquet", &options).unwrap();
+//! println!("tip-tilt std: {:?}", results.tiptilt.unwrap().std());
+//! println!("written: {:?}", results.files);
+//! ```
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+
+use crate::{
+    ImportOptions, ImportReport, LinearOpticalModelError, OpticalMetrics, OpticalSensitivity,
+    Result, RigidBodyMotions, SegmentPiston, SegmentTipTilt, Stats, Table, TableError, TipTilt,
+    ToTable, WavefrontResidual, LOM,
+};
+
+/// Running mean and variance of the items of an optical metric
+///
+/// The statistics are updated with the metric of each new batch of samples,
+/// [mean](RunningStats::mean) and [var](RunningStats::var) are the same than [Stats] over all the samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningStats {
+    n_sample: usize,
+    mean: Vec<f64>,
+    // sum of squared differences to the mean
+    m2: Vec<f64>,
+}
+impl RunningStats {
+    /// Creates empty statistics for a metric with `n_item` items
+    pub fn new(n_item: usize) -> Self {
+        Self {
+            n_sample: 0,
+            mean: vec![0f64; n_item],
+            m2: vec![0f64; n_item],
+        }
+    }
+    /// Updates the statistics with a new batch of samples
+    pub fn push<T>(&mut self, metric: &T)
+    where
+        T: Deref<Target = Vec<f64>> + OpticalMetrics + Stats,
+    {
+        assert_eq!(metric.n_item(), self.n_item(), "mismatched number of items");
+        let n_b = metric.len() / metric.n_item();
+        if n_b == 0 {
+            return;
+        }
+        let n_a = self.n_sample;
+        let n = n_a + n_b;
+        for ((mean, m2), (mean_b, var_b)) in self
+            .mean
+            .iter_mut()
+            .zip(self.m2.iter_mut())
+            .zip(metric.mean(None).into_iter().zip(metric.var(None)))
+        {
+            let delta = mean_b - *mean;
+            *mean += delta * n_b as f64 / n as f64;
+            *m2 += var_b * n_b as f64 + delta * delta * (n_a * n_b) as f64 / n as f64;
+        }
+        self.n_sample = n;
+    }
+    /// Returns the number of items
+    pub fn n_item(&self) -> usize {
+        self.mean.len()
+    }
+    /// Returns the number of samples
+    pub fn n_sample(&self) -> usize {
+        self.n_sample
+    }
+    /// Returns the mean values
+    pub fn mean(&self) -> Vec<f64> {
+        self.mean.clone()
+    }
+    /// Returns the variance values
+    pub fn var(&self) -> Vec<f64> {
+        self.m2.iter().map(|m2| m2 / self.n_sample as f64).collect()
+    }
+    /// Returns the standard deviation values
+    pub fn std(&self) -> Vec<f64> {
+        self.var().iter().map(|x| x.sqrt()).collect()
+    }
+}
+
+/// Streaming options
+///
+/// Only the optical metrics of the same variant than the [output](StreamOptions::output) indices are computed,
+/// the supported variants are [TipTilt](OpticalSensitivity::TipTilt), [SegmentTipTilt](OpticalSensitivity::SegmentTipTilt),
+/// [SegmentPiston](OpticalSensitivity::SegmentPiston) and [Wavefront](OpticalSensitivity::Wavefront) for the WFE RMS
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+    batch_size: usize,
+    outputs: Vec<OpticalSensitivity>,
+    m1_rbm_label: Option<String>,
+    m2_rbm_label: Option<String>,
+    import: ImportOptions,
+    residual: WavefrontResidual,
+    directory: Option<PathBuf>,
+}
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 8192,
+            outputs: vec![],
+            m1_rbm_label: None,
+            m2_rbm_label: None,
+            import: Default::default(),
+            residual: Default::default(),
+            directory: None,
+        }
+    }
+}
+impl StreamOptions {
+    /// Sets the maximum number of samples in a batch
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    /// Adds the optical metric of the same variant than `index` to the outputs
+    pub fn output(mut self, index: OpticalSensitivity) -> Self {
+        self.outputs.push(index);
+        self
+    }
+    /// Sets the labels of the rigid body motions columns, see [RigidBodyMotions::from_record_with]
+    ///
+    /// The columns are discovered from the table schema if the labels are not set
+    pub fn rbm_labels(mut self, m1_rbm_label: &str, m2_rbm_label: &str) -> Self {
+        self.m1_rbm_label = Some(m1_rbm_label.to_string());
+        self.m2_rbm_label = Some(m2_rbm_label.to_string());
+        self
+    }
+    /// Sets the rigid body motions import options
+    ///
+    /// The missing values are filled within each batch
+    pub fn import(mut self, import: ImportOptions) -> Self {
+        self.import = import;
+        self
+    }
+    /// Sets the low order modes removed from the wavefront before computing the WFE RMS
+    pub fn wavefront_residual(mut self, residual: WavefrontResidual) -> Self {
+        self.residual = residual;
+        self
+    }
+    /// Writes the requested optical metrics to parquet files in the `directory`
+    ///
+    /// Each optical metric is written with [ToTable] layout to a file named after the
+    /// corresponding [StreamResults] field, e.g. `tiptilt.parquet`, one row group per batch
+    pub fn write_to<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = Some(directory.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// Streaming results
+///
+/// The statistics are `None` if the optical metric has not been requested
+#[derive(Debug, Clone, Default)]
+pub struct StreamResults {
+    /// Tip-tilt statistics in `[rd]`
+    pub tiptilt: Option<RunningStats>,
+    /// Segment tip-tilt statistics in `[rd]`
+    pub segment_tiptilt: Option<RunningStats>,
+    /// Segment piston statistics in `[m]`
+    pub segment_piston: Option<RunningStats>,
+    /// WFE RMS statistics in `[m]`
+    pub wfe_rms: Option<RunningStats>,
+    /// Number of record batches
+    pub n_batch: usize,
+    /// Sampling frequency of the first batch `[Hz]`
+    pub sampling_frequency: Option<f64>,
+    /// Rigid body motions import report with sample indices in the whole time series
+    pub report: ImportReport,
+    /// Parquet files of the optical metrics if [written](StreamOptions::write_to)
+    pub files: Vec<PathBuf>,
+}
+
+/// Parquet file of an optical metric written one batch at a time
+struct MetricWriter {
+    path: PathBuf,
+    // created with the schema of the first batch
+    writer: Option<ArrowWriter<BufWriter<File>>>,
+}
+impl MetricWriter {
+    fn new(directory: &Path, name: &str) -> Self {
+        Self {
+            path: directory.join(name).with_extension("parquet"),
+            writer: None,
+        }
+    }
+    fn write(&mut self, record: &RecordBatch) -> Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => {
+                let file = File::create(&self.path)
+                    .map_err(|e| TableError::ParquetFile(e, self.path.clone()))?;
+                self.writer.insert(
+                    ArrowWriter::try_new(BufWriter::new(file), record.schema(), None)
+                        .map_err(TableError::from)?,
+                )
+            }
+        };
+        writer.write(record).map_err(TableError::from)?;
+        writer.flush().map_err(TableError::from)?;
+        Ok(())
+    }
+    fn close(self) -> Result<Option<PathBuf>> {
+        match self.writer {
+            Some(writer) => {
+                writer.close().map_err(TableError::from)?;
+                Ok(Some(self.path))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn update<T>(
+    stats: &mut Option<RunningStats>,
+    writer: Option<&mut MetricWriter>,
+    time: &[f64],
+    metric: impl FnOnce() -> Result<T>,
+) -> Result<()>
+where
+    T: Deref<Target = Vec<f64>> + OpticalMetrics + Stats + ToTable,
+{
+    if let Some(stats) = stats.as_mut() {
+        let metric = metric()?;
+        stats.push(&metric);
+        if let Some(writer) = writer {
+            writer.write(metric.to_table(time)?.table())?;
+        }
+    }
+    Ok(())
+}
+
+impl LOM {
+    /// Streams the rigid body motions of a parquet file through the [LOM]
+    ///
+    /// The file is read one batch of [batch_size](StreamOptions::batch_size) samples at a time
+    /// and only the statistics of the requested [outputs](StreamOptions::output) are accumulated
+    /// and, if [write_to](StreamOptions::write_to) is set, written.
+    /// The [LOM] rigid body motions are left unchanged
+    pub fn stream_parquet<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &StreamOptions,
+    ) -> Result<StreamResults> {
+        let mut results = StreamResults::default();
+        for index in &options.outputs {
+            self.sensitivities().try_get(index.clone())?;
+            let stats = match index {
+                OpticalSensitivity::TipTilt(_) => &mut results.tiptilt,
+                OpticalSensitivity::SegmentTipTilt(_) => &mut results.segment_tiptilt,
+                OpticalSensitivity::SegmentPiston(_) => &mut results.segment_piston,
+                OpticalSensitivity::Wavefront(_) => &mut results.wfe_rms,
+                _ => return Err(LinearOpticalModelError::NotAMeasurement(index.to_string())),
+            };
+            let n_item = match index {
+                OpticalSensitivity::TipTilt(_) => 2,
+                OpticalSensitivity::SegmentTipTilt(_) => 14,
+                OpticalSensitivity::SegmentPiston(_) => 7,
+                _ => 1,
+            };
+            stats.get_or_insert_with(|| RunningStats::new(n_item));
+        }
+        let mut writers = ["tiptilt", "segment_tiptilt", "segment_piston", "wfe_rms"].map(|name| {
+            options
+                .directory
+                .as_deref()
+                .map(|directory| MetricWriter::new(directory, name))
+        });
+        let batches = Table::record_batches(path, options.batch_size)?;
+        let mut first_row = 0;
+        for batch in batches {
+            let batch = batch.map_err(TableError::from)?;
+            let chunk = RigidBodyMotions::from_record_at(
+                &batch,
+                options.m1_rbm_label.as_deref(),
+                options.m2_rbm_label.as_deref(),
+                &options.import,
+                first_row,
+            )?;
+            first_row += batch.num_rows();
+            results.n_batch += 1;
+            results.report.append(chunk.import_report());
+            results.sampling_frequency = results.sampling_frequency.or(chunk.sampling_frequency());
+            if chunk.is_empty() {
+                continue;
+            }
+            let time = chunk.time();
+            let optics = |index: OpticalSensitivity| self.optics_of(index, &chunk);
+            let [tiptilt, segment_tiptilt, segment_piston, wfe_rms] = &mut writers;
+            update(&mut results.tiptilt, tiptilt.as_mut(), &time, || {
+                optics(OpticalSensitivity::TipTilt(vec![])).map(TipTilt)
+            })?;
+            update(
+                &mut results.segment_tiptilt,
+                segment_tiptilt.as_mut(),
+                &time,
+                || optics(OpticalSensitivity::SegmentTipTilt(vec![])).map(SegmentTipTilt),
+            )?;
+            update(
+                &mut results.segment_piston,
+                segment_piston.as_mut(),
+                &time,
+                || optics(OpticalSensitivity::SegmentPiston(vec![])).map(SegmentPiston),
+            )?;
+            update(&mut results.wfe_rms, wfe_rms.as_mut(), &time, || {
+                self.residual_wfe_rms_of::<0>(options.residual, &chunk)
+            })?;
+        }
+        for writer in writers.into_iter().flatten() {
+            results.files.extend(writer.close()?);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MissingPolicy, OpticalSensitivities};
+    use nalgebra as na;
+
+    #[test]
+    fn stream_parquet() {
+        let n = 1000;
+        let data = na::DMatrix::<f64>::from_fn(84, n, |i, j| {
+            1e-6 * ((i + 1) as f64 * j as f64 * 1e-2).sin() + 1e-7 * (i % 5) as f64
+        });
+        let rbm: RigidBodyMotions = data.clone().into();
+        let path =
+            std::env::temp_dir().join(format!("gmt-lom_stream_{}.parquet", std::process::id()));
+        rbm.to_parquet(&path, None, None).unwrap();
+
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = rbm;
+        let options = StreamOptions::default()
+            .batch_size(128)
+            .output(OpticalSensitivity::TipTilt(vec![]))
+            .output(OpticalSensitivity::SegmentPiston(vec![]))
+            .output(OpticalSensitivity::Wavefront(vec![]))
+            .import(ImportOptions::default().missing(MissingPolicy::Error));
+        let results = lom.stream_parquet(&path, &options).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results.n_batch, 8);
+        assert_eq!(results.report.n_sample, n);
+        assert_eq!(results.sampling_frequency, Some(1.));
+        assert!(results.segment_tiptilt.is_none());
+        assert_eq!(lom.len(), n);
+        let close = |a: Vec<f64>, b: Vec<f64>| {
+            a.iter()
+                .zip(&b)
+                .for_each(|(a, b)| assert!((a - b).abs() <= 1e-9 * b.abs().max(1e-12)));
+        };
+        let tiptilt = results.tiptilt.unwrap();
+        assert_eq!(tiptilt.n_sample(), n);
+        close(tiptilt.mean(), lom.tiptilt().mean(None));
+        close(tiptilt.std(), lom.tiptilt().std(None));
+        let segment_piston = results.segment_piston.unwrap();
+        close(segment_piston.std(), lom.segment_piston().std(None));
+        close(results.wfe_rms.unwrap().var(), lom.wfe_rms::<0>().var(None));
+
+        let directory = std::env::temp_dir().join(format!("gmt-lom_stream_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rbm: RigidBodyMotions = data.columns(0, 300).into_owned().into();
+        rbm.to_parquet(&path, None, None).unwrap();
+        let results = lom
+            .stream_parquet(
+                &path,
+                &StreamOptions::default()
+                    .batch_size(128)
+                    .output(OpticalSensitivity::SegmentPiston(vec![]))
+                    .write_to(&directory),
+            )
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            results.files,
+            vec![directory.join("segment_piston.parquet")]
+        );
+        let table = Table::from_parquet(&results.files[0]).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let record = table.table();
+        assert_eq!(record.num_rows(), 300);
+        assert_eq!(record.num_columns(), 8);
+        lom.rbm = rbm;
+        let s7 = record
+            .column_by_name("S7_piston")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<arrow::array::Float64Array>()
+            .unwrap();
+        assert_eq!(s7.value(299), lom.segment_piston()[299 * 7 + 6]);
+
+        assert!(matches!(
+            lom.stream_parquet(
+                "rbm.parquet",
+                &StreamOptions::default().output(OpticalSensitivity::PupilMask(vec![]))
+            ),
+            Err(LinearOpticalModelError::NotAMeasurement(_))
+        ));
+    }
+}
@@ -1,9 +1,13 @@
 use arrow::compute::concat_batches;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
 use arrow::record_batch::{RecordBatch, RecordBatchReader};
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
 use parquet::arrow::ArrowWriter;
+use std::collections::HashMap;
 use std::io::BufWriter;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{fs::File, path::Path};
 
 #[derive(Debug, thiserror::Error)]
@@ -17,6 +21,14 @@ pub enum TableError {
     Parquet(#[from] parquet::errors::ParquetError),
     #[error("arrow record get failed")]
     Arrow(#[from] arrow::error::ArrowError),
+    #[error("rigid body motions columns not found, candidates: {0:?}")]
+    RbmColumns(Vec<String>),
+    #[error("column {0} not found")]
+    Column(String),
+    #[error("time column not found")]
+    TimeColumn,
+    #[error("row group #{index} out of {n_row_group}")]
+    RowGroup { index: usize, n_row_group: usize },
 }
 
 pub struct Table {
@@ -26,20 +38,45 @@ pub struct Table {
 impl Table {
     /// Loads a table from a [parquet](https://docs.rs/parquet/latest/parquet/index.html) file
     pub fn from_parquet<P>(path: P) -> Result<Self, TableError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_parquet_with(path, &Default::default())
+    }
+    /// Loads a table from a [parquet](https://docs.rs/parquet/latest/parquet/index.html) file with the given [TableOptions]
+    pub fn from_parquet_with<P>(path: P, options: &TableOptions) -> Result<Self, TableError>
     where
         P: AsRef<Path>,
     {
         let file = File::open(&path)
             .map_err(|e| TableError::ParquetFile(e, path.as_ref().to_path_buf()))?;
-        let parquet_reader = ParquetRecordBatchReaderBuilder::try_new(file)?
-            .with_batch_size(2048)
-            .build()?;
-        let schema = parquet_reader.schema();
+        let builder = options.apply(ParquetRecordBatchReaderBuilder::try_new(file)?)?;
+        let metadata = builder.schema().metadata().clone();
+        let parquet_reader = builder.with_batch_size(2048).build()?;
+        let schema = with_metadata(parquet_reader.schema(), &metadata);
         let records: std::result::Result<Vec<_>, arrow::error::ArrowError> =
             parquet_reader.collect();
         let record = concat_batches(&schema, records?.as_slice())?;
         Ok(Self { record })
     }
+    /// Returns an iterator over the record batches of a [parquet](https://docs.rs/parquet/latest/parquet/index.html) file
+    ///
+    /// Each batch has at most `batch_size` rows, the file is read one batch at a time
+    pub fn record_batches<P>(
+        path: P,
+        batch_size: usize,
+    ) -> Result<impl Iterator<Item = Result<RecordBatch, ArrowError>>, TableError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(&path)
+            .map_err(|e| TableError::ParquetFile(e, path.as_ref().to_path_buf()))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let metadata = builder.schema().metadata().clone();
+        let parquet_reader = builder.with_batch_size(batch_size).build()?;
+        let schema = with_metadata(parquet_reader.schema(), &metadata);
+        Ok(parquet_reader.map(move |record| record?.with_schema(schema.clone())))
+    }
     /// Returns a reference to the [record](https://docs.rs/arrow/latest/arrow/array/struct.RecordBatch.html)
     pub fn table(&self) -> &RecordBatch {
         &self.record
@@ -63,11 +100,23 @@ impl Table {
     }
 }
 
+/// Restores the schema metadata stripped by the parquet record batch readers
+fn with_metadata(schema: SchemaRef, metadata: &HashMap<String, String>) -> SchemaRef {
+    Arc::new(Schema::new(schema.fields().clone()).with_metadata(metadata.clone()))
+}
+
 impl From<RecordBatch> for Table {
     fn from(record: RecordBatch) -> Self {
         Self { record }
     }
 }
 
+mod discovery;
+mod metrics;
+mod options;
 #[cfg(feature = "object_store")]
 pub mod store;
+pub(crate) use discovery::{field_units, units_scales};
+pub use discovery::{RbmColumns, RbmSchema, RBM_CONVENTIONS, RBM_WIDE_LABELS, UNITS_KEY};
+pub use metrics::ToTable;
+pub use options::TableOptions;
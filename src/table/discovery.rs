@@ -0,0 +1,199 @@
+//! Discovery of the rigid body motions columns in an Arrow schema
+
+use arrow::datatypes::{DataType, Schema};
+
+use super::{Table, TableError};
+use crate::{AngleUnit, LengthUnit, RigidBodyMotions, RigidBodyMotionsError};
+
+/// Known labels of the M1 and M2 rigid body motions columns with their units
+pub const RBM_CONVENTIONS: [(&str, &str, &str); 2] = [
+    ("OSSM1Lcl", "MCM2Lcl6D", "[m,rd]"),
+    ("M1RigidBodyMotions", "M2RigidBodyMotions", "[m,rd]"),
+];
+/// Known labels of a single column with both M1 and M2 rigid body motions
+pub const RBM_WIDE_LABELS: [&str; 2] = ["RigidBodyMotions", "M12RigidBodyMotions"];
+/// Key of the units in the Arrow field metadata
+pub const UNITS_KEY: &str = "units";
+
+/// Rigid body motions columns of a table
+#[derive(Debug, Clone, PartialEq)]
+pub enum RbmColumns {
+    /// One list-like column per mirror
+    Mirrors { m1: String, m2: String },
+    /// A single list-like column with M1 followed by M2
+    Wide(String),
+    /// One scalar column per degree of freedom labeled as in [RigidBodyMotions::labels]
+    Scalar,
+}
+
+/// Rigid body motions columns and units discovered in a table schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct RbmSchema {
+    pub columns: RbmColumns,
+    /// Units of the translations and rotations
+    pub units: String,
+}
+/// Returns the units in the [UNITS_KEY] metadata of the field `label`
+pub(crate) fn field_units(schema: &Schema, label: &str) -> Option<String> {
+    schema
+        .field_with_name(label)
+        .ok()
+        .and_then(|field| field.metadata().get(UNITS_KEY).cloned())
+}
+/// Returns the scales in `[m]` and `[rd]` of the translations and rotations `units`
+///
+/// The units are given as `[length,angle]` with the [LengthUnit] and [AngleUnit] symbols, e.g. `[mm,mrd]`
+pub(crate) fn units_scales(units: &str) -> Result<(f64, f64), RigidBodyMotionsError> {
+    let error = || RigidBodyMotionsError::Units(units.to_string());
+    let (length, angle) = units
+        .trim()
+        .strip_prefix('[')
+        .and_then(|units| units.strip_suffix(']'))
+        .and_then(|units| units.split_once(','))
+        .ok_or_else(error)?;
+    Ok((
+        length.trim().parse::<LengthUnit>()?.scale(),
+        angle.trim().parse::<AngleUnit>()?.scale(),
+    ))
+}
+impl RbmSchema {
+    /// Discovers the rigid body motions columns of a schema
+    ///
+    /// The columns are searched in the following order: the [RBM_CONVENTIONS] M1 and M2 columns,
+    /// the [RBM_WIDE_LABELS] column and the per degree of freedom columns.
+    /// The units are read from the [UNITS_KEY] field metadata or set to the convention default.
+    /// If no column is found, the error lists the candidate columns.
+    pub fn discover(schema: &Schema) -> Result<Self, TableError> {
+        let units = |label: &str, default: &str| {
+            field_units(schema, label).unwrap_or_else(|| default.to_string())
+        };
+        let has = |label: &str| schema.field_with_name(label).is_ok();
+        if let Some((m1, m2, u)) = RBM_CONVENTIONS
+            .iter()
+            .find(|(m1, m2, _)| has(m1) && has(m2))
+        {
+            return Ok(Self {
+                columns: RbmColumns::Mirrors {
+                    m1: m1.to_string(),
+                    m2: m2.to_string(),
+                },
+                units: units(m1, u),
+            });
+        }
+        if let Some(label) = RBM_WIDE_LABELS.iter().find(|label| has(label)) {
+            return Ok(Self {
+                columns: RbmColumns::Wide(label.to_string()),
+                units: units(label, "[m,rd]"),
+            });
+        }
+        let labels = RigidBodyMotions::labels();
+        if labels.iter().all(|label| has(label)) {
+            return Ok(Self {
+                columns: RbmColumns::Scalar,
+                units: units(&labels[0], "[m,rd]"),
+            });
+        }
+        Err(TableError::RbmColumns(
+            schema
+                .fields()
+                .iter()
+                .filter(|field| {
+                    matches!(
+                        field.data_type(),
+                        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(..)
+                    )
+                })
+                .map(|field| field.name().to_string())
+                .collect(),
+        ))
+    }
+}
+
+impl Table {
+    /// Discovers the rigid body motions columns of the table
+    pub fn rigid_body_motions_schema(&self) -> Result<RbmSchema, TableError> {
+        RbmSchema::discover(&self.record.schema())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArrowLayout;
+
+    #[test]
+    fn discover() {
+        let rbm: RigidBodyMotions = nalgebra::DMatrix::<f64>::zeros(84, 2).into();
+        let table = rbm.to_table(None, None).unwrap();
+        assert_eq!(
+            table.rigid_body_motions_schema().unwrap().columns,
+            RbmColumns::Mirrors {
+                m1: "M1RigidBodyMotions".to_string(),
+                m2: "M2RigidBodyMotions".to_string()
+            }
+        );
+        let record = rbm
+            .to_record_with(Some("RigidBodyMotions"), None, ArrowLayout::Wide)
+            .unwrap();
+        assert_eq!(
+            RbmSchema::discover(&record.schema()).unwrap().columns,
+            RbmColumns::Wide("RigidBodyMotions".to_string())
+        );
+        let record = rbm.to_record_with(None, None, ArrowLayout::Scalar).unwrap();
+        assert_eq!(
+            RbmSchema::discover(&record.schema()).unwrap().columns,
+            RbmColumns::Scalar
+        );
+        let record = rbm.to_record(Some("A"), Some("B")).unwrap();
+        match RbmSchema::discover(&record.schema()) {
+            Err(TableError::RbmColumns(candidates)) => assert_eq!(candidates, vec!["A", "B"]),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(RigidBodyMotions::from_record(&record, None, None).is_err());
+        assert!(RigidBodyMotions::from_record(&record, Some("A"), Some("B")).is_ok());
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(units_scales("[m,rd]").unwrap(), (1., 1.));
+        assert_eq!(units_scales("[mm, mrd]").unwrap(), (1e-3, 1e-3));
+        assert!(matches!(
+            units_scales("m,rd"),
+            Err(RigidBodyMotionsError::Units(_))
+        ));
+        assert!(matches!(
+            units_scales("[furlong,rd]"),
+            Err(RigidBodyMotionsError::Units(_))
+        ));
+
+        let rbm: RigidBodyMotions =
+            nalgebra::DMatrix::<f64>::from_fn(84, 2, |i, j| (i + j) as f64).into();
+        let record = rbm.to_record(None, None).unwrap();
+        let schema = record.schema();
+        let fields: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let mut field = field.as_ref().clone();
+                if field.name() != "time" {
+                    field.set_metadata([(UNITS_KEY.to_string(), "[mm,mrd]".to_string())].into());
+                }
+                field
+            })
+            .collect();
+        let record = arrow::record_batch::RecordBatch::try_new(
+            std::sync::Arc::new(Schema::new(fields)),
+            record.columns().to_vec(),
+        )
+        .unwrap();
+        let scaled = RigidBodyMotions::from_record(&record, None, None).unwrap();
+        assert_eq!(*scaled.data(), rbm.data() * 1e-3);
+        let scaled = RigidBodyMotions::from_record(
+            &record,
+            Some("M1RigidBodyMotions"),
+            Some("M2RigidBodyMotions"),
+        )
+        .unwrap();
+        assert_eq!(*scaled.data(), rbm.data() * 1e-3);
+    }
+}
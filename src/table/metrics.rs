@@ -0,0 +1,121 @@
+//! Optical metrics to Arrow tables
+
+use std::{collections::HashMap, ops::Deref, path::Path, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, Float64Array},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+
+use super::{Table, TableError, UNITS_KEY};
+use crate::{
+    DifferentialSegmentPiston, LinearOpticalModelError, OpticalMetrics, Result, SegmentPiston,
+    SegmentTipTilt, SegmentWfeRms, TipTilt, WfeRms,
+};
+
+/// Optical metrics conversion into a [Table]
+///
+/// The table has a `time` column followed by one column per [item](OpticalMetrics::item_labels),
+/// the units are written in the [UNITS_KEY] metadata of both the schema and the fields.
+/// A table can be saved to a store with [Table::to_stored_parquet] under the `object_store` feature
+pub trait ToTable: Deref<Target = Vec<f64>> + OpticalMetrics {
+    /// Returns a [Table] with the `time` `[s]` of each sample
+    fn to_table(&self, time: &[f64]) -> Result<Table> {
+        self.to_table_with_units(time, self.units())
+    }
+    /// Returns a [Table] with the `time` `[s]` of each sample and the items `units`
+    fn to_table_with_units(&self, time: &[f64], units: &str) -> Result<Table> {
+        let n_item = self.n_item();
+        let n_sample = self.len() / n_item.max(1);
+        if time.len() != n_sample {
+            return Err(LinearOpticalModelError::TimeLength {
+                expected: n_sample,
+                found: time.len(),
+            });
+        }
+        let field = |label: &str, units: &str| {
+            Field::new(label, DataType::Float64, false)
+                .with_metadata(HashMap::from([(UNITS_KEY.to_string(), units.to_string())]))
+        };
+        let fields: Vec<Field> = std::iter::once(field("time", "s"))
+            .chain(self.item_labels().iter().map(|label| field(label, units)))
+            .collect();
+        let columns: Vec<ArrayRef> =
+            std::iter::once(Arc::new(Float64Array::from(time.to_vec())) as ArrayRef)
+                .chain((0..n_item).map(|i| {
+                    Arc::new(Float64Array::from_iter_values(
+                        self.iter().skip(i).step_by(n_item).cloned(),
+                    )) as ArrayRef
+                }))
+                .collect();
+        let schema = Schema::new(fields)
+            .with_metadata(HashMap::from([(UNITS_KEY.to_string(), units.to_string())]));
+        Ok(RecordBatch::try_new(Arc::new(schema), columns)
+            .map_err(TableError::from)?
+            .into())
+    }
+    /// Saves the optical metrics to a [parquet](https://docs.rs/parquet) file with the `time` `[s]` of each sample
+    fn to_parquet<P: AsRef<Path>>(&self, path: P, time: &[f64]) -> Result<()> {
+        Ok(self.to_table(time)?.to_parquet(path)?)
+    }
+}
+
+impl ToTable for TipTilt {}
+impl ToTable for SegmentTipTilt {}
+impl ToTable for SegmentPiston {}
+impl ToTable for DifferentialSegmentPiston {}
+impl ToTable for SegmentWfeRms {}
+impl ToTable for WfeRms {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpticalSensitivities, PistonPairs, LOM};
+    use nalgebra as na;
+
+    #[test]
+    fn to_table() {
+        let mut lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        lom.rbm = na::DMatrix::<f64>::from_fn(84, 5, |i, j| 1e-6 * (i * j) as f64).into();
+        let time = lom.time();
+
+        let segment_tiptilt = lom.segment_tiptilt();
+        let table = segment_tiptilt.to_table(&time).unwrap();
+        let record = table.table();
+        assert_eq!(record.num_columns(), 15);
+        assert_eq!(record.num_rows(), 5);
+        let schema = record.schema();
+        assert_eq!(schema.field(0).name(), "time");
+        assert_eq!(schema.field(3).name(), "S3_tip");
+        assert_eq!(schema.field(14).name(), "S7_tilt");
+        assert_eq!(schema.metadata()[UNITS_KEY], "rd");
+        assert_eq!(schema.field(0).metadata()[UNITS_KEY], "s");
+        let s3_tilt = record
+            .column_by_name("S3_tilt")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(s3_tilt.value(4), segment_tiptilt[4 * 14 + 9]);
+
+        let path =
+            std::env::temp_dir().join(format!("gmt-lom_metrics_{}.parquet", std::process::id()));
+        lom.wfe_rms::<-9>()
+            .to_table_with_units(&time, "nm")
+            .unwrap()
+            .to_parquet(&path)
+            .unwrap();
+        let table = Table::from_parquet(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(table.table().schema().metadata()[UNITS_KEY], "nm");
+        assert_eq!(table.table().num_columns(), 2);
+
+        let dp = lom.differential_segment_piston(PistonPairs::CenterRelative);
+        assert_eq!(dp.item_labels()[0], "S1-S7_piston");
+        assert!(lom.tiptilt().to_table(&time[1..]).is_err());
+    }
+}
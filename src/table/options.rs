@@ -0,0 +1,260 @@
+//! Parquet table loading options
+
+use std::ops::Range;
+
+use arrow::{
+    array::{Array, BooleanArray, Float64Array},
+    compute::cast,
+    datatypes::DataType,
+};
+use parquet::{
+    arrow::{
+        arrow_reader::{ArrowPredicateFn, ArrowReaderBuilder, RowFilter},
+        ProjectionMask,
+    },
+    file::statistics::Statistics,
+    schema::types::SchemaDescriptor,
+};
+
+use super::TableError;
+use crate::TIME_LABELS;
+
+/// Options for loading a [Table](super::Table) from a parquet file
+///
+/// The options are applied in the following order:
+///  1. the row groups are selected and those entirely outside of the time window are skipped,
+///  2. the rows outside of the time window are filtered out,
+///  3. the row range is selected within the remaining rows,
+///  4. the columns are projected, always keeping the time column if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct TableOptions {
+    columns: Option<Vec<String>>,
+    rows: Option<Range<usize>>,
+    time_window: Option<Range<f64>>,
+    row_groups: Option<Vec<usize>>,
+}
+impl TableOptions {
+    /// Only loads the columns named `columns`
+    ///
+    /// The [TIME_LABELS] column, if any, is always loaded so that the time of the selected rows is preserved
+    pub fn columns<S: Into<String>>(mut self, columns: impl IntoIterator<Item = S>) -> Self {
+        self.columns = Some(columns.into_iter().map(|c| c.into()).collect());
+        self
+    }
+    /// Only loads the rows within the `rows` range
+    pub fn rows(mut self, rows: Range<usize>) -> Self {
+        self.rows = Some(rows);
+        self
+    }
+    /// Only loads the rows with a time `[s]` within `time_window`
+    ///
+    /// The time is read from one of the [TIME_LABELS] column which doesn't need to be one of the projected [columns](TableOptions::columns)
+    pub fn time_window(mut self, time_window: Range<f64>) -> Self {
+        self.time_window = Some(time_window);
+        self
+    }
+    /// Only reads the row groups with indices in `row_groups`
+    pub fn row_groups(mut self, row_groups: Vec<usize>) -> Self {
+        self.row_groups = Some(row_groups);
+        self
+    }
+    /// Applies the options to a parquet reader builder
+    pub(crate) fn apply<T>(
+        &self,
+        builder: ArrowReaderBuilder<T>,
+    ) -> Result<ArrowReaderBuilder<T>, TableError> {
+        let schema = builder.parquet_schema();
+        let metadata = builder.metadata();
+        let n_row_group = metadata.num_row_groups();
+        let mut row_groups = self
+            .row_groups
+            .clone()
+            .unwrap_or_else(|| (0..n_row_group).collect());
+        if let Some(&index) = row_groups.iter().find(|&&i| i >= n_row_group) {
+            return Err(TableError::RowGroup { index, n_row_group });
+        }
+        let mut filter = None;
+        if let Some(window) = self.time_window.clone() {
+            let (root, leaf) = time_column(schema).ok_or(TableError::TimeColumn)?;
+            row_groups.retain(|&i| {
+                metadata
+                    .row_group(i)
+                    .column(leaf)
+                    .statistics()
+                    .and_then(bounds)
+                    .is_none_or(|(lo, hi)| hi >= window.start && lo < window.end)
+            });
+            log::debug!(
+                "{} row groups out of {} in the time window",
+                row_groups.len(),
+                n_row_group
+            );
+            let predicate =
+                ArrowPredicateFn::new(ProjectionMask::roots(schema, [root]), move |batch| {
+                    let time = cast(batch.column(0), &DataType::Float64)?;
+                    Ok(time
+                        .as_any()
+                        .downcast_ref::<Float64Array>()
+                        .expect("time column cast to Float64")
+                        .iter()
+                        .map(|t| t.map(|t| window.contains(&t)))
+                        .collect::<BooleanArray>())
+                });
+            filter = Some(RowFilter::new(vec![Box::new(predicate)]));
+        }
+        let projection = match &self.columns {
+            Some(columns) => {
+                let roots = schema.root_schema().get_fields();
+                let mut indices = columns
+                    .iter()
+                    .map(|c| {
+                        roots
+                            .iter()
+                            .position(|root| root.name() == c)
+                            .ok_or_else(|| TableError::Column(c.clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some((root, _)) = time_column(schema) {
+                    indices.push(root);
+                }
+                Some(ProjectionMask::roots(schema, indices))
+            }
+            None => None,
+        };
+        let mut builder = builder.with_row_groups(row_groups);
+        if let Some(filter) = filter {
+            builder = builder.with_row_filter(filter);
+        }
+        if let Some(rows) = &self.rows {
+            builder = builder
+                .with_offset(rows.start)
+                .with_limit(rows.end.saturating_sub(rows.start));
+        }
+        if let Some(projection) = projection {
+            builder = builder.with_projection(projection);
+        }
+        Ok(builder)
+    }
+}
+
+/// Returns the root and the leaf indices of the time column
+fn time_column(schema: &SchemaDescriptor) -> Option<(usize, usize)> {
+    TIME_LABELS.iter().find_map(|label| {
+        let leaf = schema
+            .columns()
+            .iter()
+            .position(|c| c.path().parts() == [label.to_string()])?;
+        Some((schema.get_column_root_idx(leaf), leaf))
+    })
+}
+
+/// Returns the minimum and maximum values of a numeric column chunk
+fn bounds(statistics: &Statistics) -> Option<(f64, f64)> {
+    match statistics {
+        Statistics::Double(s) => Some((*s.min_opt()?, *s.max_opt()?)),
+        Statistics::Float(s) => Some((*s.min_opt()? as f64, *s.max_opt()? as f64)),
+        Statistics::Int32(s) => Some((*s.min_opt()? as f64, *s.max_opt()? as f64)),
+        Statistics::Int64(s) => Some((*s.min_opt()? as f64, *s.max_opt()? as f64)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Table;
+    use arrow::{
+        array::{Float64Array, Int32Array},
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+    use std::{fs::File, sync::Arc};
+
+    #[test]
+    fn table_options() {
+        let n = 1000;
+        let schema = Schema::new(vec![
+            Field::new("time", DataType::Float64, false),
+            Field::new("a", DataType::Float64, false),
+            Field::new("b", DataType::Int32, false),
+        ]);
+        let record = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Float64Array::from_iter_values(
+                    (0..n).map(|k| k as f64 / 100.),
+                )),
+                Arc::new(Float64Array::from_iter_values((0..n).map(|k| k as f64))),
+                Arc::new(Int32Array::from_iter_values(0..n)),
+            ],
+        )
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("gmt-lom_options_{}.parquet", std::process::id()));
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(100)
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            File::create(&path).unwrap(),
+            record.schema(),
+            Some(properties),
+        )
+        .unwrap();
+        writer.write(&record).unwrap();
+        writer.close().unwrap();
+
+        let load = |options: TableOptions| Table::from_parquet_with(&path, &options);
+        let column = |table: &Table, label: &str| -> Vec<f64> {
+            let column = cast(
+                table.table().column_by_name(label).unwrap(),
+                &DataType::Float64,
+            );
+            column
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .unwrap()
+                .values()
+                .to_vec()
+        };
+
+        let table = load(TableOptions::default().columns(["a"])).unwrap();
+        assert_eq!(table.table().num_columns(), 2);
+        assert_eq!(table.table().num_rows(), 1000);
+        assert!(matches!(
+            load(TableOptions::default().columns(["c"])),
+            Err(TableError::Column(_))
+        ));
+
+        let table = load(TableOptions::default().rows(10..20)).unwrap();
+        assert_eq!(
+            column(&table, "a"),
+            (10..20).map(|k| k as f64).collect::<Vec<_>>()
+        );
+
+        let table = load(TableOptions::default().time_window(2.0..3.5)).unwrap();
+        assert_eq!(table.table().num_rows(), 150);
+        assert_eq!(column(&table, "time")[0], 2.);
+
+        let table = load(TableOptions::default().row_groups(vec![1, 3])).unwrap();
+        assert_eq!(table.table().num_rows(), 200);
+        assert_eq!(column(&table, "b")[100], 300.);
+        assert!(matches!(
+            load(TableOptions::default().row_groups(vec![10])),
+            Err(TableError::RowGroup { index: 10, .. })
+        ));
+
+        let table = load(
+            TableOptions::default()
+                .columns(["b"])
+                .time_window(5.5..9.)
+                .rows(100..105),
+        )
+        .unwrap();
+        assert_eq!(table.table().num_columns(), 2);
+        assert_eq!(column(&table, "b"), vec![650., 651., 652., 653., 654.]);
+        assert_eq!(column(&table, "time"), vec![6.5, 6.51, 6.52, 6.53, 6.54]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
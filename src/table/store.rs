@@ -9,7 +9,7 @@ use parquet::{
     errors::ParquetError,
 };
 
-use crate::{LinearOpticalModelError, Table};
+use crate::{LinearOpticalModelError, Table, TableOptions};
 
 #[derive(Debug, thiserror::Error)]
 pub enum StoredTableError {
@@ -36,25 +36,35 @@ impl Table {
     pub async fn from_stored_parquet(
         store: impl ObjectStore,
         object_path: impl Into<object_store::path::Path>,
+    ) -> Result<Self, LinearOpticalModelError> {
+        Self::from_stored_parquet_with(store, object_path, &Default::default()).await
+    }
+    /// Loads a table from a parquet stored remotely in [store](https://docs.rs/object_store/latest/object_store/trait.ObjectStore.html)
+    /// with the given [TableOptions]
+    pub async fn from_stored_parquet_with(
+        store: impl ObjectStore,
+        object_path: impl Into<object_store::path::Path>,
+        options: &TableOptions,
     ) -> Result<Self, LinearOpticalModelError> {
         let object_path = object_path.into();
         let reader = ParquetObjectReader::new(Arc::new(store), object_path.clone());
-        let stream = ParquetRecordBatchStreamBuilder::new(reader)
+        let builder = ParquetRecordBatchStreamBuilder::new(reader)
             .await
-            .map_err(|e| StoredTableError::ReadParquet(e, object_path.to_string()))?
-            .build()
-            .map_err(|e| StoredTableError::from(e))?;
+            .map_err(|e| StoredTableError::ReadParquet(e, object_path.to_string()))?;
+        let builder = options.apply(builder)?;
+        let metadata = builder.schema().metadata().clone();
+        let stream = builder.build().map_err(StoredTableError::from)?;
         let results = stream
             .try_collect::<Vec<_>>()
             .await
-            .map_err(|e| StoredTableError::from(e))?;
+            .map_err(StoredTableError::from)?;
 
         if results.is_empty() {
             return Err(StoredTableError::Empty.into());
         }
 
-        let record = concat_batches(results.get(0).unwrap().schema_ref(), results.as_slice())
-            .map_err(|e| StoredTableError::from(e))?;
+        let schema = super::with_metadata(results[0].schema(), &metadata);
+        let record = concat_batches(&schema, results.as_slice()).map_err(StoredTableError::from)?;
         Ok(Self { record })
     }
     /// Saves a table to a parquet stored remotely in [store](https://docs.rs/object_store/latest/object_store/trait.ObjectStore.html)
@@ -73,16 +83,14 @@ impl Table {
                 .map_err(|e| StoredTableError::StorePut(e, object_path.to_string()))?;
 
             let mut parts = Vec::new();
-            let mut part_number = 0;
 
-            for chunk in bytes.chunks(CHUNK_SIZE) {
+            for (part_number, chunk) in bytes.chunks(CHUNK_SIZE).enumerate() {
                 let chunk_bytes = Bytes::copy_from_slice(chunk);
                 let part = store
                     .put_part(&object_path, &upload, part_number, chunk_bytes.into())
                     .await
                     .map_err(|e| StoredTableError::StorePut(e, object_path.to_string()))?;
                 parts.push(part);
-                part_number += 1;
             }
 
             store
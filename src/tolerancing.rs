@@ -0,0 +1,543 @@
+//! # Monte Carlo tolerance analysis
+//!
+//! Random draws of M1 and M2 rigid body motions according to per degree of freedom
+//! [Tolerance] distributions and statistics of the resulting optical metrics
+//!
+//! The realisations are drawn and processed by chunks so the memory footprint does not depend
+//! on the number of realisations beyond the optical metrics themselves
+
+use std::ops::{Deref, DerefMut};
+
+use nalgebra as na;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::StandardNormal;
+
+use crate::{
+    DifferentialSegmentPiston, OpticalMetrics, OpticalSensitivity, PistonPairs, Result,
+    RigidBodyMotions, SegmentPiston, SegmentTipTilt, SegmentWfeRms, TipTilt, WavefrontResidual,
+    WfeRms, LOM,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ToleranceError {
+    #[error("unknown rigid body motion {0}, expected a label like M1_S1_Tx")]
+    UnknownDof(String),
+    #[error("invalid tolerance: {0:?}")]
+    Invalid(Tolerance),
+}
+
+/// Tolerance distribution of a rigid body motion
+///
+/// All distributions are centered on zero
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Tolerance {
+    /// No perturbation
+    #[default]
+    None,
+    /// Uniform distribution in `[-half_width,half_width]`
+    Uniform { half_width: f64 },
+    /// Gaussian distribution with standard deviation `sigma`
+    Gaussian { sigma: f64 },
+    /// Gaussian distribution with standard deviation `sigma` truncated to `[-bound,bound]`
+    ///
+    /// The samples are drawn by inversion of the Gaussian cumulative distribution function within the bounds
+    TruncatedGaussian { sigma: f64, bound: f64 },
+}
+impl Tolerance {
+    fn check(self) -> std::result::Result<Self, ToleranceError> {
+        let valid = |x: f64| x.is_finite() && x >= 0f64;
+        match self {
+            Tolerance::None => Ok(self),
+            Tolerance::Uniform { half_width: x } | Tolerance::Gaussian { sigma: x } if valid(x) => {
+                Ok(self)
+            }
+            Tolerance::TruncatedGaussian { sigma, bound }
+                if valid(sigma) && valid(bound) && bound > 0f64 =>
+            {
+                Ok(self)
+            }
+            _ => Err(ToleranceError::Invalid(self)),
+        }
+    }
+    fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match *self {
+            Tolerance::None => 0f64,
+            Tolerance::Uniform { half_width } => half_width * (2f64 * rng.gen::<f64>() - 1f64),
+            Tolerance::Gaussian { sigma } => sigma * rng.sample::<f64, _>(StandardNormal),
+            Tolerance::TruncatedGaussian { sigma, bound } => {
+                if sigma == 0f64 {
+                    return 0f64;
+                }
+                let p = normal_cdf(-bound / sigma);
+                let u = p + (1f64 - 2f64 * p) * rng.gen::<f64>();
+                (sigma * normal_quantile(u)).clamp(-bound, bound)
+            }
+        }
+    }
+}
+
+/// Standard normal cumulative distribution function
+///
+/// The complementary error function is approximated with a relative error less than `1.2e-7`
+/// (W. H. Press et al., Numerical Recipes, 6.2)
+fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() * std::f64::consts::FRAC_1_SQRT_2;
+    let t = 1f64 / (1f64 + 0.5 * z);
+    let poly = [
+        -1.26551223,
+        1.00002368,
+        0.37409196,
+        0.09678418,
+        -0.18628806,
+        0.27886807,
+        -1.13520398,
+        1.48851587,
+        -0.82215223,
+        0.17087277,
+    ]
+    .iter()
+    .rev()
+    .fold(0f64, |p, c| p * t + c);
+    let erfc = t * (-z * z + poly).exp();
+    if x < 0f64 {
+        0.5 * erfc
+    } else {
+        1f64 - 0.5 * erfc
+    }
+}
+
+/// Inverse of the standard normal cumulative distribution function
+///
+/// Rational approximation with a relative error less than `1.15e-9` (P. J. Acklam)
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.38357751867269e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    let horner = |c: &[f64], x: f64| c.iter().fold(0f64, |p, c| p * x + c);
+    let tail = |p: f64| {
+        let q = (-2f64 * p.ln()).sqrt();
+        horner(&C, q) / (horner(&D, q) * q + 1f64)
+    };
+    const P_LOW: f64 = 0.02425;
+    if p <= 0f64 {
+        f64::NEG_INFINITY
+    } else if p >= 1f64 {
+        f64::INFINITY
+    } else if p < P_LOW {
+        tail(p)
+    } else if p > 1f64 - P_LOW {
+        -tail(1f64 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        horner(&A, r) * q / (horner(&B, r) * r + 1f64)
+    }
+}
+
+/// Tolerances of the 84 rigid body motions
+///
+/// The tolerances are given in the same order than [RigidBodyMotions] rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tolerances(Vec<Tolerance>);
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self(vec![Tolerance::None; 84])
+    }
+}
+impl Tolerances {
+    /// Sets the tolerance of the `i`th rigid body motion
+    pub fn dof(
+        mut self,
+        i: usize,
+        tolerance: Tolerance,
+    ) -> std::result::Result<Self, ToleranceError> {
+        let dof = self
+            .0
+            .get_mut(i)
+            .ok_or_else(|| ToleranceError::UnknownDof(i.to_string()))?;
+        *dof = tolerance.check()?;
+        Ok(self)
+    }
+    /// Sets the tolerance of the rigid body motion `label` given as `Mi_Sj_[TR][xyz]`
+    pub fn label(
+        self,
+        label: &str,
+        tolerance: Tolerance,
+    ) -> std::result::Result<Self, ToleranceError> {
+        let i = RigidBodyMotions::labels()
+            .iter()
+            .position(|l| l == label)
+            .ok_or_else(|| ToleranceError::UnknownDof(label.to_string()))?;
+        self.dof(i, tolerance)
+    }
+    /// Sets the tolerances of the translations and rotations of all the segments of mirror `M1` or `M2`
+    pub fn mirror(
+        mut self,
+        mirror: usize,
+        translation: Tolerance,
+        rotation: Tolerance,
+    ) -> std::result::Result<Self, ToleranceError> {
+        if !(1..=2).contains(&mirror) {
+            return Err(ToleranceError::UnknownDof(format!("M{mirror}")));
+        }
+        let (translation, rotation) = (translation.check()?, rotation.check()?);
+        self.0[(mirror - 1) * 42..mirror * 42]
+            .chunks_mut(6)
+            .for_each(|segment| {
+                segment[..3].fill(translation);
+                segment[3..].fill(rotation);
+            });
+        Ok(self)
+    }
+    /// Returns the tolerances
+    pub fn tolerances(&self) -> &[Tolerance] {
+        &self.0
+    }
+    /// Draws `n` realisations of the rigid body motions
+    pub fn sample<R: Rng>(&self, rng: &mut R, n: usize) -> RigidBodyMotions {
+        na::DMatrix::from_iterator(
+            84,
+            n,
+            (0..n).flat_map(|_| self.0.iter().map(|t| t.sample(rng)).collect::<Vec<_>>()),
+        )
+        .into()
+    }
+}
+
+/// Monte Carlo tolerance analysis
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    tolerances: Tolerances,
+    n_realisation: usize,
+    seed: u64,
+    chunk_size: usize,
+    residual: WavefrontResidual,
+    pairs: PistonPairs,
+}
+impl MonteCarlo {
+    /// Creates a Monte Carlo analysis with `n_realisation` draws of the rigid body motions
+    pub fn new(tolerances: Tolerances, n_realisation: usize) -> Self {
+        Self {
+            tolerances,
+            n_realisation,
+            seed: 0,
+            chunk_size: 1000,
+            residual: WavefrontResidual::default(),
+            pairs: PistonPairs::default(),
+        }
+    }
+    /// Sets the random number generator seed (default: 0)
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+    /// Sets the number of realisations processed at once (default: 1000)
+    pub fn chunk_size(self, chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            ..self
+        }
+    }
+    /// Sets the wavefront residual used for the WFE RMS and the segment WFE RMS (default: [WavefrontResidual::Full])
+    pub fn wavefront_residual(self, residual: WavefrontResidual) -> Self {
+        Self { residual, ..self }
+    }
+    /// Sets the segment pairs of the differential segment piston (default: [PistonPairs::All])
+    pub fn piston_pairs(self, pairs: PistonPairs) -> Self {
+        Self { pairs, ..self }
+    }
+    /// Returns an iterator over chunks of rigid body motions realisations
+    ///
+    /// The realisations do not depend on the chunk size
+    pub fn realisations(&self) -> impl Iterator<Item = RigidBodyMotions> + '_ {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut n_left = self.n_realisation;
+        std::iter::from_fn(move || {
+            (n_left > 0).then(|| {
+                let n = n_left.min(self.chunk_size);
+                n_left -= n;
+                self.tolerances.sample(&mut rng, n)
+            })
+        })
+    }
+}
+
+/// Distributions of the optical metrics of a [MonteCarlo] analysis
+///
+/// Each metric is given with the same layout than the [LOM] outputs, one realisation per time index;
+/// a metric is `None` if the corresponding sensitivity is missing
+#[derive(Debug, Clone, Default)]
+pub struct MonteCarloResults {
+    pub tiptilt: Option<TipTilt>,
+    pub segment_tiptilt: Option<SegmentTipTilt>,
+    pub segment_piston: Option<SegmentPiston>,
+    /// Differential segment piston of the [MonteCarlo] segment pairs
+    pub differential_segment_piston: Option<DifferentialSegmentPiston>,
+    /// Segment WFE RMS in `[m]`
+    pub segment_wfe_rms: Option<SegmentWfeRms>,
+    /// WFE RMS in `[m]`
+    pub wfe_rms: Option<WfeRms>,
+}
+
+fn extend<T: DerefMut<Target = Vec<f64>>>(metric: &mut Option<T>, values: Result<T>) -> Result<()> {
+    if let Some(metric) = metric.as_mut() {
+        metric.extend_from_slice(&values?);
+    }
+    Ok(())
+}
+
+impl LOM {
+    /// Runs the Monte Carlo tolerance analysis
+    ///
+    /// The optical metrics are evaluated on the drawn rigid body motions, the rigid body motions of the linear optical model are not used
+    ///
+    /// # Example
+    /// ```no_run
+    /// use gmt_lom::{tolerancing::{Distribution, MonteCarlo, Tolerance, Tolerances}, LOM};
+    /// use skyangle::Conversion;
+    ///
+    /// let tolerances = Tolerances::default().mirror(
+    ///     1,
+    ///     Tolerance::Gaussian { sigma: 10e-6 },
+    ///     Tolerance::Gaussian { sigma: 1f64.from_arcsec() },
+    /// )?;
+    /// let lom = LOM::builder().build()?;
+    /// let results = lom.monte_carlo(&MonteCarlo::new(tolerances, 10_000).seed(7))?;
+    /// let tiptilt = results.tiptilt.unwrap();
+    /// println!("95% tip-tilt: {:?}", tiptilt.percentile(95.));
+    /// println!("yield: {}", tiptilt.yield_within(1f64.from_arcsec()));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn monte_carlo(&self, monte_carlo: &MonteCarlo) -> Result<MonteCarloResults> {
+        let has = |index: OpticalSensitivity| self.sensitivities().try_get(index).is_ok();
+        let mut results = MonteCarloResults {
+            tiptilt: has(OpticalSensitivity::TipTilt(vec![])).then(|| TipTilt(vec![])),
+            segment_tiptilt: has(OpticalSensitivity::SegmentTipTilt(vec![]))
+                .then(|| SegmentTipTilt(vec![])),
+            segment_piston: has(OpticalSensitivity::SegmentPiston(vec![]))
+                .then(|| SegmentPiston(vec![])),
+            differential_segment_piston: has(OpticalSensitivity::SegmentPiston(vec![])).then(
+                || DifferentialSegmentPiston {
+                    data: vec![],
+                    pairs: monte_carlo.pairs.pairs(),
+                },
+            ),
+            segment_wfe_rms: (has(OpticalSensitivity::Wavefront(vec![]))
+                && has(OpticalSensitivity::SegmentMask(vec![])))
+            .then(|| SegmentWfeRms(vec![])),
+            wfe_rms: has(OpticalSensitivity::Wavefront(vec![])).then(|| WfeRms(vec![])),
+        };
+        for rbm in monte_carlo.realisations() {
+            let optics = |index: OpticalSensitivity| self.optics_of(index, &rbm);
+            extend(
+                &mut results.tiptilt,
+                optics(OpticalSensitivity::TipTilt(vec![])).map(TipTilt),
+            )?;
+            extend(
+                &mut results.segment_tiptilt,
+                optics(OpticalSensitivity::SegmentTipTilt(vec![])).map(SegmentTipTilt),
+            )?;
+            extend(
+                &mut results.segment_piston,
+                optics(OpticalSensitivity::SegmentPiston(vec![])).map(SegmentPiston),
+            )?;
+            if results.differential_segment_piston.is_some() {
+                extend(
+                    &mut results.differential_segment_piston,
+                    self.differential_segment_piston_of(monte_carlo.pairs, &rbm),
+                )?;
+            }
+            if results.segment_wfe_rms.is_some() {
+                extend(
+                    &mut results.segment_wfe_rms,
+                    self.segment_residual_wfe_rms_of::<0>(monte_carlo.residual, &rbm),
+                )?;
+            }
+            if results.wfe_rms.is_some() {
+                extend(
+                    &mut results.wfe_rms,
+                    self.residual_wfe_rms_of::<0>(monte_carlo.residual, &rbm),
+                )?;
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Distribution statistics on [OpticalMetrics]
+pub trait Distribution: Deref<Target = Vec<f64>> + OpticalMetrics {
+    /// Returns the `p`th percentile (`p` in `[0,100]`) of each item, linearly interpolated between samples
+    fn percentile(&self, p: f64) -> Vec<f64> {
+        let n_item = self.n_item();
+        (0..n_item)
+            .map(|i| {
+                let mut x: Vec<f64> = self.iter().skip(i).step_by(n_item).cloned().collect();
+                if x.is_empty() {
+                    return f64::NAN;
+                }
+                x.sort_by(f64::total_cmp);
+                let r = (p.clamp(0., 100.) / 100.) * (x.len() - 1) as f64;
+                let (k, f) = (r.floor() as usize, r.fract());
+                x[k] + f * (x[(k + 1).min(x.len() - 1)] - x[k])
+            })
+            .collect()
+    }
+    /// Returns the fraction of samples with all items within `[-threshold,threshold]`
+    fn yield_within(&self, threshold: f64) -> f64 {
+        let n_item = self.n_item();
+        let n = self.len() / n_item;
+        if n == 0 {
+            return f64::NAN;
+        }
+        self.chunks(n_item)
+            .filter(|x| x.iter().all(|x| x.abs() <= threshold))
+            .count() as f64
+            / n as f64
+    }
+}
+impl<T: Deref<Target = Vec<f64>> + OpticalMetrics> Distribution for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpticalSensitivities, Stats};
+
+    #[test]
+    fn monte_carlo() {
+        let tolerances = Tolerances::default()
+            .mirror(
+                1,
+                Tolerance::Gaussian { sigma: 1e-6 },
+                Tolerance::Uniform { half_width: 1e-6 },
+            )
+            .unwrap()
+            .label(
+                "M2_S7_Rx",
+                Tolerance::TruncatedGaussian {
+                    sigma: 1e-6,
+                    bound: 1e-6,
+                },
+            )
+            .unwrap();
+        assert!(tolerances
+            .clone()
+            .label("M3_S1_Tx", Tolerance::None)
+            .is_err());
+        assert!(tolerances
+            .clone()
+            .dof(0, Tolerance::Gaussian { sigma: -1. })
+            .is_err());
+
+        let monte_carlo = MonteCarlo::new(tolerances, 2000).seed(1);
+        let rbm: Vec<_> = monte_carlo.clone().chunk_size(300).realisations().collect();
+        assert_eq!(rbm.len(), 7);
+        assert!(rbm[6]
+            .data()
+            .iter()
+            .skip(42 + 39)
+            .step_by(84)
+            .all(|x| x.abs() <= 1e-6));
+        assert!(rbm[0].data().row(42).iter().all(|&x| x == 0f64));
+
+        let lom = LOM::builder()
+            .optical_sensitivities(OpticalSensitivities::synthetic())
+            .build()
+            .unwrap();
+        let a = lom.monte_carlo(&monte_carlo).unwrap();
+        let b = lom.monte_carlo(&monte_carlo.clone().chunk_size(7)).unwrap();
+        assert_eq!(lom.len(), 1);
+        let (a_sp, b_sp) = (a.segment_piston.unwrap(), b.segment_piston.unwrap());
+        assert_eq!(a_sp.len(), 7 * 2000);
+        assert_eq!(*a_sp, *b_sp);
+        // synthetic segment piston is 2Tz for M1
+        a_sp.std(None)
+            .into_iter()
+            .for_each(|std| assert!((std - 2e-6).abs() < 0.2e-6));
+        let median = a_sp.percentile(50.);
+        assert!(median.iter().all(|m| m.abs() < 0.2e-6));
+        assert!(a_sp.percentile(0.) <= median && median <= a_sp.percentile(100.));
+        assert_eq!(a_sp.yield_within(1.), 1.);
+        assert_eq!(a_sp.yield_within(0.), 0.);
+        assert_eq!(a.wfe_rms.unwrap().len(), 2000);
+        assert_eq!(a.segment_wfe_rms.unwrap().len(), 7 * 2000);
+        let dp = a.differential_segment_piston.unwrap();
+        assert_eq!(dp.len(), 21 * 2000);
+        assert_eq!(dp.pairs().len(), 21);
+
+        let results = lom
+            .monte_carlo(
+                &MonteCarlo::new(
+                    Tolerances::default()
+                        .dof(
+                            2,
+                            Tolerance::TruncatedGaussian {
+                                sigma: 1.,
+                                bound: 1e-9,
+                            },
+                        )
+                        .unwrap(),
+                    100,
+                )
+                .piston_pairs(PistonPairs::CenterRelative),
+            )
+            .unwrap();
+        // M1 S1 Tz within the truncation bound
+        assert!(results
+            .segment_piston
+            .unwrap()
+            .iter()
+            .all(|x| x.abs() <= 2e-9));
+        assert_eq!(results.differential_segment_piston.unwrap().len(), 6 * 100);
+    }
+
+    #[test]
+    fn truncated_gaussian() {
+        for x in [-3., -1., 0., 0.5, 2.] {
+            assert!((normal_quantile(normal_cdf(x)) - x).abs() < 1e-6);
+        }
+        assert!((normal_cdf(1.) - 0.841344746).abs() < 1e-7);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let tolerance = Tolerance::TruncatedGaussian {
+            sigma: 1.,
+            bound: 1.,
+        };
+        let n = 20_000;
+        let x: Vec<f64> = (0..n).map(|_| tolerance.sample(&mut rng)).collect();
+        assert!(x.iter().all(|x| x.abs() <= 1.));
+        // variance of the standard normal truncated to [-1,1]
+        let var = x.iter().map(|x| x * x).sum::<f64>() / n as f64;
+        assert!((var - 0.291125).abs() < 0.01, "{var}");
+        let tolerance = Tolerance::TruncatedGaussian {
+            sigma: 0.,
+            bound: 1.,
+        };
+        assert_eq!(tolerance.sample(&mut rng), 0.);
+    }
+}
@@ -0,0 +1,534 @@
+//! # Zernike decomposition of the wavefront
+//!
+//! The wavefront within the exit pupil is projected onto a [ZernikeBasis] with a least-squares fit,
+//! the basis is either defined over the whole pupil or over each of the 7 segments given by the
+//! [SegmentMask](OpticalSensitivity::SegmentMask).
+//! The Zernike polynomials are ordered and normalized according to Noll's convention:
+//! the RMS of each mode over the unit disk is 1.
+//!
+//! # Example
+//! ```no_run
+//! use gmt_lom::{zernike::ZernikeBasis, LOM};
+//!
+//! let lom = LOM::builder()
+//!     .parquet_rigid_body_motions("rbm.parquet", &Default::default(), None, None)?
+//!     .build()?;
+//! let basis = ZernikeBasis::segments(lom.sensitivities(), 11)?;
+//! // the Zernike coefficients of the 7 segments at each time step
+//! let coefficients = lom.zernike(&basis);
+//! # Ok::<(), gmt_lom::LinearOpticalModelError>(())
+//! ```
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::{OpticalSensitivities, OpticalSensitivity, Result, SensitivitiesMetadata, LOM};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ZernikeError {
+    #[error("{n_pixel} pixels in {support} are not enough to fit {n_mode} Zernike modes")]
+    Pixels {
+        support: String,
+        n_pixel: usize,
+        n_mode: usize,
+    },
+    #[error("expected a wavefront of {expected} pixels, found {found}")]
+    Wavefront { expected: usize, found: usize },
+    #[error("expected {expected} Zernike coefficients, found {found}")]
+    Coefficients { expected: usize, found: usize },
+    #[error("expected a segment mask of {expected} pixels, found {found}")]
+    SegmentMask { expected: usize, found: usize },
+    #[error("the {n_mode} Zernike modes over {support} are only of rank {rank}")]
+    Rank {
+        support: String,
+        rank: usize,
+        n_mode: usize,
+    },
+}
+
+/// Returns the radial order `n` and the azimuthal frequency `m` of the Zernike mode `j` (`j>=1`)
+///
+/// The azimuthal frequency is positive for the `cos` modes and negative for the `sin` modes
+pub fn noll(j: usize) -> (usize, i32) {
+    assert!(j > 0, "Noll indices start at 1");
+    let n = ((((8 * j - 7) as f64).sqrt() - 1.) / 2.).floor() as usize;
+    let k = j - n * (n + 1) / 2;
+    let m = if n.is_multiple_of(2) {
+        2 * (k / 2)
+    } else {
+        2 * ((k - 1) / 2) + 1
+    } as i32;
+    if m == 0 || j.is_multiple_of(2) {
+        (n, m)
+    } else {
+        (n, -m)
+    }
+}
+
+/// Returns the value of the Zernike mode `j` at the polar coordinates `(rho,theta)` of the unit disk
+pub fn zernike(j: usize, rho: f64, theta: f64) -> f64 {
+    let (n, m) = noll(j);
+    let m_abs = m.unsigned_abs() as usize;
+    let factorial = |k: usize| (1..=k).map(|i| i as f64).product::<f64>();
+    let radial: f64 = (0..=(n - m_abs) / 2)
+        .map(|s| {
+            let sign = if s.is_multiple_of(2) { 1f64 } else { -1f64 };
+            sign * factorial(n - s)
+                / (factorial(s) * factorial((n + m_abs) / 2 - s) * factorial((n - m_abs) / 2 - s))
+                * rho.powi((n - 2 * s) as i32)
+        })
+        .sum();
+    match m {
+        0 => ((n + 1) as f64).sqrt() * radial,
+        m if m > 0 => (2. * (n + 1) as f64).sqrt() * radial * (m as f64 * theta).cos(),
+        m => (2. * (n + 1) as f64).sqrt() * radial * (-m as f64 * theta).sin(),
+    }
+}
+
+/// Support of a [ZernikeBasis]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZernikeSupport {
+    /// The modes are defined over the whole exit pupil
+    Pupil,
+    /// The modes are defined over each of the 7 segments
+    Segments,
+}
+
+/// Zernike modes over a set of pixels
+#[derive(Debug, Clone)]
+struct Block {
+    /// Indices of the pixels in the wavefront within the exit pupil
+    pixels: Vec<usize>,
+    /// Zernike modes `[n_pixel,n_mode]`
+    modes: na::DMatrix<f64>,
+    /// Least-squares projection `[n_mode,n_pixel]`
+    projector: na::DMatrix<f64>,
+}
+
+/// Zernike basis tied to the exit pupil geometry of the optical sensitivities
+///
+/// The modes are sampled at the [coordinates](crate::PupilGeometry::xy) of the pixels within the exit pupil,
+/// the unit disk is centered on the centroid of the pixels and its radius is the distance to the farthest pixel.
+/// For the [segments](ZernikeSupport::Segments) support, the coefficients are ordered segment after segment:
+/// `[S1_Z1,...,S1_Zn,...,S7_Z1,...,S7_Zn]`
+#[derive(Debug, Clone)]
+pub struct ZernikeBasis {
+    support: ZernikeSupport,
+    n_mode: usize,
+    n_pixel: usize,
+    blocks: Vec<Block>,
+}
+impl ZernikeBasis {
+    /// Creates a basis of the first `n_mode` Zernike modes over the `support`
+    ///
+    /// Fails if the `PupilMask`, or the `SegmentMask` for the [segments](ZernikeSupport::Segments) support, is missing,
+    /// if the `SegmentMask` doesn't match the pixels within the `PupilMask`
+    /// or if the modes are linearly dependent over the pixels of the support
+    pub fn new<const N: usize>(
+        sensitivities: &OpticalSensitivities<N>,
+        support: ZernikeSupport,
+        n_mode: usize,
+    ) -> Result<Self> {
+        let groups: Vec<(String, Vec<usize>)> = match support {
+            ZernikeSupport::Pupil => {
+                let n_pixel = sensitivities.pupil_mask()?.iter().filter(|&&m| m).count();
+                vec![("the pupil".to_string(), (0..n_pixel).collect())]
+            }
+            ZernikeSupport::Segments => {
+                let segment_mask = sensitivities.segment_mask()?;
+                let n_pixel = sensitivities.pupil_mask()?.iter().filter(|&&m| m).count();
+                if segment_mask.len() != n_pixel {
+                    return Err(ZernikeError::SegmentMask {
+                        expected: n_pixel,
+                        found: segment_mask.len(),
+                    }
+                    .into());
+                }
+                (1..=7)
+                    .map(|sid| {
+                        (
+                            format!("segment #{sid}"),
+                            segment_mask
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, &s)| s == sid)
+                                .map(|(k, _)| k)
+                                .collect(),
+                        )
+                    })
+                    .collect()
+            }
+        };
+        let xy = sensitivities.masked_pixel_coordinates()?;
+        let blocks = groups
+            .into_iter()
+            .map(|(name, pixels)| {
+                if pixels.len() < n_mode {
+                    return Err(ZernikeError::Pixels {
+                        support: name,
+                        n_pixel: pixels.len(),
+                        n_mode,
+                    }
+                    .into());
+                }
+                let n = pixels.len() as f64;
+                let (cx, cy) = pixels
+                    .iter()
+                    .fold((0f64, 0f64), |(cx, cy), &k| (cx + xy[k].0, cy + xy[k].1));
+                let (cx, cy) = (cx / n, cy / n);
+                let polar: Vec<(f64, f64)> = pixels
+                    .iter()
+                    .map(|&k| {
+                        let (x, y) = (xy[k].0 - cx, xy[k].1 - cy);
+                        (x.hypot(y), y.atan2(x))
+                    })
+                    .collect();
+                let radius = polar.iter().map(|(r, _)| *r).fold(0f64, f64::max);
+                let modes = na::DMatrix::from_fn(pixels.len(), n_mode, |i, j| {
+                    let (r, o) = polar[i];
+                    zernike(j + 1, if radius > 0. { r / radius } else { 0. }, o)
+                });
+                let svd = modes.clone().svd(true, true);
+                let tolerance = f64::EPSILON * pixels.len() as f64 * svd.singular_values.max();
+                let rank = svd.rank(tolerance);
+                if rank < n_mode {
+                    return Err(ZernikeError::Rank {
+                        support: name,
+                        rank,
+                        n_mode,
+                    }
+                    .into());
+                }
+                let projector = svd
+                    .pseudo_inverse(tolerance)
+                    .expect("positive pseudo-inverse tolerance");
+                Ok(Block {
+                    pixels,
+                    modes,
+                    projector,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            support,
+            n_mode,
+            n_pixel: xy.len(),
+            blocks,
+        })
+    }
+    /// Creates a basis of the first `n_mode` Zernike modes over the whole exit pupil
+    pub fn pupil<const N: usize>(
+        sensitivities: &OpticalSensitivities<N>,
+        n_mode: usize,
+    ) -> Result<Self> {
+        Self::new(sensitivities, ZernikeSupport::Pupil, n_mode)
+    }
+    /// Creates a basis of the first `n_mode` Zernike modes over each segment
+    pub fn segments<const N: usize>(
+        sensitivities: &OpticalSensitivities<N>,
+        n_mode: usize,
+    ) -> Result<Self> {
+        Self::new(sensitivities, ZernikeSupport::Segments, n_mode)
+    }
+    /// Returns the support of the basis
+    pub fn support(&self) -> ZernikeSupport {
+        self.support
+    }
+    /// Returns the number of Zernike modes
+    pub fn n_mode(&self) -> usize {
+        self.n_mode
+    }
+    /// Returns the number of Zernike coefficients: `n_mode` or `7 x n_mode` for the segments support
+    pub fn n_coefficient(&self) -> usize {
+        self.blocks.len() * self.n_mode
+    }
+    /// Returns the Zernike coefficients of a wavefront within the exit pupil
+    pub fn project(&self, wavefront: &[f64]) -> Vec<f64> {
+        self.try_project(wavefront)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the Zernike coefficients of a wavefront within the exit pupil
+    ///
+    /// Fails if the wavefront size does not match the number of pixels within the exit pupil
+    pub fn try_project(&self, wavefront: &[f64]) -> Result<Vec<f64>> {
+        if wavefront.len() != self.n_pixel {
+            return Err(ZernikeError::Wavefront {
+                expected: self.n_pixel,
+                found: wavefront.len(),
+            }
+            .into());
+        }
+        Ok(self
+            .blocks
+            .iter()
+            .flat_map(|block| {
+                let w = na::DVector::from_iterator(
+                    block.pixels.len(),
+                    block.pixels.iter().map(|&k| wavefront[k]),
+                );
+                (&block.projector * w).as_slice().to_vec()
+            })
+            .collect())
+    }
+    /// Returns the wavefront within the exit pupil from the Zernike coefficients
+    pub fn reconstruct(&self, coefficients: &[f64]) -> Vec<f64> {
+        self.try_reconstruct(coefficients)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the wavefront within the exit pupil from the Zernike coefficients
+    ///
+    /// Fails if the number of coefficients does not match the [number of coefficients](ZernikeBasis::n_coefficient) of the basis
+    pub fn try_reconstruct(&self, coefficients: &[f64]) -> Result<Vec<f64>> {
+        if coefficients.len() != self.n_coefficient() {
+            return Err(ZernikeError::Coefficients {
+                expected: self.n_coefficient(),
+                found: coefficients.len(),
+            }
+            .into());
+        }
+        let mut wavefront = vec![0f64; self.n_pixel];
+        for (block, c) in self.blocks.iter().zip(coefficients.chunks(self.n_mode)) {
+            let w = &block.modes * na::DVector::from_column_slice(c);
+            for (&k, w) in block.pixels.iter().zip(w.iter()) {
+                wavefront[k] = *w;
+            }
+        }
+        Ok(wavefront)
+    }
+    /// Returns the [Zernike](OpticalSensitivity::Zernike) sensitivity `[n_coefficient,N]`,
+    /// the projection of the [Wavefront](OpticalSensitivity::Wavefront) sensitivity onto the basis
+    ///
+    /// Fails if the `Wavefront` sensitivity is missing or if it is not sampled on the pixels of the basis
+    pub fn sensitivity<const N: usize>(
+        &self,
+        sensitivities: &OpticalSensitivities<N>,
+    ) -> Result<OpticalSensitivity<N>> {
+        let wavefront: &[f64] = sensitivities
+            .try_get(OpticalSensitivity::Wavefront(vec![]))?
+            .into();
+        if wavefront.len() != self.n_pixel * N {
+            return Err(ZernikeError::Wavefront {
+                expected: self.n_pixel,
+                found: wavefront.len() / N,
+            }
+            .into());
+        }
+        let coefficients = wavefront
+            .chunks(self.n_pixel.max(1))
+            .map(|w| self.try_project(w))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(OpticalSensitivity::Zernike(coefficients.concat()))
+    }
+}
+
+impl<const N: usize> OpticalSensitivities<N> {
+    /// Adds the [Zernike](OpticalSensitivity::Zernike) sensitivity of the `basis`,
+    /// replacing any existing one, and records the basis support and number of modes in the metadata
+    pub fn with_zernike(self, basis: &ZernikeBasis) -> Result<Self> {
+        let zernike = basis.sensitivity(&self)?;
+        let metadata = self.metadata().cloned().unwrap_or_default();
+        let mut sens: Vec<_> = self
+            .iter()
+            .filter(|&s| *s != OpticalSensitivity::Zernike(vec![]))
+            .cloned()
+            .collect();
+        sens.push(zernike);
+        Ok(Self::from(sens).with_metadata(SensitivitiesMetadata {
+            zernike: Some((basis.support, basis.n_mode)),
+            ..metadata
+        }))
+    }
+}
+
+impl LOM {
+    /// Returns the Zernike coefficients of the wavefront in the `basis`, one vector per time step
+    pub fn zernike(&self, basis: &ZernikeBasis) -> Vec<Vec<f64>> {
+        self.try_zernike(basis).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the Zernike coefficients of the wavefront in the `basis`, one vector per time step
+    ///
+    /// Fails if the `Wavefront` sensitivity is missing
+    pub fn try_zernike(&self, basis: &ZernikeBasis) -> Result<Vec<Vec<f64>>> {
+        let wavefront = self.try_masked_wavefront()?;
+        wavefront
+            .chunks(basis.n_pixel.max(1))
+            .map(|w| basis.try_project(w))
+            .collect()
+    }
+    /// Returns the Zernike coefficients of the wavefront, one vector per time step,
+    /// from the precomputed [Zernike](OpticalSensitivity::Zernike) sensitivity
+    pub fn modal_zernike(&self) -> Vec<Vec<f64>> {
+        self.try_modal_zernike().unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Returns the Zernike coefficients of the wavefront, one vector per time step,
+    /// from the precomputed [Zernike](OpticalSensitivity::Zernike) sensitivity
+    ///
+    /// Fails if the `Zernike` sensitivity is missing
+    pub fn try_modal_zernike(&self) -> Result<Vec<Vec<f64>>> {
+        let sens = self
+            .sensitivities()
+            .try_get(OpticalSensitivity::Zernike(vec![]))?;
+        let coefficients = sens.into_optics(self.rbm.data());
+        Ok(coefficients
+            .chunks((coefficients.len() / self.len().max(1)).max(1))
+            .map(|c| c.to_vec())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LinearOpticalModelError, PupilGeometry};
+
+    #[test]
+    fn noll_indices() {
+        let nm: Vec<_> = (1..=11).map(noll).collect();
+        assert_eq!(
+            nm,
+            vec![
+                (0, 0),
+                (1, 1),
+                (1, -1),
+                (2, 0),
+                (2, -2),
+                (2, 2),
+                (3, -1),
+                (3, 1),
+                (3, -3),
+                (3, 3),
+                (4, 0)
+            ]
+        );
+        assert!((zernike(4, 1., 0.) - 3f64.sqrt()).abs() < 1e-12);
+        assert!((zernike(3, 0.5, std::f64::consts::FRAC_PI_2) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zernike_decomposition() {
+        let sens =
+            OpticalSensitivities::synthetic().with_pupil_geometry(PupilGeometry::new(8, 0.5));
+
+        let basis = ZernikeBasis::pupil(&sens, 6).unwrap();
+        assert_eq!(basis.n_coefficient(), 6);
+        let c = vec![1., -0.5, 0.25, 2., 0., -1.];
+        let c_fit = basis.project(&basis.reconstruct(&c));
+        c.iter()
+            .zip(&c_fit)
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-12));
+        assert!(matches!(
+            basis.try_project(&[0.; 59]),
+            Err(LinearOpticalModelError::Zernike(ZernikeError::Wavefront {
+                expected: 60,
+                found: 59
+            }))
+        ));
+        assert!(basis.try_reconstruct(&c[1..]).is_err());
+        let no_wavefront: OpticalSensitivities = sens
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::Wavefront(_) => OpticalSensitivity::Wavefront(vec![]),
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        assert!(matches!(
+            basis.sensitivity(&no_wavefront),
+            Err(LinearOpticalModelError::Zernike(ZernikeError::Wavefront {
+                found: 0,
+                ..
+            }))
+        ));
+
+        let basis = ZernikeBasis::segments(&sens, 3).unwrap();
+        assert_eq!(basis.n_coefficient(), 21);
+        let sens = sens.with_zernike(&basis).unwrap();
+        assert_eq!(
+            sens.metadata().unwrap().zernike,
+            Some((ZernikeSupport::Segments, 3))
+        );
+        let sens: OpticalSensitivities =
+            OpticalSensitivities::from_bytes(&sens.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            sens.metadata().unwrap().zernike,
+            Some((ZernikeSupport::Segments, 3))
+        );
+        let mut lom = LOM::builder().optical_sensitivities(sens).build().unwrap();
+        let mut rbm = na::DMatrix::<f64>::zeros(84, 2);
+        // M1 S3 Tz
+        rbm[(2 * 6 + 2, 0)] = 1e-6;
+        // M1 S5 Rx
+        rbm[(4 * 6 + 3, 1)] = 1e-6;
+        lom.rbm = rbm.into();
+        let coefficients = lom.zernike(&basis);
+        assert_eq!(coefficients.len(), 2);
+        for (k, c) in coefficients[0].iter().enumerate() {
+            let expected = if k == 2 * 3 { 2e-6 } else { 0. };
+            assert!((c - expected).abs() < 1e-15, "{k}: {c}");
+        }
+        let s5 = &coefficients[1][4 * 3..5 * 3];
+        assert!(s5[1].abs() < 1e-15 && s5[2] > 0.);
+        for (a, b) in lom
+            .modal_zernike()
+            .concat()
+            .iter()
+            .zip(coefficients.concat())
+        {
+            assert!((a - b).abs() < 1e-15);
+        }
+        assert!(matches!(
+            ZernikeBasis::segments(lom.sensitivities(), 20),
+            Err(LinearOpticalModelError::Zernike(
+                ZernikeError::Pixels { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn zernike_basis_errors() {
+        let sens =
+            OpticalSensitivities::synthetic().with_pupil_geometry(PupilGeometry::new(8, 0.5));
+        let segment_mask: OpticalSensitivities = sens
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::SegmentMask(mask) => {
+                    OpticalSensitivity::SegmentMask(mask[1..].to_vec())
+                }
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        assert!(matches!(
+            ZernikeBasis::segments(&segment_mask, 3),
+            Err(LinearOpticalModelError::Zernike(
+                ZernikeError::SegmentMask {
+                    expected: 60,
+                    found: 59
+                }
+            ))
+        ));
+        // a single row of pixels can't separate the tip from the tilt
+        let row: OpticalSensitivities = sens
+            .iter()
+            .map(|s| match s {
+                OpticalSensitivity::PupilMask(mask) => {
+                    OpticalSensitivity::PupilMask((0..mask.len()).map(|k| k < 8).collect())
+                }
+                OpticalSensitivity::Wavefront(_) => OpticalSensitivity::Wavefront(vec![0.; 8 * 84]),
+                OpticalSensitivity::SegmentMask(_) => OpticalSensitivity::SegmentMask(vec![1; 8]),
+                s => s.clone(),
+            })
+            .collect::<Vec<_>>()
+            .into();
+        let row = row.with_pupil_geometry(PupilGeometry::new(8, 0.5));
+        assert!(matches!(
+            ZernikeBasis::pupil(&row, 3),
+            Err(LinearOpticalModelError::Zernike(ZernikeError::Rank {
+                rank: 2,
+                n_mode: 3,
+                ..
+            }))
+        ));
+        assert!(ZernikeBasis::pupil(&row, 2).is_ok());
+    }
+}